@@ -0,0 +1,193 @@
+use globset::GlobSet;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// Number of leading bytes inspected when sniffing a file's content.
+pub const SNIFF_LEN: usize = 8192;
+
+/// Business Logic Constraint: A sample with more than this share of bytes belonging to invalid
+/// UTF-8 sequences is considered binary (e.g. Latin-1 text passes, compressed data doesn't).
+const MAX_INVALID_UTF8_RATIO: f64 = 0.1;
+
+/// Signatures of common binary formats, matched against the start of the file.
+///
+/// Business Logic Constraint: Signatures made only of printable ASCII (e.g. `MZ`, `ID3`) can start
+/// ordinary text files too, so they only count if the sample also has a NUL byte or invalid UTF-8
+/// (see `is_text_like`).
+const MAGIC_NUMBERS: &[&[u8]] = &[
+    b"\x89PNG\r\n\x1a\n",     // PNG
+    b"\xff\xd8\xff",          // JPEG
+    b"GIF87a",                // GIF
+    b"GIF89a",                // GIF
+    b"%PDF-",                 // PDF
+    b"PK\x03\x04",            // ZIP (also DOCX, XLSX, PPTX, ODT, JAR, APK)
+    b"PK\x05\x06",            // Empty ZIP
+    b"\xd0\xcf\x11\xe0\xa1\xb1\x1a\xe1", // OLE2 (DOC, XLS, PPT, MSI)
+    b"\x1f\x8b",              // gzip
+    b"BZh",                   // bzip2
+    b"\xfd7zXZ\x00",          // xz
+    b"\x28\xb5\x2f\xfd",      // zstd
+    b"7z\xbc\xaf\x27\x1c",    // 7z
+    b"Rar!\x1a\x07",          // RAR
+    b"\x7fELF",               // ELF
+    b"\xfe\xed\xfa\xce",      // Mach-O 32-bit
+    b"\xfe\xed\xfa\xcf",      // Mach-O 64-bit
+    b"\xce\xfa\xed\xfe",      // Mach-O 32-bit (reverse byte order)
+    b"\xcf\xfa\xed\xfe",      // Mach-O 64-bit (reverse byte order)
+    b"\xca\xfe\xba\xbe",      // Mach-O universal binary / Java class
+    b"MZ",                    // PE (EXE, DLL)
+    b"\x00asm",               // WebAssembly
+    b"!<arch>\n",             // ar archive (static libraries, .deb)
+    b"SQLite format 3\x00",   // SQLite
+    b"II*\x00",               // TIFF (little endian)
+    b"MM\x00*",               // TIFF (big endian)
+    b"wOFF",                  // WOFF
+    b"wOF2",                  // WOFF2
+    b"OggS",                  // Ogg
+    b"fLaC",                  // FLAC
+    b"ID3",                   // MP3 with ID3 tag
+];
+
+/// What to do with a file whose content looks binary.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BinaryAction {
+    /// Leave the file out of the output and report it on stderr.
+    #[default]
+    Skip,
+    /// Include the file's path (and size) in the output, but not its content.
+    List,
+    /// Include the file's content as-is.
+    Include,
+}
+
+/// Per-glob binary handling, compiled from the `[binary]` table.
+#[derive(Debug, Clone)]
+pub struct BinaryRules {
+    pub default: BinaryAction,
+    pub skip: GlobSet,
+    pub list: GlobSet,
+    pub include: GlobSet,
+}
+
+impl BinaryRules {
+    /// Returns the action for a binary file at `relative_path`.
+    ///
+    /// Business Logic Constraint: Glob lists are checked in the order 'include', 'list', 'skip';
+    /// files matching none of them use the default action.
+    pub fn action_for(&self, relative_path: &Path) -> BinaryAction {
+        if self.include.is_match(relative_path) {
+            BinaryAction::Include
+        } else if self.list.is_match(relative_path) {
+            BinaryAction::List
+        } else if self.skip.is_match(relative_path) {
+            BinaryAction::Skip
+        } else {
+            self.default
+        }
+    }
+}
+
+/// Reads up to `SNIFF_LEN` bytes from the start of the file.
+pub fn read_sample(path: &Path) -> io::Result<Vec<u8>> {
+    let mut sample = Vec::with_capacity(SNIFF_LEN);
+    File::open(path)?
+        .take(SNIFF_LEN as u64)
+        .read_to_end(&mut sample)?;
+    Ok(sample)
+}
+
+/// Returns whether `content` (or its first `SNIFF_LEN` bytes) looks like binary data.
+///
+/// Business Logic Constraint: A file is binary if it starts with a known magic number, contains a
+/// NUL byte, or has too many bytes that are not part of valid UTF-8 sequences. Empty files are text.
+pub fn looks_binary(content: &[u8]) -> bool {
+    let sample = &content[..content.len().min(SNIFF_LEN)];
+    if sample.is_empty() {
+        return false;
+    }
+    let has_nul = sample.contains(&0);
+    let invalid = invalid_utf8_bytes(sample, sample.len() < content.len());
+    let magic = MAGIC_NUMBERS
+        .iter()
+        .any(|magic| sample.starts_with(magic) && (!is_text_like(magic) || has_nul || invalid > 0));
+    magic || has_nul || invalid as f64 / sample.len() as f64 > MAX_INVALID_UTF8_RATIO
+}
+
+/// Returns whether `magic` consists of printable ASCII only, so text can start with it.
+fn is_text_like(magic: &[u8]) -> bool {
+    magic.iter().all(|&b| b.is_ascii_graphic() || b == b' ')
+}
+
+/// Counts bytes belonging to invalid UTF-8 sequences. If `truncated` is set, an incomplete
+/// sequence at the very end of the sample is not counted, since the sample may have cut it.
+fn invalid_utf8_bytes(mut sample: &[u8], truncated: bool) -> usize {
+    let mut invalid = 0;
+    loop {
+        match std::str::from_utf8(sample) {
+            Ok(_) => return invalid,
+            Err(e) => match e.error_len() {
+                Some(len) => {
+                    invalid += len;
+                    sample = &sample[e.valid_up_to() + len..];
+                }
+                None => {
+                    if !truncated {
+                        invalid += sample.len() - e.valid_up_to();
+                    }
+                    return invalid;
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_starting_with_short_magic_is_text() {
+        assert!(!looks_binary(b"MZ: notes on the build\n"));
+        assert!(!looks_binary(b"BZh tables\n"));
+        assert!(!looks_binary(b"ID3 tag parser design\n"));
+        assert!(!looks_binary(b"GIF89a is the animated variant.\n"));
+    }
+
+    #[test]
+    fn short_magic_with_binary_content_is_binary() {
+        assert!(looks_binary(b"MZ\x90\x00\x03\x00\x00\x00"));
+        assert!(looks_binary(b"ID3\x04\x00\x00\x00\x00\x1fv"));
+        assert!(looks_binary(b"BZh91AY&SY\xb3\x8e\xa9"));
+    }
+
+    #[test]
+    fn non_text_magic_is_binary_alone() {
+        assert!(looks_binary(b"\x89PNG\r\n\x1a\n"));
+        assert!(looks_binary(b"\x7fELF"));
+        assert!(looks_binary(b"!<arch>\n"));
+    }
+
+    #[test]
+    fn utf8_and_latin1_text_is_text() {
+        assert!(!looks_binary("Grüße, 世界\n".as_bytes()));
+        assert!(!looks_binary(b"caf\xe9 au lait, a long enough line of otherwise ASCII text\n"));
+        assert!(!looks_binary(b""));
+    }
+
+    #[test]
+    fn nul_bytes_and_invalid_utf8_are_binary() {
+        assert!(looks_binary(b"text\x00more"));
+        assert!(looks_binary(b"\xff\xfe\xfd\xfc\x80\x81"));
+    }
+
+    #[test]
+    fn truncated_sample_ignores_cut_sequence() {
+        let mut content = vec![b'a'; SNIFF_LEN - 1];
+        content.extend_from_slice("é".as_bytes());
+        assert_eq!(invalid_utf8_bytes(&content[..SNIFF_LEN], true), 0);
+        assert_eq!(invalid_utf8_bytes(&content[..SNIFF_LEN], false), 1);
+    }
+}
@@ -0,0 +1,315 @@
+use crate::config::AppConfig;
+use crate::diagnostic::Diagnostic;
+use crate::file_processor::{CategoryData, ContentMode, FileData};
+use crate::format::OutputFormat;
+use crate::output::OutputSections;
+use crate::tokenizer::Tokenizer;
+
+use std::cmp::Reverse;
+
+/// Renders the metadata surrounding a file's content in the given format.
+pub fn render_file_framing(format: OutputFormat, category_data: &CategoryData, file_data: &FileData) -> Vec<u8> {
+    let mut formatter = format.formatter();
+    let mut rendered = Vec::new();
+    let _ = formatter.begin_file(&mut rendered, category_data, file_data);
+    if file_data.content == ContentMode::ListOnly {
+        let _ = formatter.list_only(&mut rendered, file_data);
+    } else {
+        let _ = formatter.begin_content(&mut rendered, file_data);
+        if let ContentMode::Truncated { head, tail } = file_data.content {
+            let _ = formatter.truncated(&mut rendered, file_data, file_data.size - head - tail);
+        }
+        let _ = formatter.end_content(&mut rendered, file_data);
+    }
+    let _ = formatter.end_file(&mut rendered, file_data);
+    rendered
+}
+
+/// Renders the metadata surrounding a category's files in the given format.
+pub fn render_category_framing(format: OutputFormat, category_data: &CategoryData) -> Vec<u8> {
+    let mut formatter = format.formatter();
+    let mut rendered = Vec::new();
+    let _ = formatter.begin_category(&mut rendered, category_data);
+    let _ = formatter.end_category(&mut rendered, category_data);
+    rendered
+}
+
+/// Tokens spent on the metadata surrounding a file's content in the given format.
+///
+/// Business Logic Constraint: Escaping overhead of formats like JSON is not accounted for.
+fn file_framing_tokens(
+    tokenizer: Tokenizer,
+    format: OutputFormat,
+    category_data: &CategoryData,
+    file_data: &FileData,
+) -> usize {
+    tokenizer.count_bytes(&render_file_framing(format, category_data, file_data))
+}
+
+/// Tokens spent on the metadata surrounding a category's files in the given format.
+fn category_framing_tokens(
+    tokenizer: Tokenizer,
+    format: OutputFormat,
+    category_data: &CategoryData,
+) -> usize {
+    tokenizer.count_bytes(&render_category_framing(format, category_data))
+}
+
+/// Tokens spent on the start of the document (e.g. the JSON category list or the XML prolog), the
+/// sections (tree, diff, task prompt) and the end of the document in the given format.
+///
+/// Business Logic Constraint: The start of the document is rendered with all categories, so
+/// categories the budget empties can only make it smaller.
+pub fn section_tokens(
+    tokenizer: Tokenizer,
+    format: OutputFormat,
+    categories_data: &[CategoryData],
+    sections: &OutputSections,
+) -> usize {
+    let mut formatter = format.formatter();
+    let mut rendered = Vec::new();
+    let _ = formatter.begin_document(&mut rendered, categories_data);
+    if let Some(tree) = &sections.tree {
+        let _ = formatter.tree(&mut rendered, tree);
+    }
+    if let Some(diff) = &sections.diff {
+        let _ = formatter.diff(&mut rendered, diff);
+    }
+    if let Some(task) = &sections.task {
+        let _ = formatter.task(&mut rendered, task);
+    }
+    let _ = formatter.end_document(&mut rendered);
+    tokenizer.count_bytes(&rendered)
+}
+
+/// Drops files until the estimated size of the output fits into `config.max_tokens`.
+/// `reserved_tokens` are subtracted from the budget up front (e.g. for the task prompt).
+///
+/// Business Logic Constraint: Files are kept in descending priority score; among equal scores,
+/// files closer to the task prompt (later in the output) win. A file that doesn't fit into the
+/// remaining budget is dropped, but smaller lower-priority files may still be kept.
+/// Business Logic Constraint: Every dropped file is reported in `diagnostics`, followed by a summary.
+/// Categories left without files are removed. The order of the kept files is unchanged.
+/// Business Logic Constraint: Does nothing when no budget is configured or tokens weren't counted.
+pub fn apply_token_budget(
+    categories_data: &mut Vec<CategoryData>,
+    config: &AppConfig,
+    reserved_tokens: usize,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let Some(max_tokens) = config.max_tokens else {
+        return;
+    };
+    let tokenizer = config.tokenizer;
+    let format = config.format;
+
+    // (category index, file index) in output order, then sorted by descending keep preference.
+    let mut candidates: Vec<(usize, usize)> = categories_data
+        .iter()
+        .enumerate()
+        .flat_map(|(category_index, category_data)| {
+            (0..category_data.files.len()).map(move |file_index| (category_index, file_index))
+        })
+        .collect();
+    candidates.reverse();
+    candidates.sort_by_key(|&(category_index, file_index)| {
+        Reverse(categories_data[category_index].files[file_index].priority)
+    });
+
+    let mut remaining = max_tokens.saturating_sub(reserved_tokens);
+    let mut kept_tokens = reserved_tokens;
+    let mut dropped_files = 0usize;
+    let mut dropped_tokens = 0usize;
+    let mut category_charged = vec![false; categories_data.len()];
+    let mut dropped: Vec<Vec<bool>> = categories_data
+        .iter()
+        .map(|category_data| vec![false; category_data.files.len()])
+        .collect();
+
+    for (category_index, file_index) in candidates {
+        let category_data = &categories_data[category_index];
+        let file_data = &category_data.files[file_index];
+        let Some(content_tokens) = file_data.tokens else {
+            continue;
+        };
+        let mut cost = content_tokens + file_framing_tokens(tokenizer, format, category_data, file_data);
+        if !category_charged[category_index] {
+            cost += category_framing_tokens(tokenizer, format, category_data);
+        }
+
+        if cost <= remaining {
+            remaining -= cost;
+            kept_tokens += cost;
+            category_charged[category_index] = true;
+        } else {
+            diagnostics.push(Diagnostic::BudgetDropped(file_data.relative_path.clone(), content_tokens));
+            dropped[category_index][file_index] = true;
+            dropped_files += 1;
+            dropped_tokens += content_tokens;
+        }
+    }
+
+    for (category_data, dropped) in categories_data.iter_mut().zip(dropped) {
+        let mut dropped = dropped.into_iter();
+        category_data.files.retain(|_| !dropped.next().unwrap_or(false));
+        category_data.total_size = category_data.files.iter().map(|f| f.size).sum();
+    }
+    categories_data.retain(|category_data| !category_data.files.is_empty());
+
+    if dropped_files > 0 {
+        diagnostics.push(Diagnostic::BudgetSummary {
+            kept_tokens,
+            max_tokens,
+            dropped_files,
+            dropped_tokens,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn file(path: &str, tokens: usize, priority: i64) -> FileData {
+        FileData {
+            relative_path: PathBuf::from(path),
+            absolute_path: PathBuf::from("/nonexistent").join(path),
+            size: tokens as u64 * 4,
+            tokens: Some(tokens),
+            content: ContentMode::Full,
+            boundary: None,
+            priority,
+        }
+    }
+
+    fn category(name: &str, files: Vec<FileData>) -> CategoryData {
+        CategoryData {
+            name: name.to_string(),
+            description_text: String::new(),
+            total_size: files.iter().map(|f| f.size).sum(),
+            files,
+            position: 0,
+        }
+    }
+
+    fn config(max_tokens: usize) -> AppConfig {
+        AppConfig {
+            max_tokens: Some(max_tokens),
+            ..AppConfig::default()
+        }
+    }
+
+    /// Tokens a file costs when its category is already charged.
+    fn file_cost(config: &AppConfig, category_data: &CategoryData, file_data: &FileData) -> usize {
+        file_data.tokens.unwrap() + file_framing_tokens(config.tokenizer, config.format, category_data, file_data)
+    }
+
+    fn kept_paths(categories_data: &[CategoryData]) -> Vec<&str> {
+        categories_data
+            .iter()
+            .flat_map(|c| &c.files)
+            .map(|f| f.relative_path.to_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn drops_lowest_priority_and_earliest_files_first() {
+        let src = category("src", vec![file("a.rs", 100, 0), file("b.rs", 100, 10), file("c.rs", 100, 0)]);
+        let defaults = AppConfig::default();
+        let budget = category_framing_tokens(defaults.tokenizer, defaults.format, &src)
+            + file_cost(&defaults, &src, &src.files[1])
+            + file_cost(&defaults, &src, &src.files[2])
+            + 50;
+        let mut categories_data = vec![src];
+        let mut diagnostics = Vec::new();
+        apply_token_budget(&mut categories_data, &config(budget), 0, &mut diagnostics);
+
+        assert_eq!(kept_paths(&categories_data), ["b.rs", "c.rs"]);
+        assert_eq!(categories_data[0].total_size, 800);
+        assert_eq!(diagnostics[0], Diagnostic::BudgetDropped(PathBuf::from("a.rs"), 100));
+        assert!(matches!(
+            diagnostics[1],
+            Diagnostic::BudgetSummary {
+                dropped_files: 1,
+                dropped_tokens: 100,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn smaller_files_still_fit_after_a_drop() {
+        let src = category("src", vec![file("small.rs", 10, 0), file("big.rs", 1000, 5)]);
+        let mut categories_data = vec![src];
+        let mut diagnostics = Vec::new();
+        apply_token_budget(&mut categories_data, &config(200), 0, &mut diagnostics);
+
+        assert_eq!(kept_paths(&categories_data), ["small.rs"]);
+        assert_eq!(diagnostics[0], Diagnostic::BudgetDropped(PathBuf::from("big.rs"), 1000));
+    }
+
+    #[test]
+    fn framing_and_reserved_tokens_count_against_the_budget() {
+        let defaults = AppConfig::default();
+        let src = category("src", vec![file("a.rs", 100, 0)]);
+        let exact = 7
+            + category_framing_tokens(defaults.tokenizer, defaults.format, &src)
+            + file_cost(&defaults, &src, &src.files[0]);
+
+        let mut categories_data = vec![src.clone()];
+        apply_token_budget(&mut categories_data, &config(exact), 7, &mut Vec::new());
+        assert_eq!(kept_paths(&categories_data), ["a.rs"]);
+
+        let mut categories_data = vec![src];
+        apply_token_budget(&mut categories_data, &config(exact - 1), 7, &mut Vec::new());
+        assert!(categories_data.is_empty(), "the emptied category is removed");
+    }
+
+    #[test]
+    fn category_framing_is_charged_once() {
+        let defaults = AppConfig::default();
+        let src = category("src", vec![file("a.rs", 50, 0), file("b.rs", 50, 0)]);
+        let docs = category("docs", vec![file("README.md", 50, 0)]);
+        let src_only = category_framing_tokens(defaults.tokenizer, defaults.format, &src)
+            + file_cost(&defaults, &src, &src.files[0])
+            + file_cost(&defaults, &src, &src.files[1]);
+
+        // Enough for both src files, but not for the docs file with its category framing.
+        let mut categories_data = vec![docs, src];
+        let mut diagnostics = Vec::new();
+        apply_token_budget(&mut categories_data, &config(src_only + 30), 0, &mut diagnostics);
+        assert_eq!(kept_paths(&categories_data), ["a.rs", "b.rs"]);
+        assert_eq!(diagnostics[0], Diagnostic::BudgetDropped(PathBuf::from("README.md"), 50));
+    }
+
+    #[test]
+    fn without_budget_or_token_counts_nothing_is_dropped() {
+        let mut categories_data = vec![category("src", vec![file("a.rs", 100, 0)])];
+        let mut diagnostics = Vec::new();
+        apply_token_budget(&mut categories_data, &AppConfig::default(), 0, &mut diagnostics);
+        assert_eq!(kept_paths(&categories_data), ["a.rs"]);
+
+        categories_data[0].files[0].tokens = None;
+        apply_token_budget(&mut categories_data, &config(1), 0, &mut diagnostics);
+        assert_eq!(kept_paths(&categories_data), ["a.rs"]);
+        assert!(diagnostics.is_empty());
+    }
+
+    #[test]
+    fn section_tokens_include_the_document_header() {
+        let categories_data = vec![category("src", vec![file("a.rs", 1, 0)])];
+        let sections = OutputSections::default();
+        let without_categories = section_tokens(Tokenizer::Cl100k, OutputFormat::Json, &[], &sections);
+        let with_categories = section_tokens(Tokenizer::Cl100k, OutputFormat::Json, &categories_data, &sections);
+        assert!(with_categories > without_categories);
+
+        let with_task = OutputSections {
+            task: Some("Fix the login bug.".to_string()),
+            ..OutputSections::default()
+        };
+        assert!(
+            section_tokens(Tokenizer::Cl100k, OutputFormat::Json, &categories_data, &with_task) > with_categories
+        );
+    }
+}
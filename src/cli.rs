@@ -0,0 +1,545 @@
+use crate::clipboard::ClipboardBackend;
+use kek::config::AppConfig;
+use kek::format::OutputFormat;
+use kek::git::{GitHistorySettings, GitSelection};
+use kek::priority::SortKey;
+use kek::split::SplitLimit;
+use kek::tokenizer::Tokenizer;
+
+use clap::{Args, CommandFactory, Parser, Subcommand};
+use std::env;
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+// Command-line interface definition.
+//
+// Business Logic Constraint: Running `kek` without a subcommand behaves like `kek serialize`,
+// so `kek "Optimize code."` keeps working. Any bare words are joined into the task prompt, even if
+// the first one is a subcommand name (see `Cli::parse_args`).
+#[derive(Parser, Debug)]
+#[command(
+    name = "kek",
+    version,
+    about = "A repository serializer.",
+    args_conflicts_with_subcommands = true
+)]
+pub struct Cli {
+    #[command(flatten)]
+    pub global: GlobalArgs,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Arguments used when no subcommand is given (implicit `serialize`).
+    #[command(flatten)]
+    pub serialize: SerializeArgs,
+}
+
+impl Cli {
+    /// Parses the command line, exiting with clap's message if it is invalid.
+    pub fn parse_command_line() -> Cli {
+        Cli::parse_args(env::args_os().collect()).unwrap_or_else(|error| error.exit())
+    }
+
+    /// Parses `args`, including the program name.
+    ///
+    /// Business Logic Constraint: A task whose first word is a subcommand name (`kek list all
+    /// bugs`) stays a task: if the arguments don't parse and the subcommand name is followed by
+    /// another word, they are parsed again as `kek serialize <words>`. The values of options before
+    /// the task (`kek -c kek.toml list all bugs`) aren't taken for its first word.
+    /// Business Logic Constraint: Global options can precede a subcommand (`kek -c kek.toml list`),
+    /// even though other options make bare words a task.
+    fn parse_args(args: Vec<OsString>) -> Result<Cli, clap::Error> {
+        let command = Cli::command();
+        let position = first_positional(&command, &args);
+        let is_subcommand = position.is_some_and(|position| {
+            command
+                .get_subcommands()
+                .any(|subcommand| args[position] == subcommand.get_name())
+        });
+        if let Some(position) = position
+            && is_subcommand
+            && only_global_options(&command, &args[1..position])
+        {
+            let mut subcommand_args = args.clone();
+            let name = subcommand_args.remove(position);
+            subcommand_args.insert(1, name);
+            if let Ok(cli) = Cli::try_parse_from(subcommand_args) {
+                return Ok(cli);
+            }
+        }
+
+        let error = match Cli::try_parse_from(&args) {
+            Ok(cli) => return Ok(cli),
+            Err(error) => error,
+        };
+        let Some(position) = position else {
+            return Err(error);
+        };
+        let followed_by_word = args
+            .get(position + 1)
+            .is_some_and(|arg| !arg.to_string_lossy().starts_with('-'));
+        if is_subcommand && followed_by_word {
+            let mut task_args = args.clone();
+            task_args.insert(position, OsString::from("serialize"));
+            if let Ok(cli) = Cli::try_parse_from(task_args) {
+                return Ok(cli);
+            }
+        }
+        Err(error)
+    }
+}
+
+/// Returns the index of the first argument after the program name that is neither an option nor
+/// the value of one.
+fn first_positional(command: &clap::Command, args: &[OsString]) -> Option<usize> {
+    let mut position = 1;
+    while let Some(arg) = args.get(position) {
+        let arg = arg.to_string_lossy();
+        if arg == "--" {
+            return args.get(position + 1).map(|_| position + 1);
+        }
+        if !arg.starts_with('-') || arg == "-" {
+            return Some(position);
+        }
+        position += if takes_separate_value(command, &arg) { 2 } else { 1 };
+    }
+    None
+}
+
+/// Whether `args` only consists of global options and their values.
+fn only_global_options(command: &clap::Command, args: &[OsString]) -> bool {
+    let mut args = args.iter().map(|arg| arg.to_string_lossy());
+    while let Some(arg) = args.next() {
+        if !find_option(command, &arg).is_some_and(clap::Arg::is_global_set) {
+            return false;
+        }
+        if takes_separate_value(command, &arg) && args.next().is_none() {
+            return false;
+        }
+    }
+    true
+}
+
+/// Returns the option `arg` names (`--config`, `--config=kek.toml`, `-c`, or the first of `-vc`).
+fn find_option<'a>(command: &'a clap::Command, arg: &str) -> Option<&'a clap::Arg> {
+    if let Some(long) = arg.strip_prefix("--") {
+        let name = long.split('=').next()?;
+        return command.get_arguments().find(|option| option.get_long() == Some(name));
+    }
+    let short = arg.strip_prefix('-')?.chars().next()?;
+    command.get_arguments().find(|option| option.get_short() == Some(short))
+}
+
+/// Whether the option `arg` is followed by its value as a separate argument (`-c kek.toml`, as
+/// opposed to `-ckek.toml`, `--config=kek.toml` or a flag).
+fn takes_separate_value(command: &clap::Command, arg: &str) -> bool {
+    let takes_value = |option: &clap::Arg| option.get_action().takes_values() && !option.is_require_equals_set();
+    if let Some(long) = arg.strip_prefix("--") {
+        return !long.contains('=')
+            && command
+                .get_arguments()
+                .any(|option| option.get_long() == Some(long) && takes_value(option));
+    }
+    // In a cluster of short options (`-vc`), the first one taking a value takes the rest.
+    let shorts: Vec<char> = arg[1..].chars().collect();
+    shorts
+        .iter()
+        .position(|&short| {
+            command
+                .get_arguments()
+                .any(|option| option.get_short() == Some(short) && takes_value(option))
+        })
+        .is_some_and(|index| index + 1 == shorts.len())
+}
+
+/// Options shared by every subcommand.
+#[derive(Args, Debug)]
+pub struct GlobalArgs {
+    /// Path to a configuration file merged over all others (user config, project kek.toml and
+    /// `KEK_CONFIG`).
+    #[arg(long, short = 'c', value_name = "PATH", global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Apply the settings of `[profile.NAME]` from the configuration. Overrides `default_profile`.
+    #[arg(long, short = 'p', value_name = "NAME", global = true)]
+    pub profile: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Serialize the repository to stdout (default).
+    Serialize(SerializeArgs),
+    /// List the files that would be serialized, with their categories.
+    List(ListArgs),
+    /// Serialize the repository to a file and rewrite it whenever the scanned files change.
+    Watch(WatchArgs),
+    /// Serve serializations over HTTP on a local port or a Unix socket.
+    Serve(ServeArgs),
+    /// Run a Model Context Protocol server on stdin and stdout, with tools to list, read and
+    /// serialize the files kek would select.
+    Mcp,
+    /// Inspect the configuration.
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+}
+
+/// Options controlling which files are selected. Shared by `serialize` and `list`.
+#[derive(Args, Debug, Default)]
+pub struct SelectionArgs {
+    /// Maximum number of tokens in the output. Overrides `max_tokens` in kek.toml.
+    #[arg(long, value_name = "N")]
+    pub max_tokens: Option<usize>,
+
+    /// Tokenizer used for token counting: cl100k or o200k. Overrides `tokenizer` in kek.toml.
+    #[arg(long, value_name = "NAME")]
+    pub tokenizer: Option<Tokenizer>,
+
+    /// Only include files matching this gitignore-style glob. Can be repeated; adds to `include`
+    /// in kek.toml.
+    #[arg(long, value_name = "GLOB")]
+    pub include: Vec<String>,
+
+    /// Leave out files and directories matching this gitignore-style glob. Can be repeated; adds to
+    /// `exclude` in kek.toml.
+    #[arg(long, value_name = "GLOB")]
+    pub exclude: Vec<String>,
+
+    /// Order of files with equal priority: path or size. Overrides `sort` in kek.toml.
+    #[arg(long, value_name = "KEY")]
+    pub sort: Option<SortKey>,
+
+    /// Boost recently and frequently changed files using the local git history.
+    /// Uses `[git_history]` from kek.toml if present, otherwise defaults.
+    #[arg(long)]
+    pub git_history: bool,
+
+    /// Only include files changed since the merge base of REF and HEAD (committed or not).
+    #[arg(long, value_name = "REF", group = "git_selection")]
+    pub changed_since: Option<String>,
+
+    /// Only include files with changes staged in the index.
+    #[arg(long, group = "git_selection")]
+    pub staged: bool,
+
+    /// Only include files with staged, unstaged or untracked changes in the working tree.
+    #[arg(long, group = "git_selection")]
+    pub uncommitted: bool,
+
+    /// Strip comments, trailing whitespace and extra blank lines from the files of the 'src'
+    /// category. Overrides `minify` in kek.toml.
+    #[arg(long)]
+    pub minify: bool,
+}
+
+impl SelectionArgs {
+    /// Applies the command-line overrides on top of the loaded configuration.
+    ///
+    /// Business Logic Constraint: Command-line options take precedence over kek.toml.
+    pub fn apply(&self, config: &mut AppConfig) {
+        if let Some(max_tokens) = self.max_tokens {
+            config.max_tokens = Some(max_tokens);
+        }
+        if let Some(tokenizer) = self.tokenizer {
+            config.tokenizer = tokenizer;
+        }
+        config.include.extend(self.include.iter().cloned());
+        config.exclude.extend(self.exclude.iter().cloned());
+        if let Some(sort) = self.sort {
+            config.sort = sort;
+        }
+        if self.git_history && config.git_history.is_none() {
+            config.git_history = Some(GitHistorySettings::default());
+        }
+        if let Some(selection) = self.git_selection() {
+            config.git_selection = Some(selection);
+        }
+        if self.minify {
+            config.minify = true;
+        }
+    }
+
+    /// Returns the git selection mode requested on the command line, if any.
+    pub fn git_selection(&self) -> Option<GitSelection> {
+        if let Some(git_ref) = &self.changed_since {
+            Some(GitSelection::ChangedSince(git_ref.clone()))
+        } else if self.staged {
+            Some(GitSelection::Staged)
+        } else if self.uncommitted {
+            Some(GitSelection::Uncommitted)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Args, Debug, Default)]
+pub struct SerializeArgs {
+    #[command(flatten)]
+    pub selection: SelectionArgs,
+
+    /// Output format: pseudo-xml, markdown, json or xml. Overrides `format` in kek.toml.
+    #[arg(long, short = 'f', value_name = "FORMAT")]
+    pub format: Option<OutputFormat>,
+
+    /// Write a directory tree of the serialized files before their contents.
+    #[arg(long)]
+    pub tree: bool,
+
+    /// Also show scanned files that are not serialized in the tree, marked as excluded.
+    /// Implies --tree.
+    #[arg(long)]
+    pub tree_excluded: bool,
+
+    /// Write the output to this file instead of stdout. The file is replaced atomically.
+    #[arg(long, short = 'o', value_name = "PATH", conflicts_with = "split")]
+    pub output: Option<PathBuf>,
+
+    /// Copy the output to the clipboard instead of writing it to stdout. BACKEND is auto (default),
+    /// wl-copy, xclip, xsel or osc52 (terminal escape sequence, works over SSH).
+    #[arg(
+        long,
+        value_name = "BACKEND",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "auto",
+        conflicts_with_all = ["output", "split"]
+    )]
+    pub clipboard: Option<ClipboardBackend>,
+
+    /// Allow writing the output to stdout when it is a terminal.
+    #[arg(long)]
+    pub force_tty: bool,
+
+    /// Write the output into numbered files (kek-001.xml, kek-002.xml, ...) of at most LIMIT bytes
+    /// (e.g. 200000 or 200k) or tokens (e.g. 50000t or 50kt) instead of stdout.
+    #[arg(long, value_name = "LIMIT")]
+    pub split: Option<SplitLimit>,
+
+    /// Directory for the files written by --split. Defaults to the current directory.
+    #[arg(long, value_name = "DIR", requires = "split", default_value = ".")]
+    pub split_dir: PathBuf,
+
+    /// Append the unified diff of the git selection as a `<diff>` section.
+    /// Requires --changed-since, --staged or --uncommitted.
+    #[arg(long, requires = "git_selection")]
+    pub diff: bool,
+
+    /// Task prompt appended at the end of the output. Conflicts with a positional task.
+    #[arg(long, short = 't', value_name = "TEXT", conflicts_with = "task_words")]
+    pub task: Option<String>,
+
+    /// Task prompt words, joined by spaces. Use `--` to pass words starting with `-`. A task starting
+    /// with a subcommand name (e.g. `list all bugs`) is only taken as the subcommand if the rest of
+    /// the arguments are valid for it; use `--task` to be explicit.
+    #[arg(value_name = "TASK", trailing_var_arg = true)]
+    pub task_words: Vec<String>,
+}
+
+impl SerializeArgs {
+    /// Applies the command-line overrides on top of the loaded configuration.
+    pub fn apply(&self, config: &mut AppConfig) {
+        self.selection.apply(config);
+        if let Some(format) = self.format {
+            config.format = format;
+        }
+        if self.tree || self.tree_excluded {
+            config.tree.enabled = true;
+        }
+        if self.tree_excluded {
+            config.tree.excluded = true;
+        }
+        if let Some(SplitLimit::Tokens(_)) = self.split {
+            config.count_tokens = true;
+        }
+    }
+
+    /// Returns the task prompt, if one was given either via `--task` or as positional words.
+    ///
+    /// Business Logic Constraint: Positional words are joined by single spaces. An explicitly
+    /// empty task (e.g. `kek ""`) still produces a `<task></task>` element.
+    pub fn task(&self) -> Option<String> {
+        if let Some(task) = &self.task {
+            return Some(task.clone());
+        }
+        if self.task_words.is_empty() {
+            None
+        } else {
+            Some(self.task_words.join(" "))
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct ListArgs {
+    #[command(flatten)]
+    pub selection: SelectionArgs,
+
+    /// Print the token count of every file.
+    #[arg(long)]
+    pub tokens: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct WatchArgs {
+    /// Serialization options. `-o/--output` is required and the file is rewritten after every
+    /// change.
+    #[command(flatten)]
+    pub serialize: SerializeArgs,
+
+    /// Milliseconds without further changes to wait for before regenerating the output.
+    #[arg(long, value_name = "MS", default_value_t = 200)]
+    pub debounce: u64,
+}
+
+#[derive(Args, Debug)]
+pub struct ServeArgs {
+    /// Listen on this Unix socket (created with mode 0600) instead of a TCP port.
+    #[arg(long, value_name = "PATH")]
+    pub socket: Option<PathBuf>,
+
+    /// TCP port to listen on, on 127.0.0.1 only.
+    #[arg(long, value_name = "PORT", default_value_t = 7878, conflicts_with = "socket")]
+    pub port: u16,
+}
+
+/// Options of a `kek serve` request to `/serialize` or `/file`, or of a `kek mcp` tool call,
+/// parsed like command-line options (see `parse_request`).
+#[derive(Parser, Debug)]
+#[command(name = "serialize", no_binary_name = true)]
+pub struct SerializeRequest {
+    /// Profile to apply. Overrides the `--profile` the server was started with.
+    #[arg(long, value_name = "NAME")]
+    pub profile: Option<String>,
+
+    #[command(flatten)]
+    pub serialize: SerializeArgs,
+}
+
+/// Options of a `kek serve` request to `/files` or of the `list_files` tool of `kek mcp`.
+#[derive(Parser, Debug)]
+#[command(name = "files", no_binary_name = true)]
+pub struct ListRequest {
+    /// Profile to apply. Overrides the `--profile` the server was started with.
+    #[arg(long, value_name = "NAME")]
+    pub profile: Option<String>,
+
+    #[command(flatten)]
+    pub list: ListArgs,
+}
+
+/// Options that only make sense on the command line; servers always answer with the output.
+const SERVER_UNSUPPORTED_OPTIONS: [&str; 5] = ["output", "split", "split-dir", "clipboard", "force-tty"];
+
+/// Converts named options of a server request into command-line arguments for the parser `P`.
+///
+/// Business Logic Constraint: Options are named like long options without the dashes
+/// (`max-tokens` or `max_tokens`). Flags are set by an empty value or `true` and left unset by
+/// `false`; other options take the value as-is and can be repeated if the option can. Options that
+/// write elsewhere than the response (e.g. `output`) are rejected.
+fn request_args<P: CommandFactory>(options: &[(String, String)]) -> Result<Vec<String>, String> {
+    let command = P::command();
+    let mut args = Vec::with_capacity(options.len());
+    for (key, value) in options {
+        let name = key.replace('_', "-");
+        if SERVER_UNSUPPORTED_OPTIONS.contains(&name.as_str()) {
+            return Err(format!("Option '{}' is not supported by the server", key));
+        }
+        let takes_value = command
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(name.as_str()))
+            .map(|arg| arg.get_action().takes_values());
+        match (takes_value, value.as_str()) {
+            (Some(false), "" | "true") => args.push(format!("--{}", name)),
+            (Some(false), "false") => {}
+            (Some(false), _) => {
+                return Err(format!("Flag '{}' takes no value other than true or false", key));
+            }
+            // Unknown options are passed on, so the parser reports them.
+            _ => args.push(format!("--{}={}", name, value)),
+        }
+    }
+    Ok(args)
+}
+
+/// Parses the named options of a server request (`kek serve`, `kek mcp`) like command-line
+/// options. Returns the parsed options and the arguments they were parsed from, without the task,
+/// as cache key.
+pub fn parse_request<P: Parser>(options: &[(String, String)]) -> Result<(P, Vec<String>), String> {
+    let args = request_args::<P>(options)?;
+    let parsed = P::try_parse_from(&args).map_err(|e| e.render().to_string())?;
+    let key = args.into_iter().filter(|arg| !arg.starts_with("--task=")).collect();
+    Ok((parsed, key))
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigAction {
+    /// Print the configuration files that would be loaded, lowest precedence first.
+    Path,
+    /// Print the effective configuration, including defaults, as TOML.
+    Show,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Cli {
+        let args = std::iter::once("kek").chain(args.iter().copied()).map(OsString::from).collect();
+        Cli::parse_args(args).unwrap()
+    }
+
+    fn task(cli: &Cli) -> Option<String> {
+        match &cli.command {
+            Some(Command::Serialize(args)) => args.task(),
+            None => cli.serialize.task(),
+            Some(command) => panic!("expected a task, got {:?}", command),
+        }
+    }
+
+    #[test]
+    fn task_starting_with_a_subcommand_name_stays_a_task() {
+        let cli = parse(&["list", "all", "bugs"]);
+        assert_eq!(task(&cli).as_deref(), Some("list all bugs"));
+    }
+
+    #[test]
+    fn option_values_are_not_taken_for_the_task() {
+        let cli = parse(&["-c", "kek.toml", "list", "all", "bugs"]);
+        assert_eq!(cli.global.config, Some(PathBuf::from("kek.toml")));
+        assert_eq!(task(&cli).as_deref(), Some("list all bugs"));
+
+        let cli = parse(&["--profile", "review", "--tree", "list", "the", "bugs"]);
+        assert_eq!(cli.global.profile.as_deref(), Some("review"));
+        assert_eq!(task(&cli).as_deref(), Some("list the bugs"));
+
+        let cli = parse(&["--config=kek.toml", "--clipboard", "list", "all", "bugs"]);
+        assert_eq!(task(&cli).as_deref(), Some("list all bugs"));
+    }
+
+    #[test]
+    fn subcommands_are_still_parsed() {
+        let cli = parse(&["-c", "kek.toml", "list", "--tokens"]);
+        assert!(matches!(cli.command, Some(Command::List(ListArgs { tokens: true, .. }))));
+
+        let cli = parse(&["--profile", "review", "list"]);
+        assert!(matches!(cli.command, Some(Command::List(_))));
+    }
+
+    #[test]
+    fn finds_the_first_positional_argument() {
+        let command = Cli::command();
+        let position = |args: &[&str]| {
+            let args: Vec<OsString> = std::iter::once("kek").chain(args.iter().copied()).map(OsString::from).collect();
+            first_positional(&command, &args)
+        };
+        assert_eq!(position(&["list", "bugs"]), Some(1));
+        assert_eq!(position(&["-c", "kek.toml", "list"]), Some(3));
+        assert_eq!(position(&["-ckek.toml", "list"]), Some(2));
+        assert_eq!(position(&["--tree", "-f", "json", "list"]), Some(4));
+        assert_eq!(position(&["--", "-list"]), Some(2));
+        assert_eq!(position(&["-c", "kek.toml"]), None);
+    }
+}
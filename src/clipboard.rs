@@ -0,0 +1,192 @@
+use kek::file_processor::CategoryData;
+use kek::format::OutputFormat;
+use kek::output::{self, OutputSections};
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use std::env;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+
+/// Practical size limit of OSC 52 sequences in bytes of base64: many terminals (and tmux) silently
+/// drop longer sequences.
+const OSC52_LIMIT: u64 = 100_000;
+
+/// How the output is copied to the clipboard (`--clipboard`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClipboardBackend {
+    /// Picks a backend for the current session (see `ClipboardBackend::detect`).
+    Auto,
+    /// `wl-copy` on Wayland.
+    WlCopy,
+    /// `xclip` on X11.
+    Xclip,
+    /// `xsel` on X11.
+    Xsel,
+    /// The OSC 52 terminal escape sequence, which works over SSH if the terminal supports it.
+    Osc52,
+}
+
+impl FromStr for ClipboardBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "auto" => Ok(ClipboardBackend::Auto),
+            "wl-copy" => Ok(ClipboardBackend::WlCopy),
+            "xclip" => Ok(ClipboardBackend::Xclip),
+            "xsel" => Ok(ClipboardBackend::Xsel),
+            "osc52" => Ok(ClipboardBackend::Osc52),
+            _ => Err(format!(
+                "Unknown clipboard backend \"{}\" (expected \"auto\", \"wl-copy\", \"xclip\", \"xsel\" or \"osc52\")",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ClipboardBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClipboardBackend::Auto => f.write_str("auto"),
+            ClipboardBackend::WlCopy => f.write_str("wl-copy"),
+            ClipboardBackend::Xclip => f.write_str("xclip"),
+            ClipboardBackend::Xsel => f.write_str("xsel"),
+            ClipboardBackend::Osc52 => f.write_str("osc52"),
+        }
+    }
+}
+
+/// Returns whether an executable named `name` is in one of the `PATH` directories.
+fn in_path(name: &str) -> bool {
+    env::var_os("PATH").is_some_and(|path| {
+        env::split_paths(&path).any(|dir| {
+            dir.join(name)
+                .metadata()
+                .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+        })
+    })
+}
+
+fn env_is_set(name: &str) -> bool {
+    env::var_os(name).is_some_and(|value| !value.is_empty())
+}
+
+impl ClipboardBackend {
+    /// Resolves `Auto` to a concrete backend.
+    ///
+    /// Business Logic Constraint: In SSH sessions, the clipboard that matters is the one of the
+    /// local machine, so OSC 52 is used. Otherwise wl-copy (Wayland), xclip and xsel (X11) are
+    /// tried in this order, if the display is available and the tool is installed; OSC 52 is the
+    /// fallback.
+    fn detect(self) -> ClipboardBackend {
+        if self != ClipboardBackend::Auto {
+            return self;
+        }
+        if env_is_set("SSH_TTY") || env_is_set("SSH_CONNECTION") {
+            return ClipboardBackend::Osc52;
+        }
+        if env_is_set("WAYLAND_DISPLAY") && in_path("wl-copy") {
+            return ClipboardBackend::WlCopy;
+        }
+        if env_is_set("DISPLAY") {
+            if in_path("xclip") {
+                return ClipboardBackend::Xclip;
+            }
+            if in_path("xsel") {
+                return ClipboardBackend::Xsel;
+            }
+        }
+        ClipboardBackend::Osc52
+    }
+
+    /// Returns the program and arguments of a command-line backend.
+    fn command(self) -> Option<(&'static str, &'static [&'static str])> {
+        match self {
+            ClipboardBackend::WlCopy => Some(("wl-copy", &[])),
+            ClipboardBackend::Xclip => Some(("xclip", &["-selection", "clipboard", "-in"])),
+            ClipboardBackend::Xsel => Some(("xsel", &["--clipboard", "--input"])),
+            ClipboardBackend::Auto | ClipboardBackend::Osc52 => None,
+        }
+    }
+}
+
+/// Copies the payload to the clipboard by piping it into a command-line tool.
+///
+/// Business Logic Constraint: The tool's stdout is discarded, since xclip and wl-copy keep running
+/// in the background to serve the selection and would otherwise hold on to kek's stdout.
+fn copy_with_command(program: &str, args: &[&str], payload: File) -> Result<(), String> {
+    let status = Command::new(program)
+        .args(args)
+        .stdin(Stdio::from(payload))
+        .stdout(Stdio::null())
+        .status()
+        .map_err(|e| format!("Failed to run {}: {}", program, e))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("{} failed ({})", program, status))
+    }
+}
+
+/// Copies the payload to the clipboard by writing an OSC 52 sequence to the controlling terminal.
+///
+/// Business Logic Constraint: Inside tmux, the sequence is wrapped in a DCS passthrough, so it
+/// reaches the outer terminal (requires `set -g allow-passthrough on`).
+fn copy_with_osc52(mut payload: File) -> Result<(), String> {
+    let mut content = Vec::new();
+    payload
+        .read_to_end(&mut content)
+        .map_err(|e| format!("Failed to read the output: {}", e))?;
+    let encoded = BASE64.encode(&content);
+    let sequence = if env_is_set("TMUX") {
+        format!("\x1bPtmux;\x1b\x1b]52;c;{}\x07\x1b\\", encoded)
+    } else {
+        format!("\x1b]52;c;{}\x07", encoded)
+    };
+
+    let mut tty = OpenOptions::new()
+        .write(true)
+        .open(Path::new("/dev/tty"))
+        .map_err(|e| format!("Failed to open the terminal for OSC 52: {}", e))?;
+    tty.write_all(sequence.as_bytes())
+        .and_then(|_| tty.flush())
+        .map_err(|e| format!("Failed to write the OSC 52 sequence: {}", e))
+}
+
+/// Renders the output and copies it to the clipboard using `backend`. Returns the backend used and
+/// the size of the output in bytes.
+///
+/// Business Logic Constraint: The output is rendered into an anonymous in-memory file first, so its
+/// size is known before copying. Only OSC 52 has a known practical limit; larger payloads are
+/// copied anyway, with a warning. wl-copy, xclip and xsel are not checked.
+pub fn copy_output(
+    backend: ClipboardBackend,
+    categories_data: &[CategoryData],
+    sections: &OutputSections,
+    format: OutputFormat,
+) -> Result<(ClipboardBackend, u64), String> {
+    let (payload, size) = output::write_output_memfd(categories_data, sections, format)
+        .map_err(|e| format!("Failed to render the output: {}", e))?;
+
+    let backend = backend.detect();
+    if backend == ClipboardBackend::Osc52 && size.div_ceil(3) * 4 > OSC52_LIMIT {
+        eprintln!(
+            "[WARNING] The output is {} bytes ({} bytes as base64); many terminals ignore OSC 52 sequences over {} bytes.",
+            size,
+            size.div_ceil(3) * 4,
+            OSC52_LIMIT
+        );
+    }
+
+    match backend.command() {
+        Some((program, args)) => copy_with_command(program, args, payload)?,
+        None => copy_with_osc52(payload)?,
+    }
+    Ok((backend, size))
+}
@@ -1,42 +1,239 @@
+use crate::binary::{BinaryAction, BinaryRules};
+use crate::diagnostic::Diagnostic;
+use crate::error::Error;
+use crate::format::OutputFormat;
+use crate::git::{GitHistorySettings, GitSelection};
+use crate::priority::{PriorityRules, SortKey};
+use crate::size_limit::OversizedAction;
+use crate::tokenizer::Tokenizer;
+use crate::transform::{TransformRules, Transforms};
+use crate::tree::{TreeMetric, TreeSettings};
+
 use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
-use serde::Deserialize;
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
 use std::env;
+use std::fmt;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 pub const DOCS_DESCRIPTION: &str = "Immutable documentation. Provided FOR REFERENCE ONLY.";
 pub const SRC_DESCRIPTION: &str = "Source code files.";
 pub const OTHER_DESCRIPTION: &str = "Other files.";
 
-#[derive(Deserialize, Debug)]
+/// Name of the fallback category for files matching no other category.
+pub const OTHER_CATEGORY: &str = "other";
+
+/// A `[category.<name>]` entry: either a plain list of globs (`docs = [...]`) or a table.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(untagged)]
+enum TomlCategory {
+    Globs(Vec<String>),
+    Table(TomlCategoryTable),
+}
+
+/// A category given as a table with globs, description and output position.
+#[derive(Deserialize, Serialize, Debug)]
 #[serde(deny_unknown_fields)]
-struct TomlCategoryGlobs {
-    #[serde(default = "default_docs_globs_str_vec")]
-    docs: Vec<String>,
-    #[serde(default = "default_src_globs_str_vec")]
-    src: Vec<String>,
+struct TomlCategoryTable {
+    /// Globs of files belonging to the category. Built-in categories keep their defaults if
+    /// omitted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    globs: Option<Vec<String>>,
+    /// Text of the category's `<description>`. Built-in categories keep their default if omitted.
+    /// May span multiple lines and contain the placeholders `{name}`, `{count}`, `{size}`,
+    /// `{bytes}` and `{tokens}` (see `file_processor::expand_descriptions`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    /// Output position of the category. Categories are ordered by ascending position before
+    /// priority scores are considered. Defaults to 0.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    position: i64,
+    /// Size limit in bytes for files in this category, overriding the top-level `max_file_size`.
+    /// 0 disables the limit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_file_size: Option<u64>,
+    /// Handling of files over the size limit, overriding the top-level `oversized`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    oversized: Option<OversizedAction>,
+    /// Sends only the skeleton (signatures, type definitions, doc comments) of the files in this
+    /// category.
+    #[serde(default, skip_serializing_if = "is_false")]
+    skeleton: bool,
 }
 
-impl Default for TomlCategoryGlobs {
-    fn default() -> Self {
-        Self {
-            docs: default_docs_globs_str_vec(),
-            src: default_src_globs_str_vec(),
+fn is_zero(value: &i64) -> bool {
+    *value == 0
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+/// Escapes the glob metacharacters in `text`, so it only matches itself.
+fn escape_glob(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '*' | '?' | '[' | ']' | '{' | '}') {
+            escaped.push('\\');
         }
+        escaped.push(c);
     }
+    escaped
+}
+
+fn default_categories() -> IndexMap<String, TomlCategory> {
+    IndexMap::from([
+        ("docs".to_string(), TomlCategory::Globs(default_docs_globs_str_vec())),
+        ("src".to_string(), TomlCategory::Globs(default_src_globs_str_vec())),
+    ])
+}
+
+/// Returns the default globs of a category (empty for categories that aren't built in).
+fn default_globs(name: &str) -> Vec<String> {
+    match name {
+        "docs" => default_docs_globs_str_vec(),
+        "src" => default_src_globs_str_vec(),
+        _ => Vec::new(),
+    }
+}
+
+/// Returns the default description of a category.
+fn default_description(name: &str) -> String {
+    match name {
+        "docs" => DOCS_DESCRIPTION.to_string(),
+        "src" => SRC_DESCRIPTION.to_string(),
+        OTHER_CATEGORY => OTHER_DESCRIPTION.to_string(),
+        _ => format!("Files in the '{}' category.", name),
+    }
+}
+
+/// Handling of files whose content looks binary.
+#[derive(Deserialize, Serialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+struct TomlBinary {
+    /// Action for binary files matching none of the glob lists below. Defaults to "skip".
+    #[serde(default)]
+    default: BinaryAction,
+    /// Globs of binary files to leave out of the output.
+    #[serde(default)]
+    skip: Vec<String>,
+    /// Globs of binary files whose path is listed without content.
+    #[serde(default)]
+    list: Vec<String>,
+    /// Globs of binary files whose content is included as-is.
+    #[serde(default)]
+    include: Vec<String>,
+}
+
+/// A `[[priority]]` entry assigning a score to files matching a glob.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct TomlPriority {
+    glob: String,
+    score: i64,
+}
+
+/// A `[[transform]]` entry selecting content transforms for files matching a glob.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct TomlTransform {
+    glob: String,
+    #[serde(default)]
+    strip_comments: bool,
+    #[serde(default)]
+    collapse_blank_lines: bool,
+    #[serde(default)]
+    trim_trailing_whitespace: bool,
+    #[serde(default)]
+    skeleton: bool,
 }
 
 /// Defines the root structure of the TOML configuration file.
-#[derive(Deserialize, Debug, Default)]
+#[derive(Deserialize, Serialize, Debug)]
 #[serde(deny_unknown_fields)]
 struct TomlConfig {
-    /// Contains specifications for glob patterns belonging to different categories.
-    #[serde(default)]
-    category: TomlCategoryGlobs,
+    /// Categories in order of matching precedence, keyed by name.
+    #[serde(default = "default_categories")]
+    category: IndexMap<String, TomlCategory>,
     /// List of directories to scan. Paths can be absolute or relative to the current working directory.
     /// If omitted, defaults to the current working directory ["."].
     #[serde(default = "default_scan_str_vec")]
     scan: Vec<String>,
+    /// Gitignore-style globs of files to include. If non-empty, other files are left out.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    include: Vec<String>,
+    /// Gitignore-style globs of files and directories to leave out.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    exclude: Vec<String>,
+    /// Tokenizer used for token counting: "cl100k" (default) or "o200k".
+    #[serde(default)]
+    tokenizer: Tokenizer,
+    /// Maximum number of tokens in the serialized output. Unlimited if omitted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<usize>,
+    /// Output format: "pseudo-xml" (default), "markdown", "json" or "xml".
+    #[serde(default)]
+    format: OutputFormat,
+    /// Binary file handling.
+    #[serde(default)]
+    binary: TomlBinary,
+    /// Size limit in bytes for a single file. Unlimited if omitted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_file_size: Option<u64>,
+    /// Handling of files over the size limit: "truncate" (default) or "skip".
+    #[serde(default)]
+    oversized: OversizedAction,
+    /// Order of files with equal priority: "path" (default) or "size".
+    #[serde(default)]
+    sort: SortKey,
+    /// Priority scores controlling file order. Higher scores are placed closer to the task.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    priority: Vec<TomlPriority>,
+    /// Content transforms (comment and whitespace stripping) for files matching globs.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    transform: Vec<TomlTransform>,
+    /// Applies all transforms to the files of the 'src' category.
+    #[serde(default)]
+    minify: bool,
+    /// Enables git-history-based importance scores when present.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    git_history: Option<GitHistorySettings>,
+    /// Directory tree section written before the file contents.
+    #[serde(default)]
+    tree: TreeSettings,
+    /// Profile applied when none is given with `--profile`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    default_profile: Option<String>,
+    /// Named sets of settings merged over the rest of the configuration when selected
+    /// (see `apply_profile`). Any top-level key except `profile` and `default_profile` is allowed.
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    profile: IndexMap<String, toml::Table>,
+}
+
+impl Default for TomlConfig {
+    fn default() -> Self {
+        Self {
+            category: default_categories(),
+            scan: default_scan_str_vec(),
+            include: Vec::new(),
+            exclude: Vec::new(),
+            tokenizer: Tokenizer::default(),
+            max_tokens: None,
+            format: OutputFormat::default(),
+            binary: TomlBinary::default(),
+            max_file_size: None,
+            oversized: OversizedAction::default(),
+            sort: SortKey::default(),
+            priority: Vec::new(),
+            transform: Vec::new(),
+            minify: false,
+            git_history: None,
+            tree: TreeSettings::default(),
+            default_profile: None,
+            profile: IndexMap::new(),
+        }
+    }
 }
 
 fn default_scan_str_vec() -> Vec<String> {
@@ -44,8 +241,8 @@ fn default_scan_str_vec() -> Vec<String> {
 }
 
 // --- Default Glob Pattern Lists ---
-// Business Logic Constraint: Default glob patterns are provided for 'docs' and 'src' categories.
-// These are used if not overridden in the configuration file.
+// Business Logic Constraint: Default glob patterns are provided for the built-in 'docs' and 'src'
+// categories. These are used if the categories are not defined in the configuration file.
 // Defaults include common extensions (as "*.ext" and ".*.ext") and common exact filenames.
 
 fn default_docs_globs_str_vec() -> Vec<String> {
@@ -307,12 +504,160 @@ fn default_src_globs_str_vec() -> Vec<String> {
         .collect()
 }
 
+/// A category compiled from a `[category.<name>]` entry.
+#[derive(Debug, Clone)]
+pub struct CategoryRule {
+    pub name: String,
+    pub globs: GlobSet,
+    /// Description template; placeholders are expanded right before output.
+    pub description: String,
+    pub position: i64,
+    /// Size limit overriding `AppConfig::max_file_size`; `Some(0)` disables the limit.
+    pub max_file_size: Option<u64>,
+    /// Handling of oversized files overriding `AppConfig::oversized`.
+    pub oversized: Option<OversizedAction>,
+    /// Whether only the skeletons of the category's files are sent (see `Transforms::skeleton`).
+    pub skeleton: bool,
+}
+
 /// Application configuration, derived from `TomlConfig`.
 #[derive(Debug, Clone)]
 pub struct AppConfig {
-    pub docs: GlobSet,
-    pub src: GlobSet,
+    /// Directory that relative scan paths, globs and output paths are relative to: the directory of
+    /// the project kek.toml, or the working directory if there is none.
+    pub base_dir: PathBuf,
+    /// Categories in order of matching precedence. The last one is always the 'other' fallback,
+    /// whose globs are empty.
+    pub categories: Vec<CategoryRule>,
     pub scan: Vec<PathBuf>,
+    /// Include globs, applied in the walker as overrides (see `file_processor::build_overrides`).
+    pub include: Vec<String>,
+    /// Exclude globs, applied in the walker as overrides.
+    pub exclude: Vec<String>,
+    pub tokenizer: Tokenizer,
+    /// Token budget for the whole output. Files are dropped until the output fits.
+    pub max_tokens: Option<usize>,
+    /// Whether token counts should be computed even without a budget (e.g. for
+    /// `kek list --tokens`).
+    pub count_tokens: bool,
+    pub binary: BinaryRules,
+    /// Size limit in bytes for files in categories without their own limit.
+    pub max_file_size: Option<u64>,
+    pub oversized: OversizedAction,
+    pub format: OutputFormat,
+    pub priority: PriorityRules,
+    pub transform: TransformRules,
+    /// Applies all transforms to the files of the 'src' category (`minify`, `--minify`).
+    pub minify: bool,
+    pub sort: SortKey,
+    /// Adds recency/churn scores from the local git history to the priority scores.
+    pub git_history: Option<GitHistorySettings>,
+    pub tree: TreeSettings,
+    /// Restricts the output to files changed in git (set from the command line only).
+    pub git_selection: Option<GitSelection>,
+    /// Problems noticed while loading the configuration, such as a missing `KEK_CONFIG` file.
+    /// Serializations report them before their own (see `pipeline::collect_files`).
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// The built-in defaults, as if there were no configuration files, relative to the working
+/// directory.
+impl Default for AppConfig {
+    fn default() -> Self {
+        build_config(TomlConfig::default(), PathBuf::from("."), Vec::new())
+            .expect("the default configuration is valid")
+    }
+}
+
+impl AppConfig {
+    /// Returns the index in `categories` of the category of the file at `relative_path`.
+    ///
+    /// Business Logic Constraint: The first category with a matching glob wins; files matching
+    /// none fall back to 'other'.
+    pub fn category_index(&self, relative_path: &Path) -> usize {
+        self.categories
+            .iter()
+            .position(|category| category.globs.is_match(relative_path))
+            .unwrap_or(self.categories.len() - 1)
+    }
+
+    /// Returns the size limit and the handling of oversized files for the category at
+    /// `category_index`, or `None` if its files are unlimited.
+    ///
+    /// Business Logic Constraint: Category settings take precedence over the top-level ones.
+    pub fn size_limit_for(&self, category_index: usize) -> Option<(u64, OversizedAction)> {
+        let category = &self.categories[category_index];
+        let limit = category.max_file_size.or(self.max_file_size).filter(|&limit| limit > 0)?;
+        Some((limit, category.oversized.unwrap_or(self.oversized)))
+    }
+
+    /// Returns the content transforms for the file at `relative_path` in the category at
+    /// `category_index`.
+    ///
+    /// Business Logic Constraint: `minify` applies the comment and whitespace transforms to the
+    /// 'src' category, and a category's `skeleton` makes skeletons of its files, on top of the
+    /// `[[transform]]` entries matching the file.
+    pub fn transforms_for(&self, relative_path: &Path, category_index: usize) -> Transforms {
+        let category = &self.categories[category_index];
+        let mut transforms = self.transform.transforms_for(relative_path);
+        if self.minify && category.name == "src" {
+            transforms = transforms.union(Transforms::MINIFY);
+        }
+        transforms.skeleton |= category.skeleton;
+        transforms
+    }
+
+    /// Leaves the file at `path` out of the walk by adding an anchored `exclude` glob, so an output
+    /// file written inside a scan root is never serialized into itself.
+    ///
+    /// Business Logic Constraint: Only files below the base directory are excluded; others can't
+    /// be matched by an anchored glob.
+    pub fn exclude_file(&mut self, path: &Path) {
+        let (Some(parent), Some(file_name)) = (path.parent(), path.file_name()) else {
+            return;
+        };
+        if let Some(mut glob) = self.anchored_dir_glob(parent) {
+            glob.push_str(&escape_glob(&file_name.to_string_lossy()));
+            self.exclude.push(glob);
+        }
+    }
+
+    /// Leaves the chunk files `--split` writes into `dir` (`kek-001.<extension>`, ...) out of the
+    /// walk, like `exclude_file`, so a second run doesn't serialize the chunks of the first one.
+    /// The glob matches three-digit numbers only, as there are at most `split::MAX_CHUNKS` chunks.
+    pub fn exclude_split_chunks(&mut self, dir: &Path, extension: &str) {
+        if let Some(mut glob) = self.anchored_dir_glob(dir) {
+            glob.push_str("kek-[0-9][0-9][0-9].");
+            glob.push_str(&escape_glob(extension));
+            self.exclude.push(glob);
+        }
+    }
+
+    /// Returns the anchored glob prefix matching the files directly in `dir` (e.g. `/out/`), or
+    /// `None` if `dir` doesn't exist or isn't below the base directory.
+    fn anchored_dir_glob(&self, dir: &Path) -> Option<String> {
+        let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+        let dir = fs::canonicalize(dir).ok()?;
+        let base_dir = fs::canonicalize(&self.base_dir).ok()?;
+        let relative_dir = dir.strip_prefix(&base_dir).ok()?;
+        let mut glob = String::from("/");
+        if !relative_dir.as_os_str().is_empty() {
+            glob.push_str(&escape_glob(&relative_dir.to_string_lossy()));
+            glob.push('/');
+        }
+        Some(glob)
+    }
+
+    /// Returns whether files need to be tokenized while walking.
+    pub fn needs_token_counts(&self) -> bool {
+        self.count_tokens
+            || self.max_tokens.is_some()
+            || (self.tree.enabled && self.tree.metric == TreeMetric::Tokens)
+            || self
+                .categories
+                .iter()
+                .any(|category| category.description.contains("{tokens}"))
+    }
 }
 
 /// Helper function to build a GlobSet from a list of pattern strings.
@@ -339,39 +684,412 @@ fn build_glob_set(glob_strings: &[String], category_name: &str) -> Result<GlobSe
         .map_err(|e| format!("Failed to build glob set for '{}': {}", category_name, e))
 }
 
-/// Loads the application configuration from the TOML file specified by the KEK_CONFIG environment variable,
-/// or `kek.toml` by default.
+/// File name of project configuration files.
+pub const PROJECT_CONFIG_FILE: &str = "kek.toml";
+
+/// Origin of a configuration file, in ascending order of precedence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ConfigSource {
+    /// `$XDG_CONFIG_HOME/kek/config.toml`, or `~/.config/kek/config.toml`.
+    User,
+    /// The nearest `kek.toml` between the working directory and the repository root.
+    Project,
+    /// The file named by the `KEK_CONFIG` environment variable.
+    Env,
+    /// The file given with `--config`.
+    Cli,
+}
+
+impl fmt::Display for ConfigSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigSource::User => f.write_str("user"),
+            ConfigSource::Project => f.write_str("project"),
+            ConfigSource::Env => f.write_str("env"),
+            ConfigSource::Cli => f.write_str("cli"),
+        }
+    }
+}
+
+/// A configuration file contributing to the effective configuration.
+#[derive(Debug, Clone)]
+pub struct ConfigLayer {
+    pub source: ConfigSource,
+    pub path: PathBuf,
+}
+
+/// Returns the path of the user configuration file, whether it exists or not.
+///
+/// Business Logic Constraint: Like the XDG base directory specification, a relative
+/// `XDG_CONFIG_HOME` is ignored in favour of `~/.config`.
+fn user_config_path() -> Option<PathBuf> {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_home.join("kek").join("config.toml"))
+}
+
+/// Finds the nearest `kek.toml` in `working_dir` or one of its parents.
+///
+/// Business Logic Constraint: The search stops at the repository root (the first directory
+/// containing `.git`), so a `kek.toml` outside the repository is never picked up. Outside of a
+/// repository, only `working_dir` itself is searched.
+fn project_config_path(working_dir: &Path) -> Option<PathBuf> {
+    let search_end = working_dir
+        .ancestors()
+        .find(|dir| dir.join(".git").exists())
+        .unwrap_or(working_dir);
+    for dir in working_dir.ancestors() {
+        let candidate = dir.join(PROJECT_CONFIG_FILE);
+        if candidate.is_file() {
+            return Some(candidate);
+        }
+        if dir == search_end {
+            break;
+        }
+    }
+    None
+}
+
+/// Discovers the configuration files for `working_dir`, in ascending order of precedence:
+/// the user configuration, the project `kek.toml`, the file named by `KEK_CONFIG` and the file
+/// given with `--config` (`explicit_path`).
+///
+/// Business Logic Constraint: Missing user and project files are skipped silently, a missing
+/// `KEK_CONFIG` file with a warning in `diagnostics`. A path given via `--config` must exist,
+/// which is checked when the layer is read.
+pub fn discover_config_layers(
+    explicit_path: Option<&Path>,
+    working_dir: &Path,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Vec<ConfigLayer> {
+    let mut layers = Vec::new();
+    if let Some(path) = user_config_path().filter(|path| path.is_file()) {
+        layers.push(ConfigLayer {
+            source: ConfigSource::User,
+            path,
+        });
+    }
+    if let Some(path) = project_config_path(working_dir) {
+        layers.push(ConfigLayer {
+            source: ConfigSource::Project,
+            path,
+        });
+    }
+    if let Some(path) = env::var_os("KEK_CONFIG").map(PathBuf::from) {
+        if path.exists() {
+            layers.push(ConfigLayer {
+                source: ConfigSource::Env,
+                path,
+            });
+        } else {
+            diagnostics.push(Diagnostic::Warning(format!(
+                "Config file {:?} from KEK_CONFIG does not exist. Ignoring it.",
+                path
+            )));
+        }
+    }
+    if let Some(path) = explicit_path {
+        layers.push(ConfigLayer {
+            source: ConfigSource::Cli,
+            path: path.to_path_buf(),
+        });
+    }
+    layers
+}
+
+/// Reads a configuration file as a TOML table.
+///
+/// Business Logic Constraint: Every file is validated on its own before merging, so errors point
+/// at the file and line they come from.
+fn read_config_layer(layer: &ConfigLayer) -> Result<toml::Table, String> {
+    if !layer.path.exists() {
+        return Err(format!("Config file {:?} does not exist", layer.path));
+    }
+
+    let config_content = fs::read_to_string(&layer.path)
+        .map_err(|e| format!("Failed to read config file {:?}: {}", layer.path, e))?;
+    let parse_error = |e: toml::de::Error| {
+        format!(
+            "Failed to parse configuration file (TOML) {:?}: {}",
+            layer.path, e
+        )
+    };
+
+    let toml_config = toml::from_str::<TomlConfig>(&config_content).map_err(parse_error)?;
+    for (name, profile) in &toml_config.profile {
+        validate_profile(profile)
+            .map_err(|e| format!("Invalid profile '{}' in {:?}: {}", name, layer.path, e))?;
+    }
+    toml::from_str::<toml::Table>(&config_content).map_err(parse_error)
+}
+
+/// Checks that a `[profile.<name>]` table only contains valid top-level settings.
+fn validate_profile(profile: &toml::Table) -> Result<(), String> {
+    if let Some(key) = ["profile", "default_profile"]
+        .into_iter()
+        .find(|key| profile.contains_key(*key))
+    {
+        return Err(format!("'{}' can't be set in a profile", key));
+    }
+    toml::Value::Table(profile.clone())
+        .try_into::<TomlConfig>()
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+/// Merges the selected profile over the merged configuration files and removes all profiles.
+///
+/// Business Logic Constraint: `--profile` (`profile`) takes precedence over `default_profile`.
+/// Profiles from all files are merged like any other table, so a project can extend a profile of
+/// the user configuration. Selecting a profile that isn't defined is an error.
+fn apply_profile(mut merged: toml::Table, profile: Option<&str>) -> Result<toml::Table, String> {
+    let profiles = match merged.remove("profile") {
+        Some(toml::Value::Table(profiles)) => profiles,
+        _ => toml::Table::new(),
+    };
+    let default_profile = match merged.remove("default_profile") {
+        Some(toml::Value::String(name)) => Some(name),
+        _ => None,
+    };
+    let Some(name) = profile.map(str::to_string).or(default_profile) else {
+        return Ok(merged);
+    };
+
+    match profiles.get(&name) {
+        Some(toml::Value::Table(settings)) => Ok(merge_tables(merged, settings.clone())),
+        _ => {
+            let available: Vec<&str> = profiles.keys().map(String::as_str).collect();
+            Err(format!(
+                "Profile '{}' is not defined (available: {})",
+                name,
+                if available.is_empty() {
+                    "none".to_string()
+                } else {
+                    available.join(", ")
+                }
+            ))
+        }
+    }
+}
+
+/// Merges the table of a configuration file over the tables of the files below it.
+///
+/// Business Logic Constraint: Tables (e.g. `[tree]`, `[category.docs]`) are merged key by key;
+/// any other value, including arrays such as `scan` or `[[priority]]`, replaces the lower one.
+/// Keys of the higher layer come first, so its categories are matched before inherited ones.
+fn merge_tables(mut lower: toml::Table, higher: toml::Table) -> toml::Table {
+    let mut merged = toml::Table::new();
+    for (key, value) in higher {
+        let value = match (lower.remove(&key), value) {
+            (Some(toml::Value::Table(lower_table)), toml::Value::Table(higher_table)) => {
+                toml::Value::Table(merge_tables(lower_table, higher_table))
+            }
+            (_, value) => value,
+        };
+        merged.insert(key, value);
+    }
+    merged.extend(lower);
+    merged
+}
+
+/// Reads and merges the configuration files and the selected profile, falling back to defaults for
+/// anything they omit.
+fn read_toml_config(layers: &[ConfigLayer], profile: Option<&str>) -> Result<TomlConfig, String> {
+    let mut merged = toml::Table::new();
+    for layer in layers {
+        merged = merge_tables(merged, read_config_layer(layer)?);
+    }
+    let merged = apply_profile(merged, profile)?;
+
+    let mut toml_config: TomlConfig = toml::Value::Table(merged)
+        .try_into()
+        .map_err(|e| format!("Failed to merge configuration files: {}", e))?;
+
+    // Business Logic Constraint: Built-in categories that aren't defined in any file keep their
+    // default globs and are matched after the categories from the files.
+    for (name, category) in default_categories() {
+        toml_config.category.entry(name).or_insert(category);
+    }
+
+    Ok(toml_config)
+}
+
+/// Compiles the `[category.<name>]` entries, followed by the 'other' fallback.
 ///
-/// The configuration file can specify:
-/// - `scan`: A list of paths to scan. Defaults to `["."]` (current working directory).
-///   Paths are relative to the current working directory unless absolute.
-/// - `category.docs`: Glob patterns for 'docs' category.
-/// - `category.src`: Glob patterns for 'src' category.
+/// Business Logic Constraint: `[category.other]` configures the fallback category; it can't have
+/// globs and is always matched last.
+fn build_categories(toml_categories: IndexMap<String, TomlCategory>) -> Result<Vec<CategoryRule>, String> {
+    let mut categories = Vec::with_capacity(toml_categories.len() + 1);
+    let mut other = CategoryRule {
+        name: OTHER_CATEGORY.to_string(),
+        globs: GlobSet::empty(),
+        description: default_description(OTHER_CATEGORY),
+        position: 0,
+        max_file_size: None,
+        oversized: None,
+        skeleton: false,
+    };
+    for (name, category) in toml_categories {
+        if name == OTHER_CATEGORY {
+            match category {
+                TomlCategory::Table(table) if table.globs.is_none() => {
+                    if let Some(description) = table.description {
+                        other.description = description;
+                    }
+                    other.position = table.position;
+                    other.max_file_size = table.max_file_size;
+                    other.oversized = table.oversized;
+                    other.skeleton = table.skeleton;
+                    continue;
+                }
+                _ => {
+                    return Err(format!(
+                        "Category '{}' is the fallback for unmatched files and can't have globs",
+                        OTHER_CATEGORY
+                    ));
+                }
+            }
+        }
+        let rule = match category {
+            TomlCategory::Globs(globs) => CategoryRule {
+                globs: build_glob_set(&globs, &format!("category.{}", name))?,
+                description: default_description(&name),
+                position: 0,
+                max_file_size: None,
+                oversized: None,
+                skeleton: false,
+                name,
+            },
+            TomlCategory::Table(table) => CategoryRule {
+                globs: build_glob_set(
+                    &table.globs.unwrap_or_else(|| default_globs(&name)),
+                    &format!("category.{}", name),
+                )?,
+                description: table.description.unwrap_or_else(|| default_description(&name)),
+                position: table.position,
+                max_file_size: table.max_file_size,
+                oversized: table.oversized,
+                skeleton: table.skeleton,
+                name,
+            },
+        };
+        categories.push(rule);
+    }
+    categories.push(other);
+    Ok(categories)
+}
+
+/// Renders the effective configuration (all configuration files and the selected profile merged
+/// with defaults) as TOML.
+pub fn effective_config_toml(
+    explicit_path: Option<&Path>,
+    profile: Option<&str>,
+    working_dir: &Path,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Result<String, Error> {
+    let layers = discover_config_layers(explicit_path, working_dir, diagnostics);
+    let toml_config = read_toml_config(&layers, profile).map_err(Error::Config)?;
+    toml::to_string_pretty(&toml_config)
+        .map_err(|e| Error::Config(format!("Failed to render configuration as TOML: {}", e)))
+}
+
+/// Loads the application configuration for `working_dir` from the user configuration, the project
+/// `kek.toml`, the file named by `KEK_CONFIG` and the file given with `--config` (`explicit_path`),
+/// merged in this order (see `discover_config_layers` and `merge_tables`). The profile given with
+/// `--profile` (`profile`), or else `default_profile`, is merged over the result (see
+/// `apply_profile`).
+///
+/// The configuration files can specify:
+/// - `scan`: A list of paths to scan. Defaults to `["."]`.
+///   Paths are relative to the base directory (see `AppConfig::base_dir`) unless absolute.
+/// - `category.<name>`: Glob patterns for a category, either as a list or as a table with `globs`,
+///   `description`, `position`, `max_file_size`, `oversized` and `skeleton`. The built-in 'docs'
+///   and 'src' categories have default globs; `category.other` can override the description and
+///   position of the fallback category.
+/// - `include` / `exclude`: Gitignore-style globs selecting files independently of ignore files.
+/// - `tokenizer`: Tokenizer used for token counting (`cl100k` or `o200k`).
+/// - `max_tokens`: Token budget for the output.
+/// - `format`: Output format (`pseudo-xml`, `markdown`, `json` or `xml`).
+/// - `max_file_size` / `oversized`: Size limit for single files and whether oversized files are
+///   truncated or skipped. Both can be overridden per category.
+/// - `binary`: Default action and per-glob overrides (`skip`, `list`, `include`) for binary files.
+/// - `sort`: Order of files with equal priority (`path` or `size`).
+/// - `priority`: A list of `{ glob, score }` entries controlling the order of files and categories.
+/// - `transform`: A list of `{ glob, strip_comments, collapse_blank_lines,
+///   trim_trailing_whitespace, skeleton }` entries transforming the content of matching files.
+/// - `minify`: Applies all transforms to the 'src' category.
+/// - `tree`: Whether to write a directory tree section (`enabled`), including excluded files
+///   (`excluded`), annotated with sizes or tokens (`metric`).
+/// - `git_history`: Bounds (`commits`, `days`) and weight (`boost`) of git-history-based scores.
+/// - `profile.<name>`: Any of the settings above, applied when the profile is selected.
+/// - `default_profile`: The profile applied when none is given on the command line.
 ///
-/// If the configuration file doesn't exist or specific settings are omitted, defaults are used.
+/// If no configuration file exists or specific settings are omitted, defaults are used.
 ///
 /// Business Logic Constraint: Glob patterns are matched case-insensitively against relative file paths.
 /// Business Logic Constraint: Glob compilation errors will cause configuration loading to fail.
-pub fn load_config() -> Result<AppConfig, String> {
-    let config_path_str = env::var("KEK_CONFIG").unwrap_or_else(|_| "kek.toml".to_string());
-    let config_path = PathBuf::from(config_path_str);
+pub fn load_config(
+    explicit_path: Option<&Path>,
+    profile: Option<&str>,
+    working_dir: &Path,
+) -> Result<AppConfig, Error> {
+    let mut diagnostics = Vec::new();
+    let layers = discover_config_layers(explicit_path, working_dir, &mut diagnostics);
+    let toml_config = read_toml_config(&layers, profile).map_err(Error::Config)?;
 
-    let toml_config: TomlConfig = if !config_path.exists() {
-        TomlConfig::default()
-    } else {
-        let config_content = fs::read_to_string(&config_path)
-            .map_err(|e| format!("Failed to read config file {:?}: {}", config_path, e))?;
-
-        toml::from_str(&config_content).map_err(|e| {
-            format!(
-                "Failed to parse configuration file (TOML) {:?}: {}",
-                config_path, e
-            )
-        })?
-    };
+    // Business Logic Constraint: Running kek from a subdirectory behaves like running it next to
+    // the project kek.toml, so its scan paths and globs mean the same everywhere in the project.
+    let base_dir = layers
+        .iter()
+        .find(|layer| layer.source == ConfigSource::Project)
+        .and_then(|layer| layer.path.parent())
+        .map_or_else(|| working_dir.to_path_buf(), Path::to_path_buf);
 
-    let docs_globset = build_glob_set(&toml_config.category.docs, "docs")?;
-    let src_globset = build_glob_set(&toml_config.category.src, "src")?;
+    build_config(toml_config, base_dir, diagnostics)
+}
+
+/// Compiles the merged configuration into an `AppConfig`, adding to the `diagnostics` of loading
+/// it.
+fn build_config(
+    toml_config: TomlConfig,
+    base_dir: PathBuf,
+    mut diagnostics: Vec<Diagnostic>,
+) -> Result<AppConfig, Error> {
+    let categories = build_categories(toml_config.category).map_err(Error::Config)?;
+    let binary = BinaryRules {
+        default: toml_config.binary.default,
+        skip: build_glob_set(&toml_config.binary.skip, "binary.skip").map_err(Error::Config)?,
+        list: build_glob_set(&toml_config.binary.list, "binary.list").map_err(Error::Config)?,
+        include: build_glob_set(&toml_config.binary.include, "binary.include").map_err(Error::Config)?,
+    };
+    let priority_globs: Vec<String> = toml_config.priority.iter().map(|p| p.glob.clone()).collect();
+    let priority = PriorityRules {
+        globs: build_glob_set(&priority_globs, "priority").map_err(Error::Config)?,
+        scores: toml_config.priority.iter().map(|p| p.score).collect(),
+    };
+    let transform_globs: Vec<String> = toml_config.transform.iter().map(|t| t.glob.clone()).collect();
+    let transform = TransformRules {
+        globs: build_glob_set(&transform_globs, "transform").map_err(Error::Config)?,
+        transforms: toml_config
+            .transform
+            .iter()
+            .map(|t| Transforms {
+                strip_comments: t.strip_comments,
+                collapse_blank_lines: t.collapse_blank_lines,
+                trim_trailing_whitespace: t.trim_trailing_whitespace,
+                skeleton: t.skeleton,
+            })
+            .collect(),
+    };
+    if !cfg!(feature = "skeleton")
+        && (transform.transforms.iter().any(|t| t.skeleton) || categories.iter().any(|c| c.skeleton))
+    {
+        diagnostics.push(Diagnostic::Warning(
+            "kek was built without the 'skeleton' feature. Sending whole files instead of skeletons.".to_string(),
+        ));
+    }
 
     let scan: Vec<PathBuf> = toml_config
         .scan
@@ -387,14 +1105,199 @@ pub fn load_config() -> Result<AppConfig, String> {
         scan
     };
 
-
-    // Business Logic Constraint: A file path will be categorized by the first matching glob list,
-    // in the order of 'docs', then 'src'. If a file matches globs from both lists,
-    // it will be categorized as 'docs' due to this precedence.
+    // Business Logic Constraint: A file path will be categorized by the first matching category,
+    // in the order of the configuration files (see `merge_tables`), followed by the built-in 'docs'
+    // and 'src' categories if no file defines them. If a file matches several categories, the first one wins.
 
     Ok(AppConfig {
-        docs: docs_globset,
-        src: src_globset,
-        scan
+        base_dir,
+        categories,
+        scan,
+        include: toml_config.include,
+        exclude: toml_config.exclude,
+        tokenizer: toml_config.tokenizer,
+        max_tokens: toml_config.max_tokens,
+        count_tokens: false,
+        binary,
+        max_file_size: toml_config.max_file_size,
+        oversized: toml_config.oversized,
+        format: toml_config.format,
+        priority,
+        transform,
+        minify: toml_config.minify,
+        sort: toml_config.sort,
+        git_history: toml_config.git_history,
+        tree: toml_config.tree,
+        git_selection: None,
+        diagnostics,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(toml: &str) -> toml::Table {
+        toml.parse().unwrap()
+    }
+
+    /// Writes `content` to a file in the temporary directory, removed when dropped.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, content: &str) -> TempFile {
+            let path = std::env::temp_dir().join(format!("kek-config-{}-{}", std::process::id(), name));
+            fs::write(&path, content).unwrap();
+            TempFile(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn merges_tables_key_by_key_and_replaces_other_values() {
+        let lower = table(
+            r#"
+            scan = ["src", "docs"]
+            max_tokens = 1000
+            [tree]
+            enabled = true
+            max_depth = 3
+            [category.docs]
+            globs = ["*.md"]
+            description = "Documentation."
+            "#,
+        );
+        let higher = table(
+            r#"
+            scan = ["lib"]
+            [tree]
+            max_depth = 5
+            [category.tests]
+            globs = ["tests/**"]
+            [category.docs]
+            globs = ["*.rst"]
+            "#,
+        );
+        let expected = table(
+            r#"
+            scan = ["lib"]
+            max_tokens = 1000
+            [tree]
+            enabled = true
+            max_depth = 5
+            [category.tests]
+            globs = ["tests/**"]
+            [category.docs]
+            globs = ["*.rst"]
+            description = "Documentation."
+            "#,
+        );
+        let merged = merge_tables(lower, higher);
+        assert_eq!(merged, expected);
+        let categories: Vec<&String> = merged["category"].as_table().unwrap().keys().collect();
+        assert_eq!(categories, ["tests", "docs"]);
+    }
+
+    #[test]
+    fn applies_the_selected_profile() {
+        let merged = table(
+            r#"
+            default_profile = "small"
+            max_tokens = 1000
+            format = "markdown"
+            [tree]
+            metric = "tokens"
+            [profile.small]
+            max_tokens = 500
+            [profile.review.tree]
+            enabled = true
+            "#,
+        );
+        let build = |merged: toml::Table| {
+            let toml_config: TomlConfig = toml::Value::Table(merged).try_into().unwrap();
+            build_config(toml_config, PathBuf::from("."), Vec::new()).unwrap()
+        };
+
+        let small = apply_profile(merged.clone(), None).unwrap();
+        assert_eq!(
+            small,
+            table("max_tokens = 500\nformat = \"markdown\"\n[tree]\nmetric = \"tokens\"")
+        );
+        let app_config = build(small);
+        assert_eq!(app_config.max_tokens, Some(500));
+        assert!(!app_config.tree.enabled);
+
+        let review = apply_profile(merged.clone(), Some("review")).unwrap();
+        assert_eq!(
+            review,
+            table("max_tokens = 1000\nformat = \"markdown\"\n[tree]\nmetric = \"tokens\"\nenabled = true")
+        );
+        let app_config = build(review);
+        assert_eq!(app_config.max_tokens, Some(1000));
+        assert_eq!(app_config.format, OutputFormat::Markdown);
+        assert_eq!(
+            app_config.tree,
+            TreeSettings {
+                enabled: true,
+                excluded: false,
+                metric: TreeMetric::Tokens,
+            }
+        );
+
+        let error = apply_profile(merged, Some("large")).unwrap_err();
+        assert_eq!(error, "Profile 'large' is not defined (available: small, review)");
+    }
+
+    #[test]
+    fn without_a_profile_the_profiles_are_removed() {
+        let merged = table("max_tokens = 1000\n[profile.small]\nmax_tokens = 500");
+        assert_eq!(apply_profile(merged, None).unwrap(), table("max_tokens = 1000"));
+
+        let error = apply_profile(table("max_tokens = 1000"), Some("small")).unwrap_err();
+        assert_eq!(error, "Profile 'small' is not defined (available: none)");
+    }
+
+    #[test]
+    fn profiles_cant_select_profiles_or_set_invalid_settings() {
+        assert!(validate_profile(&table("max_tokens = 500\n[tree]\nenabled = false")).is_ok());
+        assert_eq!(
+            validate_profile(&table("default_profile = \"small\"")).unwrap_err(),
+            "'default_profile' can't be set in a profile"
+        );
+        assert!(validate_profile(&table("profile = {}")).is_err());
+        assert!(validate_profile(&table("max_tokens = \"many\"")).is_err());
+    }
+
+    #[test]
+    fn project_profiles_extend_user_profiles() {
+        let user = TempFile::new(
+            "user.toml",
+            "scan = [\"src\"]\nmax_tokens = 1000\n[profile.small]\nmax_tokens = 500\nformat = \"json\"\n",
+        );
+        let project = TempFile::new("project.toml", "[profile.small]\nmax_tokens = 200\n");
+        let layers = [
+            ConfigLayer {
+                source: ConfigSource::User,
+                path: user.0.clone(),
+            },
+            ConfigLayer {
+                source: ConfigSource::Project,
+                path: project.0.clone(),
+            },
+        ];
+
+        let toml_config = read_toml_config(&layers, Some("small")).unwrap();
+        assert_eq!(toml_config.scan, ["src"]);
+        assert_eq!(toml_config.max_tokens, Some(200));
+        assert_eq!(toml_config.format, OutputFormat::Json);
+
+        let toml_config = read_toml_config(&layers, None).unwrap();
+        assert_eq!(toml_config.max_tokens, Some(1000));
+        assert_eq!(toml_config.format, OutputFormat::PseudoXml);
+    }
+}
@@ -0,0 +1,76 @@
+use std::fmt;
+use std::path::PathBuf;
+
+/// Something a serialization skipped, dropped or worked around instead of failing. Collected in
+/// `Serialized::diagnostics`; the command line prints them to stderr.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Diagnostic {
+    /// A configuration file, scan directory, git repository or file couldn't be used and was
+    /// skipped, or a setting has no effect. The message says which and why.
+    Warning(String),
+    /// A binary file was skipped (see `binary::looks_binary`).
+    SkippedBinary(PathBuf),
+    /// A file over its category's size limit was skipped, with its size in bytes.
+    SkippedOversized(PathBuf, u64),
+    /// A file didn't parse for its skeleton and is sent whole.
+    SkeletonFailed(PathBuf),
+    /// A file was dropped to fit the token budget, with its content tokens.
+    BudgetDropped(PathBuf, usize),
+    /// The token budget dropped files.
+    BudgetSummary {
+        kept_tokens: usize,
+        max_tokens: usize,
+        dropped_files: usize,
+        dropped_tokens: usize,
+    },
+    /// A file exceeds the split limit on its own and can't be split, so its chunk is over the limit.
+    ChunkOverLimit(PathBuf),
+    /// A chunk file left over from an earlier run with more chunks was removed.
+    RemovedStaleChunk(PathBuf),
+}
+
+impl Diagnostic {
+    /// Whether the output may not be what was asked for, as opposed to an expected skip or drop.
+    pub fn is_warning(&self) -> bool {
+        matches!(
+            self,
+            Diagnostic::Warning(_) | Diagnostic::SkeletonFailed(_) | Diagnostic::ChunkOverLimit(_)
+        )
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Diagnostic::Warning(message) => write!(f, "{}", message),
+            Diagnostic::SkippedBinary(path) => write!(f, "Skipped binary file: {}", path.display()),
+            Diagnostic::SkippedOversized(path, size) => {
+                write!(f, "Skipped oversized file: {} ({} bytes)", path.display(), size)
+            }
+            Diagnostic::SkeletonFailed(path) => write!(
+                f,
+                "Failed to parse {} for its skeleton. Including the whole file.",
+                path.display()
+            ),
+            Diagnostic::BudgetDropped(path, tokens) => {
+                write!(f, "Token budget: dropped {} ({} tokens)", path.display(), tokens)
+            }
+            Diagnostic::BudgetSummary {
+                kept_tokens,
+                max_tokens,
+                dropped_files,
+                dropped_tokens,
+            } => write!(
+                f,
+                "Token budget: kept ~{} of {} tokens, dropped {} file(s) totalling {} tokens.",
+                kept_tokens, max_tokens, dropped_files, dropped_tokens
+            ),
+            Diagnostic::ChunkOverLimit(path) => write!(
+                f,
+                "{} exceeds the split limit on its own and can't be split.",
+                path.display()
+            ),
+            Diagnostic::RemovedStaleChunk(path) => write!(f, "Removed stale chunk {}", path.display()),
+        }
+    }
+}
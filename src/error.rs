@@ -0,0 +1,45 @@
+use std::error;
+use std::fmt;
+use std::io;
+
+/// Errors of the library API (see `Serializer`).
+#[derive(Debug)]
+pub enum Error {
+    /// A configuration file couldn't be read or is invalid, or the profile doesn't exist.
+    Config(String),
+    /// Walking the scan roots or reading the selected files failed.
+    Files(String),
+    /// A git repository couldn't be read for the git selection, the history scores or the diff.
+    Git(String),
+    /// The split limit is too small for the framing of a chunk.
+    Split(String),
+    /// Writing the output failed.
+    Output(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Config(message) => write!(f, "Configuration error: {}", message),
+            Error::Files(message) => write!(f, "Error processing files: {}", message),
+            Error::Git(message) => write!(f, "Git error: {}", message),
+            Error::Split(message) => write!(f, "Error splitting output: {}", message),
+            Error::Output(e) => write!(f, "Error writing output: {}", e),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Output(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Output(e)
+    }
+}
@@ -1,24 +1,131 @@
-use crate::config::{AppConfig, DOCS_DESCRIPTION, OTHER_DESCRIPTION, SRC_DESCRIPTION};
+use crate::binary::{self, BinaryAction};
+use crate::config::AppConfig;
+use crate::diagnostic::Diagnostic;
+use crate::error::Error;
+use crate::git;
+use crate::priority;
+use crate::size_limit::{self, OversizedAction};
 
 use std::path::{Path, PathBuf, Component};
 use std::fs;
+use std::time::SystemTime;
+use std::sync::Arc;
 
 use lockfree::stack::Stack;
 use ignore::WalkBuilder;
+use ignore::overrides::{Override, OverrideBuilder};
 use rustc_hash::{FxHashMap, FxHashSet};
 
-#[derive(Debug)]
+/// How a file's content is emitted in the output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ContentMode {
+    /// The whole file is streamed via `sendfile`.
+    Full,
+    /// Only the path (and size) is emitted, e.g. for binary files configured as `list`.
+    ListOnly,
+    /// Only the first `head` and the last `tail` bytes are streamed, separated by a truncation
+    /// marker (see `max_file_size`).
+    Truncated { head: u64, tail: u64 },
+    /// Only `len` bytes starting at `offset` are streamed. Used for the pieces of a file that is
+    /// split across several output chunks (see `split`).
+    Range { offset: u64, len: u64 },
+    /// The content after its transforms (see `Transforms`), written from memory instead of the file.
+    Transformed(Arc<[u8]>),
+}
+
+#[derive(Debug, Clone)]
 pub struct FileData {
     pub relative_path: PathBuf,
     pub absolute_path: PathBuf,
     pub size: u64,
+    /// Number of tokens in the file content, if token counting is enabled.
+    pub tokens: Option<usize>,
+    pub content: ContentMode,
+    /// Format-specific boundary used instead of the default content delimiters, if the content
+    /// collides with them (see `Formatter::boundary_for`).
+    pub boundary: Option<String>,
+    /// Priority score from the `[[priority]]` entries plus the git history score, if enabled.
+    /// Higher scores are placed closer to the task.
+    pub priority: i64,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct CategoryData {
+    pub name: String,
     pub description_text: String,
     pub files: Vec<FileData>,
     pub total_size: u64,
+    /// Output position from the category's configuration (see `priority::sort_by_priority`).
+    pub position: i64,
+}
+
+/// Formats a byte count with a binary unit, e.g. `12.3 KiB`.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+/// Expands the placeholders in the categories' description templates and trims trailing whitespace.
+///
+/// Supported placeholders: `{name}` (category name), `{count}` (number of files), `{size}` (total
+/// size, human-readable), `{bytes}` (total size in bytes) and `{tokens}` (total content tokens).
+/// `{{` and `}}` produce literal braces; unknown placeholders are kept as-is.
+/// Business Logic Constraint: Must run after the token budget was applied, so the values describe
+/// the files that are actually written.
+pub fn expand_descriptions(categories_data: &mut [CategoryData]) {
+    for category_data in categories_data.iter_mut() {
+        let template = std::mem::take(&mut category_data.description_text);
+        let mut expanded = String::with_capacity(template.len());
+        let mut rest = template.as_str();
+
+        while let Some(start) = rest.find(['{', '}']) {
+            expanded.push_str(&rest[..start]);
+            rest = &rest[start..];
+            if rest.starts_with("{{") || rest.starts_with("}}") {
+                expanded.push_str(&rest[..1]);
+                rest = &rest[2..];
+                continue;
+            }
+            let placeholder_end = rest.find('}').map(|end| end + 1).unwrap_or(1);
+            let value = match &rest[..placeholder_end] {
+                "{name}" => Some(category_data.name.clone()),
+                "{count}" => Some(category_data.files.len().to_string()),
+                "{size}" => Some(format_size(category_data.total_size)),
+                "{bytes}" => Some(category_data.total_size.to_string()),
+                "{tokens}" => Some(
+                    category_data
+                        .files
+                        .iter()
+                        .filter_map(|f| f.tokens)
+                        .sum::<usize>()
+                        .to_string(),
+                ),
+                _ => None,
+            };
+            match value {
+                Some(value) => {
+                    expanded.push_str(&value);
+                    rest = &rest[placeholder_end..];
+                }
+                None => {
+                    expanded.push_str(&rest[..1]);
+                    rest = &rest[1..];
+                }
+            }
+        }
+        expanded.push_str(rest);
+        expanded.truncate(expanded.trim_end().len());
+        category_data.description_text = expanded;
+    }
 }
 
 /// Creates a relative path from `base` to `target_path`.
@@ -72,49 +179,41 @@ fn create_relative_path(base: &Path, target_path: &Path) -> Result<PathBuf, Stri
 }
 
 
-#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
-enum FileCategoryType {
-    Docs,
-    Src,
-    Other,
-}
-
-impl FileCategoryType {
-    fn get_description(&self) -> &'static str {
-        match self {
-            FileCategoryType::Docs => DOCS_DESCRIPTION,
-            FileCategoryType::Src => SRC_DESCRIPTION,
-            FileCategoryType::Other => OTHER_DESCRIPTION,
-        }
+/// Compiles the `include` and `exclude` globs into walker overrides rooted at `canonical_working_dir`.
+///
+/// Business Logic Constraint: Globs use gitignore syntax and are matched case-insensitively. Globs
+/// without a slash match at any depth; others are anchored at the working directory (files outside
+/// it, e.g. in `../migrations`, are matched by their absolute path).
+/// Business Logic Constraint: If there are include globs, files matching none of them are left out,
+/// but directories are still descended into. Exclude globs win over include globs. Like ripgrep's
+/// `--glob`, overrides take precedence over ignore files, so an included file is kept even if
+/// `.gitignore` lists it (unless one of its parent directories is ignored).
+fn build_overrides(config: &AppConfig, canonical_working_dir: &Path) -> Result<Override, String> {
+    let mut builder = OverrideBuilder::new(canonical_working_dir);
+    builder
+        .case_insensitive(true)
+        .map_err(|e| format!("Failed to configure include/exclude globs: {}", e))?;
+    for glob in &config.include {
+        builder
+            .add(glob)
+            .map_err(|e| format!("Invalid glob pattern in 'include' -> \"{}\": {}", glob, e))?;
     }
-}
-
-fn categorize_file(relative_path: &Path, config: &AppConfig) -> FileCategoryType {
-    if config.docs.is_match(relative_path) {
-        return FileCategoryType::Docs;
-    }
-    if config.src.is_match(relative_path) {
-        return FileCategoryType::Src;
+    for glob in &config.exclude {
+        builder
+            .add(&format!("!{}", glob))
+            .map_err(|e| format!("Invalid glob pattern in 'exclude' -> \"{}\": {}", glob, e))?;
     }
-    FileCategoryType::Other
+    builder
+        .build()
+        .map_err(|e| format!("Failed to build include/exclude globs: {}", e))
 }
 
-pub fn process_all_categories(
-    config: &AppConfig,
-    working_dir: &Path, 
-) -> Result<Vec<CategoryData>, String> {
-    
-    let categorized_results_stack = Stack::<Result<(FileCategoryType, FileData), String>>::new();
-    
-    let canonical_working_dir = working_dir.canonicalize().map_err(|e| {
-        format!(
-            "Failed to canonicalize working directory {:?}: {}",
-            working_dir, e
-        )
-    })?;
-
-    let mut walk_builder_opt: Option<WalkBuilder> = None;
-    let mut has_valid_scan_paths = false;
+/// Resolves the configured scan directories against `working_dir` and canonicalizes them.
+///
+/// Business Logic Constraint: Scan paths that can't be canonicalized or aren't directories are
+/// skipped with a warning in `diagnostics`.
+pub fn resolve_scan_roots(config: &AppConfig, working_dir: &Path, diagnostics: &mut Vec<Diagnostic>) -> Vec<PathBuf> {
+    let mut scan_roots = Vec::new();
 
     for scan_dir_config_path in &config.scan {
         let current_scan_target_abs = if scan_dir_config_path.is_absolute() {
@@ -126,24 +225,34 @@ pub fn process_all_categories(
         let canonical_scan_root = match fs::canonicalize(&current_scan_target_abs) {
             Ok(p) => p,
             Err(e) => {
-                eprintln!(
-                    "[WARNING] Failed to canonicalize scan directory {:?} (configured as {:?}): {}. Skipping.",
+                diagnostics.push(Diagnostic::Warning(format!(
+                    "Failed to canonicalize scan directory {:?} (configured as {:?}): {}. Skipping.",
                     current_scan_target_abs, scan_dir_config_path, e
-                );
+                )));
                 continue;
             }
         };
 
         if !canonical_scan_root.is_dir() {
-            eprintln!(
-                "[WARNING] Scan path {:?} (configured as {:?}, resolved to {:?}) is not a directory. Skipping.",
+            diagnostics.push(Diagnostic::Warning(format!(
+                "Scan path {:?} (configured as {:?}, resolved to {:?}) is not a directory. Skipping.",
                 scan_dir_config_path, current_scan_target_abs, canonical_scan_root
-            );
+            )));
             continue;
         }
-        
-        has_valid_scan_paths = true;
 
+        scan_roots.push(canonical_scan_root);
+    }
+
+    scan_roots
+}
+
+/// Creates a walker over the scan roots honouring `.gitignore`, `.kekignore` and the given overrides.
+/// Returns `None` if there are no scan roots.
+fn walk_builder(scan_roots: &[PathBuf], overrides: Option<Override>) -> Option<WalkBuilder> {
+    let mut walk_builder_opt: Option<WalkBuilder> = None;
+
+    for canonical_scan_root in scan_roots {
         match walk_builder_opt.as_mut() {
             Some(builder) => {
                 builder.add(canonical_scan_root);
@@ -153,126 +262,377 @@ pub fn process_all_categories(
                 new_builder
                     .standard_filters(true) 
                     .add_custom_ignore_filename(".kekignore");
+                if let Some(overrides) = &overrides {
+                    new_builder.overrides(overrides.clone());
+                }
                 walk_builder_opt = Some(new_builder);
             }
         }
     }
 
-    if !has_valid_scan_paths || walk_builder_opt.is_none() {
-        eprintln!("[INFO] No valid scan directories to process.");
-        return Ok(Vec::new()); // No valid paths to walk, return empty
+    walk_builder_opt
+}
+
+/// Lists every file below the scan roots that isn't ignored by `.gitignore` or `.kekignore`, with
+/// its path relative to `working_dir` and its size, regardless of any other selection setting.
+/// Used to show excluded files in the tree section. Skipped scan paths are reported by
+/// `process_all_categories`, not here.
+pub fn list_scanned_files(config: &AppConfig, working_dir: &Path) -> Result<Vec<(PathBuf, u64)>, Error> {
+    let canonical_working_dir = canonicalize_working_dir(working_dir)?;
+    let scan_roots = resolve_scan_roots(config, working_dir, &mut Vec::new());
+    let Some(walk_builder) = walk_builder(&scan_roots, None) else {
+        return Ok(Vec::new());
+    };
+
+    let mut scanned = Vec::new();
+    for entry in walk_builder.build().flatten() {
+        if !entry.file_type().is_some_and(|ft| ft.is_file()) {
+            continue;
+        }
+        let (Ok(absolute_path), Ok(metadata)) = (fs::canonicalize(entry.path()), entry.metadata()) else {
+            continue;
+        };
+        if let Ok(relative_path) = create_relative_path(&canonical_working_dir, &absolute_path) {
+            scanned.push((relative_path, metadata.len()));
+        }
     }
+    scanned.sort();
+    scanned.dedup();
+    Ok(scanned)
+}
+
+/// The files and directories selected by the walker, with the size and modification time of every
+/// file. Used by `kek watch` to tell whether file system events changed anything in the output.
+#[derive(Debug, Default)]
+pub struct ScanSnapshot {
+    /// Absolute paths, sizes and modification times of the selected files, sorted.
+    pub files: Vec<(PathBuf, u64, Option<SystemTime>)>,
+    /// Absolute paths of the directories the walker descended into, including the scan roots.
+    pub dirs: Vec<PathBuf>,
+}
+
+/// Walks the scan roots with the same ignore files and `include`/`exclude` globs as
+/// `process_all_categories`, without reading any file. Skipped scan paths are reported by
+/// `process_all_categories`, not here.
+pub fn scan_snapshot(config: &AppConfig, working_dir: &Path) -> Result<ScanSnapshot, Error> {
+    let canonical_working_dir = canonicalize_working_dir(working_dir)?;
+    let scan_roots = resolve_scan_roots(config, working_dir, &mut Vec::new());
+    let overrides = build_overrides(config, &canonical_working_dir).map_err(Error::Files)?;
+    let Some(walk_builder) = walk_builder(&scan_roots, Some(overrides)) else {
+        return Ok(ScanSnapshot::default());
+    };
 
-    // We can unwrap here because has_valid_scan_paths ensures walk_builder_opt is Some
-    let walk_builder = walk_builder_opt.unwrap();
+    let mut snapshot = ScanSnapshot::default();
+    for entry in walk_builder.build().flatten() {
+        let Some(file_type) = entry.file_type() else {
+            continue;
+        };
+        if file_type.is_dir() {
+            snapshot.dirs.push(entry.into_path());
+        } else if file_type.is_file()
+            && let Ok(metadata) = entry.metadata()
+        {
+            snapshot
+                .files
+                .push((entry.into_path(), metadata.len(), metadata.modified().ok()));
+        }
+    }
+    snapshot.files.sort();
+    Ok(snapshot)
+}
+
+/// Canonicalizes the directory file paths are made relative to.
+fn canonicalize_working_dir(working_dir: &Path) -> Result<PathBuf, Error> {
+    working_dir.canonicalize().map_err(|e| {
+        Error::Files(format!(
+            "Failed to canonicalize working directory {:?}: {}",
+            working_dir, e
+        ))
+    })
+}
+
+/// Walks the scan roots and collects the selected files by category, in priority order.
+///
+/// Business Logic Constraint: Skipped scan paths, repositories and files are reported in
+/// `diagnostics` instead of failing the walk.
+pub fn process_all_categories(
+    config: &AppConfig,
+    working_dir: &Path,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Result<Vec<CategoryData>, Error> {
+    
+    let categorized_results_stack = Stack::<(usize, FileData)>::new();
+    let diagnostics_stack = Stack::<Diagnostic>::new();
+    
+    let canonical_working_dir = canonicalize_working_dir(working_dir)?;
+
+    let scan_roots = resolve_scan_roots(config, working_dir, diagnostics);
+
+    let overrides = build_overrides(config, &canonical_working_dir).map_err(Error::Files)?;
+    let Some(walk_builder) = walk_builder(&scan_roots, Some(overrides)) else {
+        diagnostics.push(Diagnostic::Warning("No valid scan directories to process.".to_string()));
+        return Ok(Vec::new()); // No valid paths to walk, return empty
+    };
+
+    // Business Logic Constraint: In git selection mode, only files changed according to the
+    // local repositories containing the scan roots are kept.
+    let git_changed_paths = match &config.git_selection {
+        Some(selection) => Some(git::changed_paths(&scan_roots, selection, diagnostics)?),
+        None => None,
+    };
+    let git_history_scores = match &config.git_history {
+        // Business Logic Constraint: Repositories the git selection already warned about aren't
+        // reported twice.
+        Some(settings) if git_changed_paths.is_some() => {
+            git::history_scores(&scan_roots, settings, &mut Vec::new())?
+        }
+        Some(settings) => git::history_scores(&scan_roots, settings, diagnostics)?,
+        None => FxHashMap::default(),
+    };
     
     // References for the parallel closure
     let config_ref = config; 
     let canonical_working_dir_ref = &canonical_working_dir;
     let results_stack_ref = &categorized_results_stack;
+    let diagnostics_stack_ref = &diagnostics_stack;
+    let git_changed_paths_ref = &git_changed_paths;
+    let git_history_scores_ref = &git_history_scores;
 
     walk_builder.build_parallel().run(|| {
         let thread_local_config = config_ref;
         let thread_local_canonical_cwd = canonical_working_dir_ref;
         let thread_local_results_stack = results_stack_ref;
+        let thread_local_diagnostics_stack = diagnostics_stack_ref;
+        let thread_local_formatter = config_ref.format.formatter();
 
         Box::new(move |entry_result| {
             match entry_result {
                 Ok(entry) => {
-                    if entry.file_type().map_or(false, |ft| ft.is_file()) {
+                    if entry.file_type().is_some_and(|ft| ft.is_file()) {
                         let path_from_walker = entry.path();
                         
                         let file_absolute_path_canonical = match fs::canonicalize(path_from_walker) {
                             Ok(p) => p,
                             Err(e) => {
-                                eprintln!(
-                                    "Warning: Failed to canonicalize path for file {:?}: {}. Skipping file.",
+                                thread_local_diagnostics_stack.push(Diagnostic::Warning(format!(
+                                    "Failed to canonicalize path for file {:?}: {}. Skipping file.",
                                     path_from_walker, e
-                                );
+                                )));
                                 return ignore::WalkState::Continue;
                             }
                         };
 
+                        if git_changed_paths_ref
+                            .as_ref()
+                            .is_some_and(|changed_paths| !changed_paths.contains(&file_absolute_path_canonical))
+                        {
+                            return ignore::WalkState::Continue;
+                        }
+
                         let metadata = match entry.metadata() {
                             Ok(md) => md,
                             Err(e) => {
-                                eprintln!(
-                                    "Warning: Failed to get metadata for file {:?}: {}. Skipping file.",
+                                thread_local_diagnostics_stack.push(Diagnostic::Warning(format!(
+                                    "Failed to get metadata for file {:?}: {}. Skipping file.",
                                     file_absolute_path_canonical, e
-                                );
+                                )));
                                 return ignore::WalkState::Continue;
                             }
                         };
-                        let file_size = metadata.len();
+                        let mut file_size = metadata.len();
 
                         let relative_path_to_cwd = match create_relative_path(thread_local_canonical_cwd, &file_absolute_path_canonical) {
                             Ok(path) => path,
                             Err(e_str) => {
-                                eprintln!(
-                                    "Warning: Failed to create relative path for {:?} (base {:?}): {}. Skipping file.",
+                                thread_local_diagnostics_stack.push(Diagnostic::Warning(format!(
+                                    "Failed to create relative path for {:?} (base {:?}): {}. Skipping file.",
                                     file_absolute_path_canonical, thread_local_canonical_cwd, e_str
-                                );
+                                )));
                                 return ignore::WalkState::Continue;
                             }
                         };
                         
-                        let category_type = categorize_file(&relative_path_to_cwd, thread_local_config);
-                        
+                        let category_index = thread_local_config.category_index(&relative_path_to_cwd);
+
+                        // Business Logic Constraint: Files over their category's size limit are
+                        // skipped or truncated to head/tail excerpts, which are then used instead of
+                        // the whole content for everything below.
+                        let size_limit = thread_local_config.size_limit_for(category_index);
+                        let mut truncated = None;
+                        if let Some((limit, action)) = size_limit.filter(|&(limit, _)| file_size > limit) {
+                            match action {
+                                OversizedAction::Skip => {
+                                    thread_local_diagnostics_stack
+                                        .push(Diagnostic::SkippedOversized(relative_path_to_cwd, file_size));
+                                    return ignore::WalkState::Continue;
+                                }
+                                OversizedAction::Truncate => {
+                                    match size_limit::read_excerpts(&file_absolute_path_canonical, file_size, limit) {
+                                        Ok(excerpts) => truncated = Some(excerpts),
+                                        Err(e) => {
+                                            thread_local_diagnostics_stack.push(Diagnostic::Warning(format!(
+                                                "Failed to read file {:?}: {}. Skipping file.",
+                                                file_absolute_path_canonical, e
+                                            )));
+                                            return ignore::WalkState::Continue;
+                                        }
+                                    }
+                                }
+                            }
+                        }
+
+                        // Business Logic Constraint: Only the first `binary::SNIFF_LEN` bytes are read to
+                        // detect binary content, unless token counts are needed or the file is
+                        // transformed. In that case the whole file is read once and used for everything.
+                        // Otherwise, formats writing raw content scan the file for delimiter collisions
+                        // in chunks (see `Formatter::boundary_for_file`).
+                        let transforms = thread_local_config.transforms_for(&relative_path_to_cwd, category_index);
+                        let needs_token_counts = thread_local_config.needs_token_counts();
+                        let needs_full_content = needs_token_counts || !transforms.is_empty();
+                        let read_result = match &truncated {
+                            Some(excerpts) => Ok(excerpts.joined()),
+                            None if needs_full_content => fs::read(&file_absolute_path_canonical),
+                            None => binary::read_sample(&file_absolute_path_canonical),
+                        };
+                        let mut content_or_sample = match read_result {
+                            Ok(bytes) => bytes,
+                            Err(e) => {
+                                thread_local_diagnostics_stack.push(Diagnostic::Warning(format!(
+                                    "Failed to read file {:?}: {}. Skipping file.",
+                                    file_absolute_path_canonical, e
+                                )));
+                                return ignore::WalkState::Continue;
+                            }
+                        };
+
+                        let whole_content = truncated.is_some()
+                            || needs_full_content
+                            || content_or_sample.len() as u64 >= file_size;
+                        let mut content = match &truncated {
+                            Some(excerpts) => ContentMode::Truncated {
+                                head: excerpts.head.len() as u64,
+                                tail: excerpts.tail.len() as u64,
+                            },
+                            None => ContentMode::Full,
+                        };
+                        let is_binary = binary::looks_binary(&content_or_sample);
+                        if is_binary {
+                            match thread_local_config.binary.action_for(&relative_path_to_cwd) {
+                                BinaryAction::Skip => {
+                                    thread_local_diagnostics_stack.push(Diagnostic::SkippedBinary(relative_path_to_cwd));
+                                    return ignore::WalkState::Continue;
+                                }
+                                BinaryAction::List => content = ContentMode::ListOnly,
+                                BinaryAction::Include => {}
+                            }
+                        }
+
+                        // Business Logic Constraint: Transforms only apply to whole text files, not to
+                        // truncated excerpts or binary content. A file the transforms leave unchanged
+                        // keeps being streamed with `sendfile`.
+                        if content == ContentMode::Full
+                            && !transforms.is_empty()
+                            && !is_binary
+                        {
+                            let mut transform_diagnostics = Vec::new();
+                            let transformed =
+                                transforms.apply(&relative_path_to_cwd, &content_or_sample, &mut transform_diagnostics);
+                            for diagnostic in transform_diagnostics {
+                                thread_local_diagnostics_stack.push(diagnostic);
+                            }
+                            if transformed != content_or_sample {
+                                file_size = transformed.len() as u64;
+                                content_or_sample = transformed;
+                                content = ContentMode::Transformed(Arc::from(content_or_sample.as_slice()));
+                            }
+                        }
+
+                        let tokens = match content {
+                            _ if !needs_token_counts => None,
+                            ContentMode::ListOnly => Some(0),
+                            _ => Some(thread_local_config.tokenizer.count_bytes(&content_or_sample)),
+                        };
+
+                        let boundary = match content {
+                            ContentMode::Full | ContentMode::Truncated { .. } | ContentMode::Transformed(_)
+                                if thread_local_formatter.raw_content() && whole_content =>
+                            {
+                                thread_local_formatter.boundary_for(&content_or_sample)
+                            }
+                            ContentMode::Full if thread_local_formatter.raw_content() => {
+                                match thread_local_formatter.boundary_for_file(&file_absolute_path_canonical) {
+                                    Ok(boundary) => boundary,
+                                    Err(e) => {
+                                        thread_local_diagnostics_stack.push(Diagnostic::Warning(format!(
+                                            "Failed to read file {:?}: {}. Skipping file.",
+                                            file_absolute_path_canonical, e
+                                        )));
+                                        return ignore::WalkState::Continue;
+                                    }
+                                }
+                            }
+                            _ => None,
+                        };
+
+                        // Business Logic Constraint: The git history score is added to the rule score.
+                        let priority = thread_local_config.priority.score_for(&relative_path_to_cwd)
+                            + git_history_scores_ref
+                                .get(&file_absolute_path_canonical)
+                                .copied()
+                                .unwrap_or(0);
+
                         let file_data = FileData {
                             relative_path: relative_path_to_cwd,
                             absolute_path: file_absolute_path_canonical,
                             size: file_size,
+                            tokens,
+                            content,
+                            boundary,
+                            priority,
                         };
-                        thread_local_results_stack.push(Ok((category_type, file_data)));
+                        thread_local_results_stack.push((category_index, file_data));
                     }
                 }
                 Err(e) => {
-                    eprintln!("Warning: Error walking directory entry: {}", e);
+                    thread_local_diagnostics_stack
+                        .push(Diagnostic::Warning(format!("Error walking directory entry: {}", e)));
                 }
             }
             ignore::WalkState::Continue
         })
     });
 
-    let mut grouped_files: FxHashMap<FileCategoryType, Vec<FileData>> = FxHashMap::default();
+    // Business Logic Constraint: The walk's diagnostics are sorted, so they don't depend on the
+    // order the threads got to the files in.
+    let mut walk_diagnostics: Vec<Diagnostic> = diagnostics_stack.into_iter().collect();
+    walk_diagnostics.sort();
+    walk_diagnostics.dedup();
+    diagnostics.extend(walk_diagnostics);
+
+    let mut grouped_files: Vec<Vec<FileData>> = config.categories.iter().map(|_| Vec::new()).collect();
     let mut processed_abs_paths: FxHashSet<PathBuf> = FxHashSet::default();
 
-    for result in categorized_results_stack {
-        match result {
-            Ok((category_type, file_data)) => {
-                if processed_abs_paths.insert(file_data.absolute_path.clone()) {
-                    grouped_files
-                        .entry(category_type)
-                        .or_default()
-                        .push(file_data);
-                }
-            }
-            Err(e) => {
-                eprintln!("[ERROR] An error occurred during file data collection: {}", e);
-            }
+    for (category_index, file_data) in categorized_results_stack {
+        if processed_abs_paths.insert(file_data.absolute_path.clone()) {
+            grouped_files[category_index].push(file_data);
         }
     }
 
     let mut all_category_data = Vec::new();
-    let category_types_to_consider = [
-        FileCategoryType::Docs,
-        FileCategoryType::Src,
-        FileCategoryType::Other,
-    ];
-
-    for cat_type in category_types_to_consider.iter() {
-        if let Some(files) = grouped_files.remove(cat_type) {
-            if files.is_empty() { continue; }
-            let total_category_size: u64 = files.iter().map(|f| f.size).sum();
-            all_category_data.push(CategoryData {
-                description_text: cat_type.get_description().to_string(),
-                files,
-                total_size: total_category_size,
-            });
-        }
+
+    for (category, files) in config.categories.iter().zip(grouped_files) {
+        if files.is_empty() { continue; }
+        let total_category_size: u64 = files.iter().map(|f| f.size).sum();
+        all_category_data.push(CategoryData {
+            name: category.name.clone(),
+            description_text: category.description.clone(),
+            files,
+            total_size: total_category_size,
+            position: category.position,
+        });
     }
 
-    all_category_data.sort_by(|a, b| b.total_size.cmp(&a.total_size));
+    priority::sort_by_priority(&mut all_category_data, config.sort);
 
     Ok(all_category_data)
 }
@@ -0,0 +1,941 @@
+use crate::file_processor::{CategoryData, ContentMode, FileData};
+
+use memchr::memmem;
+use rustc_hash::FxHasher;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::File;
+use std::hash::Hasher;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+/// Output formats selectable via `--format` or `format` in kek.toml.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputFormat {
+    /// The original pseudo-XML. File content is written raw, nothing is escaped.
+    #[default]
+    PseudoXml,
+    /// Markdown with fenced code blocks and language tags. File content is written raw.
+    Markdown,
+    /// A JSON document with an array of files. File content is escaped.
+    Json,
+    /// Well-formed XML. File content is wrapped in CDATA sections.
+    Xml,
+}
+
+impl OutputFormat {
+    /// Creates a fresh formatter for one output document.
+    pub fn formatter(&self) -> Box<dyn Formatter> {
+        match self {
+            OutputFormat::PseudoXml => Box::new(PseudoXmlFormatter),
+            OutputFormat::Markdown => Box::new(MarkdownFormatter),
+            OutputFormat::Json => Box::new(JsonFormatter::default()),
+            OutputFormat::Xml => Box::new(XmlFormatter),
+        }
+    }
+
+    /// File extension used for output files in this format, e.g. chunks written by `--split`.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::PseudoXml | OutputFormat::Xml => "xml",
+            OutputFormat::Markdown => "md",
+            OutputFormat::Json => "json",
+        }
+    }
+
+    /// Media type of output in this format, e.g. for responses of `kek serve`.
+    pub fn media_type(&self) -> &'static str {
+        match self {
+            OutputFormat::PseudoXml => "text/plain; charset=utf-8",
+            OutputFormat::Markdown => "text/markdown; charset=utf-8",
+            OutputFormat::Json => "application/json",
+            OutputFormat::Xml => "application/xml",
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "pseudo-xml" => Ok(OutputFormat::PseudoXml),
+            "markdown" | "md" => Ok(OutputFormat::Markdown),
+            "json" => Ok(OutputFormat::Json),
+            "xml" => Ok(OutputFormat::Xml),
+            _ => Err(format!(
+                "Unknown format \"{}\" (expected \"pseudo-xml\", \"markdown\", \"json\" or \"xml\")",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputFormat::PseudoXml => f.write_str("pseudo-xml"),
+            OutputFormat::Markdown => f.write_str("markdown"),
+            OutputFormat::Json => f.write_str("json"),
+            OutputFormat::Xml => f.write_str("xml"),
+        }
+    }
+}
+
+/// Writes the metadata surrounding file contents for one output format.
+///
+/// `output::write_output` calls the methods in document order; `part` and `tree` directly follow
+/// `begin_document`. For every file either
+/// `list_only` or the `begin_content` / content / `end_content` sequence is called between
+/// `begin_file` and `end_file`. For truncated files, the content consists of the head excerpt,
+/// `truncated` and the tail excerpt. For pieces of a split file (`ContentMode::Range`),
+/// `begin_content` must state which byte range of the file follows.
+///
+/// Business Logic Constraint: If `raw_content` returns true, file content is streamed as-is
+/// (via `sendfile`) and `write_content` is never called. Otherwise the content is read into memory
+/// and passed to `write_content`, which must escape it.
+/// Business Logic Constraint: Formats with raw content must pick a boundary in `boundary_for` when
+/// the content collides with their delimiters, and the same one in `boundary_for_file`, which is
+/// used for files that aren't read into memory. The boundary is stored in `FileData::boundary`.
+pub trait Formatter: Send {
+    fn begin_document(&mut self, _w: &mut dyn Write, _categories: &[CategoryData]) -> io::Result<()> {
+        Ok(())
+    }
+    /// Writes the header of chunk `index` (1-based) of `count` when the output is split.
+    fn part(&mut self, w: &mut dyn Write, index: usize, count: usize) -> io::Result<()>;
+    fn tree(&mut self, w: &mut dyn Write, tree: &str) -> io::Result<()>;
+    fn begin_category(&mut self, w: &mut dyn Write, category: &CategoryData) -> io::Result<()>;
+    fn begin_file(&mut self, w: &mut dyn Write, category: &CategoryData, file: &FileData) -> io::Result<()>;
+    fn list_only(&mut self, w: &mut dyn Write, file: &FileData) -> io::Result<()>;
+    fn begin_content(&mut self, w: &mut dyn Write, file: &FileData) -> io::Result<()>;
+    fn raw_content(&self) -> bool;
+    /// Returns a boundary that can't be confused with `content`, or `None` if the default
+    /// delimiters are safe.
+    fn boundary_for(&self, _content: &[u8]) -> Option<String> {
+        None
+    }
+    /// Returns the boundary `boundary_for` would return for the content of the file at `path`,
+    /// reading it in chunks of `SCAN_CHUNK` bytes instead of into memory.
+    fn boundary_for_file(&self, _path: &Path) -> io::Result<Option<String>> {
+        Ok(None)
+    }
+    fn write_content(&mut self, _w: &mut dyn Write, _content: &[u8]) -> io::Result<()> {
+        unreachable!("write_content is only called for formats without raw content")
+    }
+    /// Writes the marker replacing the `omitted_bytes` bytes between the head and tail excerpts.
+    /// The head excerpt doesn't end with a line break, the tail excerpt starts at a new line.
+    fn truncated(&mut self, w: &mut dyn Write, file: &FileData, omitted_bytes: u64) -> io::Result<()>;
+    fn end_content(&mut self, w: &mut dyn Write, file: &FileData) -> io::Result<()>;
+    fn end_file(&mut self, w: &mut dyn Write, file: &FileData) -> io::Result<()>;
+    fn end_category(&mut self, w: &mut dyn Write, category: &CategoryData) -> io::Result<()>;
+    fn diff(&mut self, w: &mut dyn Write, diff: &str) -> io::Result<()>;
+    fn task(&mut self, w: &mut dyn Write, task: &str) -> io::Result<()>;
+    fn end_document(&mut self, _w: &mut dyn Write) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Returns whether `needle` occurs anywhere in `haystack`.
+fn contains_bytes(haystack: &[u8], needle: &[u8]) -> bool {
+    memmem::find(haystack, needle).is_some()
+}
+
+/// Size of the chunks files are read in by `Formatter::boundary_for_file`.
+pub const SCAN_CHUNK: usize = 64 * 1024;
+
+/// Reads the file at `path` in chunks of `SCAN_CHUNK` bytes (only the last one is shorter) and
+/// calls `visit` with each chunk, preceded by up to `overlap` bytes of the previous ones so
+/// matches spanning two chunks are seen. The second argument of `visit` is where the chunk starts.
+/// Stops early if `visit` returns false.
+fn scan_file(path: &Path, overlap: usize, mut visit: impl FnMut(&[u8], usize) -> bool) -> io::Result<()> {
+    let mut file = File::open(path)?;
+    let mut window = Vec::with_capacity(overlap + SCAN_CHUNK);
+    loop {
+        let kept = window.len().min(overlap);
+        window.drain(..window.len() - kept);
+        let read = (&mut file).take(SCAN_CHUNK as u64).read_to_end(&mut window)?;
+        if read == 0 || !visit(&window, kept) {
+            return Ok(());
+        }
+    }
+}
+
+/// Returns whether any of `needles` occurs in the file at `path`, reading it in chunks.
+fn file_contains(path: &Path, needles: &[&[u8]]) -> io::Result<bool> {
+    let overlap = needles.iter().map(|needle| needle.len()).max().unwrap_or(1) - 1;
+    let mut found = false;
+    scan_file(path, overlap, |window, _| {
+        found = needles.iter().any(|needle| contains_bytes(window, needle));
+        !found
+    })?;
+    Ok(found)
+}
+
+/// Hashes content for `content_nonce`, fed in chunks of `SCAN_CHUNK` bytes, so content hashed from
+/// memory and from a file read in chunks gets the same nonce.
+#[derive(Default, Clone)]
+struct NonceHasher(FxHasher);
+
+impl NonceHasher {
+    fn of(content: &[u8]) -> Self {
+        let mut hasher = NonceHasher::default();
+        for chunk in content.chunks(SCAN_CHUNK) {
+            hasher.write_chunk(chunk);
+        }
+        hasher
+    }
+
+    fn write_chunk(&mut self, chunk: &[u8]) {
+        self.0.write(chunk);
+    }
+
+    /// Derives an 8 hex digit nonce from the content, so the same content always gets the same
+    /// boundary (keeps the output reproducible). `attempt` is mixed in to resolve the (unlikely)
+    /// case where the nonce itself occurs in the content.
+    fn nonce(&self, attempt: u32) -> String {
+        let mut hasher = self.0.clone();
+        hasher.write_u32(attempt);
+        format!("{:08x}", hasher.finish() as u32)
+    }
+}
+
+/// Returns the nonce of `content` for `attempt` (see `NonceHasher::nonce`).
+fn content_nonce(content: &[u8], attempt: u32) -> String {
+    NonceHasher::of(content).nonce(attempt)
+}
+
+// --- Pseudo-XML ---
+
+/// Business Logic Constraint: Output is pseudo-XML, not strictly valid XML. No escaping is performed.
+/// Business Logic Constraint: If the content contains `</content` or `</file`, it is wrapped in
+/// `<content-{nonce}>` ... `</content-{nonce}>` instead, with a nonce that doesn't occur in it.
+struct PseudoXmlFormatter;
+
+impl Formatter for PseudoXmlFormatter {
+    fn part(&mut self, w: &mut dyn Write, index: usize, count: usize) -> io::Result<()> {
+        writeln!(w, "<part>{} of {}</part>", index, count)
+    }
+
+    fn tree(&mut self, w: &mut dyn Write, tree: &str) -> io::Result<()> {
+        writeln!(w, "<tree>")?;
+        writeln!(w, "{}", tree.trim_end_matches('\n'))?;
+        writeln!(w, "</tree>")
+    }
+
+    fn begin_category(&mut self, w: &mut dyn Write, category: &CategoryData) -> io::Result<()> {
+        writeln!(w, "<category>")?;
+        writeln!(w, "<description>")?;
+        writeln!(w, "{}", category.description_text)?;
+        writeln!(w, "</description>")?;
+        writeln!(w, "<files>")
+    }
+
+    fn begin_file(&mut self, w: &mut dyn Write, _category: &CategoryData, file: &FileData) -> io::Result<()> {
+        writeln!(w, "<file>")?;
+        writeln!(w, "<path>")?;
+        writeln!(w, "{}", file.relative_path.display())?;
+        writeln!(w, "</path>")
+    }
+
+    fn list_only(&mut self, w: &mut dyn Write, file: &FileData) -> io::Result<()> {
+        writeln!(w, "<binary bytes=\"{}\"/>", file.size)
+    }
+
+    fn begin_content(&mut self, w: &mut dyn Write, file: &FileData) -> io::Result<()> {
+        if let ContentMode::Range { offset, len } = file.content {
+            writeln!(w, "<range start=\"{}\" end=\"{}\" bytes=\"{}\"/>", offset, offset + len, file.size)?;
+        }
+        match &file.boundary {
+            Some(nonce) => writeln!(w, "<content-{}>", nonce),
+            None => writeln!(w, "<content>"),
+        }
+    }
+
+    fn raw_content(&self) -> bool {
+        true
+    }
+
+    fn boundary_for(&self, content: &[u8]) -> Option<String> {
+        if !contains_bytes(content, b"</content") && !contains_bytes(content, b"</file") {
+            return None;
+        }
+        let hasher = NonceHasher::of(content);
+        (0..)
+            .map(|attempt| hasher.nonce(attempt))
+            .find(|nonce| !contains_bytes(content, format!("content-{}", nonce).as_bytes()))
+    }
+
+    /// Business Logic Constraint: The file is read once to look for the delimiters while hashing
+    /// it. Only files containing them are read again, to check that the nonce doesn't occur.
+    fn boundary_for_file(&self, path: &Path) -> io::Result<Option<String>> {
+        let mut hasher = NonceHasher::default();
+        let mut collides = false;
+        scan_file(path, "</content".len() - 1, |window, chunk_start| {
+            hasher.write_chunk(&window[chunk_start..]);
+            collides = collides || contains_bytes(window, b"</content") || contains_bytes(window, b"</file");
+            true
+        })?;
+        if !collides {
+            return Ok(None);
+        }
+        for attempt in 0.. {
+            let nonce = hasher.nonce(attempt);
+            if !file_contains(path, &[format!("content-{}", nonce).as_bytes()])? {
+                return Ok(Some(nonce));
+            }
+        }
+        unreachable!("some nonce doesn't occur in the file")
+    }
+
+    fn truncated(&mut self, w: &mut dyn Write, _file: &FileData, omitted_bytes: u64) -> io::Result<()> {
+        writeln!(w)?;
+        writeln!(w, "<truncated bytes=\"{}\"/>", omitted_bytes)
+    }
+
+    fn end_content(&mut self, w: &mut dyn Write, file: &FileData) -> io::Result<()> {
+        // Newline after the file content, which may not end with one.
+        writeln!(w)?;
+        match &file.boundary {
+            Some(nonce) => writeln!(w, "</content-{}>", nonce),
+            None => writeln!(w, "</content>"),
+        }
+    }
+
+    fn end_file(&mut self, w: &mut dyn Write, _file: &FileData) -> io::Result<()> {
+        writeln!(w, "</file>")
+    }
+
+    fn end_category(&mut self, w: &mut dyn Write, _category: &CategoryData) -> io::Result<()> {
+        writeln!(w, "</files>")?;
+        writeln!(w, "</category>")
+    }
+
+    fn diff(&mut self, w: &mut dyn Write, diff: &str) -> io::Result<()> {
+        // Business Logic Constraint: A diff containing `</diff` gets a `<diff-{nonce}>` boundary.
+        let tag = if contains_bytes(diff.as_bytes(), b"</diff") {
+            let nonce = (0..)
+                .map(|attempt| content_nonce(diff.as_bytes(), attempt))
+                .find(|nonce| !contains_bytes(diff.as_bytes(), format!("diff-{}", nonce).as_bytes()))
+                .unwrap_or_default();
+            format!("diff-{}", nonce)
+        } else {
+            "diff".to_string()
+        };
+        writeln!(w, "<{}>", tag)?;
+        writeln!(w, "{}", diff.trim_end_matches('\n'))?;
+        writeln!(w, "</{}>", tag)
+    }
+
+    fn task(&mut self, w: &mut dyn Write, task: &str) -> io::Result<()> {
+        writeln!(w, "<task>{}</task>", task)
+    }
+}
+
+// --- Markdown ---
+
+/// Business Logic Constraint: Code fences are three backticks, unless the content has a line
+/// starting with a run of three or more backticks, in which case the fence is one backtick longer
+/// than the longest such run (CommonMark only closes a fence with a run at least as long).
+struct MarkdownFormatter;
+
+/// Returns the length of the longest run of backticks at the start of a line (after up to three
+/// spaces of indentation).
+fn longest_backtick_fence(content: &[u8]) -> usize {
+    let mut scan = FenceScan::default();
+    scan.feed(content);
+    scan.finish()
+}
+
+/// Incremental `longest_backtick_fence` over content fed in chunks.
+#[derive(Default)]
+struct FenceScan {
+    /// Spaces at the start of the current line.
+    indent: usize,
+    /// Backticks of the current run at the start of the line.
+    backticks: usize,
+    /// Whether the current line can't start a fence anymore.
+    rest_of_line: bool,
+    longest: usize,
+}
+
+impl FenceScan {
+    fn feed(&mut self, chunk: &[u8]) {
+        for &b in chunk {
+            if b == b'\n' {
+                self.end_run();
+                self.indent = 0;
+                self.rest_of_line = false;
+            } else if self.rest_of_line {
+                continue;
+            } else if b == b'`' {
+                self.backticks += 1;
+            } else if b == b' ' && self.backticks == 0 && self.indent < 3 {
+                self.indent += 1;
+            } else {
+                self.end_run();
+                self.rest_of_line = true;
+            }
+        }
+    }
+
+    fn end_run(&mut self) {
+        self.longest = self.longest.max(self.backticks);
+        self.backticks = 0;
+    }
+
+    fn finish(mut self) -> usize {
+        self.end_run();
+        self.longest
+    }
+}
+
+/// Returns the fenced code block language tag for a file, based on its extension or name.
+fn markdown_language(path: &Path) -> &'static str {
+    let file_name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    match file_name.as_str() {
+        "makefile" | "gnumakefile" => return "makefile",
+        "dockerfile" | "containerfile" => return "dockerfile",
+        "cmakelists.txt" => return "cmake",
+        _ => {}
+    }
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "rs" => "rust",
+        "py" => "python",
+        "js" | "mjs" | "cjs" => "javascript",
+        "jsx" => "jsx",
+        "ts" | "mts" | "cts" => "typescript",
+        "tsx" => "tsx",
+        "java" => "java",
+        "c" | "h" => "c",
+        "cpp" | "cc" | "cxx" | "hpp" | "hh" => "cpp",
+        "cs" => "csharp",
+        "go" => "go",
+        "rb" => "ruby",
+        "php" => "php",
+        "swift" => "swift",
+        "kt" | "kts" => "kotlin",
+        "scala" => "scala",
+        "dart" => "dart",
+        "lua" => "lua",
+        "pl" => "perl",
+        "r" => "r",
+        "hs" => "haskell",
+        "ex" | "exs" => "elixir",
+        "erl" | "hrl" => "erlang",
+        "clj" | "cljs" | "cljc" | "edn" => "clojure",
+        "zig" => "zig",
+        "nim" => "nim",
+        "sh" | "bash" => "bash",
+        "zsh" => "zsh",
+        "fish" => "fish",
+        "ps1" => "powershell",
+        "bat" | "cmd" => "batch",
+        "sql" | "ddl" | "dml" | "psql" | "plsql" => "sql",
+        "json" | "ipynb" => "json",
+        "yaml" | "yml" => "yaml",
+        "toml" => "toml",
+        "ini" | "cfg" | "conf" => "ini",
+        "xml" | "xsd" | "xsl" | "xslt" | "wsdl" | "svg" | "plist" => "xml",
+        "html" | "htm" | "xhtml" => "html",
+        "css" => "css",
+        "scss" => "scss",
+        "less" => "less",
+        "vue" => "vue",
+        "svelte" => "svelte",
+        "graphql" | "gql" => "graphql",
+        "proto" => "protobuf",
+        "tf" | "tfvars" | "hcl" => "hcl",
+        "md" | "mdx" => "markdown",
+        "rst" => "rst",
+        "tex" => "latex",
+        "diff" | "patch" => "diff",
+        "glsl" | "vert" | "frag" | "geom" | "comp" | "tesc" | "tese" => "glsl",
+        "hlsl" => "hlsl",
+        "sol" => "solidity",
+        "mk" => "makefile",
+        "cmake" => "cmake",
+        "gradle" => "groovy",
+        "groovy" => "groovy",
+        _ => "",
+    }
+}
+
+impl Formatter for MarkdownFormatter {
+    fn part(&mut self, w: &mut dyn Write, index: usize, count: usize) -> io::Result<()> {
+        writeln!(w, "# Part {} of {}", index, count)?;
+        writeln!(w)
+    }
+
+    fn tree(&mut self, w: &mut dyn Write, tree: &str) -> io::Result<()> {
+        let fence = "`".repeat(longest_backtick_fence(tree.as_bytes()).max(2) + 1);
+        writeln!(w, "## Tree")?;
+        writeln!(w)?;
+        writeln!(w, "{}text", fence)?;
+        writeln!(w, "{}", tree.trim_end_matches('\n'))?;
+        writeln!(w, "{}", fence)?;
+        writeln!(w)
+    }
+
+    fn begin_category(&mut self, w: &mut dyn Write, category: &CategoryData) -> io::Result<()> {
+        writeln!(w, "## {}", category.name)?;
+        writeln!(w)?;
+        writeln!(w, "{}", category.description_text)?;
+        writeln!(w)
+    }
+
+    fn begin_file(&mut self, w: &mut dyn Write, _category: &CategoryData, file: &FileData) -> io::Result<()> {
+        writeln!(w, "### `{}`", file.relative_path.display())?;
+        writeln!(w)
+    }
+
+    fn list_only(&mut self, w: &mut dyn Write, file: &FileData) -> io::Result<()> {
+        writeln!(w, "_Binary file ({} bytes), content omitted._", file.size)
+    }
+
+    fn begin_content(&mut self, w: &mut dyn Write, file: &FileData) -> io::Result<()> {
+        if let ContentMode::Range { offset, len } = file.content {
+            writeln!(w, "_Bytes {}..{} of {}._", offset, offset + len, file.size)?;
+            writeln!(w)?;
+        }
+        let fence = file.boundary.as_deref().unwrap_or("```");
+        writeln!(w, "{}{}", fence, markdown_language(&file.relative_path))
+    }
+
+    fn raw_content(&self) -> bool {
+        true
+    }
+
+    fn boundary_for(&self, content: &[u8]) -> Option<String> {
+        let longest = longest_backtick_fence(content);
+        (longest >= 3).then(|| "`".repeat(longest + 1))
+    }
+
+    fn boundary_for_file(&self, path: &Path) -> io::Result<Option<String>> {
+        let mut scan = FenceScan::default();
+        scan_file(path, 0, |chunk, _| {
+            scan.feed(chunk);
+            true
+        })?;
+        let longest = scan.finish();
+        Ok((longest >= 3).then(|| "`".repeat(longest + 1)))
+    }
+
+    fn truncated(&mut self, w: &mut dyn Write, _file: &FileData, omitted_bytes: u64) -> io::Result<()> {
+        writeln!(w)?;
+        writeln!(w, "<truncated bytes=\"{}\"/>", omitted_bytes)
+    }
+
+    fn end_content(&mut self, w: &mut dyn Write, file: &FileData) -> io::Result<()> {
+        writeln!(w)?;
+        writeln!(w, "{}", file.boundary.as_deref().unwrap_or("```"))
+    }
+
+    fn end_file(&mut self, w: &mut dyn Write, _file: &FileData) -> io::Result<()> {
+        writeln!(w)
+    }
+
+    fn end_category(&mut self, _w: &mut dyn Write, _category: &CategoryData) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn diff(&mut self, w: &mut dyn Write, diff: &str) -> io::Result<()> {
+        let fence = "`".repeat(longest_backtick_fence(diff.as_bytes()).max(2) + 1);
+        writeln!(w, "## Diff")?;
+        writeln!(w)?;
+        writeln!(w, "{}diff", fence)?;
+        writeln!(w, "{}", diff.trim_end_matches('\n'))?;
+        writeln!(w, "{}", fence)?;
+        writeln!(w)
+    }
+
+    fn task(&mut self, w: &mut dyn Write, task: &str) -> io::Result<()> {
+        writeln!(w, "## Task")?;
+        writeln!(w)?;
+        writeln!(w, "{}", task)
+    }
+}
+
+// --- JSON ---
+
+/// Writes `{"categories": [...], "files": [{"path", "category", "content"}, ...], "task": ...}`
+/// with one file object per line. `"part"` and `"tree"` keys precede `"files"` if written, and a
+/// `"diff"` key precedes `"task"` if a diff is written. Pieces of split files have `"range"`
+/// (start and end offsets) and `"bytes"` (the file size).
+///
+/// Business Logic Constraint: File content that is not valid UTF-8 is converted lossily.
+/// List-only files have `"content": null` and `"binary": true`. The content of truncated files
+/// contains a `<truncated bytes="..."/>` line and is followed by `"truncated_bytes"`.
+#[derive(Default)]
+struct JsonFormatter {
+    opened_files: bool,
+    wrote_file: bool,
+    diff: Option<String>,
+    task: Option<String>,
+}
+
+impl JsonFormatter {
+    /// Opens the `"files"` array, which follows the optional `"tree"` key.
+    fn open_files(&mut self, w: &mut dyn Write) -> io::Result<()> {
+        if !self.opened_files {
+            self.opened_files = true;
+            write!(w, "\"files\": [")?;
+        }
+        Ok(())
+    }
+}
+
+fn write_json_string(w: &mut dyn Write, value: &str) -> io::Result<()> {
+    serde_json::to_writer(w, value).map_err(io::Error::from)
+}
+
+/// Writes `value` escaped for use inside a JSON string, without the surrounding quotes.
+fn write_json_string_contents(w: &mut dyn Write, value: &str) -> io::Result<()> {
+    let quoted = serde_json::to_string(value).map_err(io::Error::from)?;
+    w.write_all(&quoted.as_bytes()[1..quoted.len() - 1])
+}
+
+impl Formatter for JsonFormatter {
+    fn begin_document(&mut self, w: &mut dyn Write, categories: &[CategoryData]) -> io::Result<()> {
+        writeln!(w, "{{")?;
+        writeln!(w, "\"categories\": [")?;
+        for (index, category) in categories.iter().enumerate() {
+            write!(w, "{{\"name\": ")?;
+            write_json_string(w, &category.name)?;
+            write!(w, ", \"description\": ")?;
+            write_json_string(w, &category.description_text)?;
+            writeln!(w, "}}{}", if index + 1 < categories.len() { "," } else { "" })?;
+        }
+        writeln!(w, "],")
+    }
+
+    fn part(&mut self, w: &mut dyn Write, index: usize, count: usize) -> io::Result<()> {
+        writeln!(w, "\"part\": {{\"index\": {}, \"count\": {}}},", index, count)
+    }
+
+    fn tree(&mut self, w: &mut dyn Write, tree: &str) -> io::Result<()> {
+        write!(w, "\"tree\": ")?;
+        write_json_string(w, tree)?;
+        writeln!(w, ",")
+    }
+
+    fn begin_category(&mut self, w: &mut dyn Write, _category: &CategoryData) -> io::Result<()> {
+        self.open_files(w)
+    }
+
+    fn begin_file(&mut self, w: &mut dyn Write, category: &CategoryData, file: &FileData) -> io::Result<()> {
+        if self.wrote_file {
+            write!(w, ",")?;
+        }
+        self.wrote_file = true;
+        write!(w, "\n{{\"path\": ")?;
+        write_json_string(w, &file.relative_path.to_string_lossy())?;
+        write!(w, ", \"category\": ")?;
+        write_json_string(w, &category.name)
+    }
+
+    fn list_only(&mut self, w: &mut dyn Write, file: &FileData) -> io::Result<()> {
+        write!(w, ", \"bytes\": {}, \"binary\": true, \"content\": null", file.size)
+    }
+
+    fn begin_content(&mut self, w: &mut dyn Write, file: &FileData) -> io::Result<()> {
+        if let ContentMode::Range { offset, len } = file.content {
+            write!(w, ", \"range\": [{}, {}], \"bytes\": {}", offset, offset + len, file.size)?;
+        }
+        write!(w, ", \"content\": \"")
+    }
+
+    fn raw_content(&self) -> bool {
+        false
+    }
+
+    fn write_content(&mut self, w: &mut dyn Write, content: &[u8]) -> io::Result<()> {
+        write_json_string_contents(w, &String::from_utf8_lossy(content))
+    }
+
+    fn truncated(&mut self, w: &mut dyn Write, _file: &FileData, omitted_bytes: u64) -> io::Result<()> {
+        write_json_string_contents(w, &format!("\n<truncated bytes=\"{}\"/>\n", omitted_bytes))
+    }
+
+    fn end_content(&mut self, w: &mut dyn Write, file: &FileData) -> io::Result<()> {
+        write!(w, "\"")?;
+        if let ContentMode::Truncated { head, tail } = file.content {
+            write!(w, ", \"truncated_bytes\": {}", file.size - head - tail)?;
+        }
+        Ok(())
+    }
+
+    fn end_file(&mut self, w: &mut dyn Write, _file: &FileData) -> io::Result<()> {
+        write!(w, "}}")
+    }
+
+    fn end_category(&mut self, _w: &mut dyn Write, _category: &CategoryData) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn diff(&mut self, _w: &mut dyn Write, diff: &str) -> io::Result<()> {
+        // The diff is a top-level key after the files array, so it is written by `end_document`.
+        self.diff = Some(diff.to_string());
+        Ok(())
+    }
+
+    fn task(&mut self, _w: &mut dyn Write, task: &str) -> io::Result<()> {
+        // The task is a top-level key after the files array, so it is written by `end_document`.
+        self.task = Some(task.to_string());
+        Ok(())
+    }
+
+    fn end_document(&mut self, w: &mut dyn Write) -> io::Result<()> {
+        self.open_files(w)?;
+        writeln!(w, "\n],")?;
+        if let Some(diff) = &self.diff {
+            write!(w, "\"diff\": ")?;
+            write_json_string(w, diff)?;
+            writeln!(w, ",")?;
+        }
+        write!(w, "\"task\": ")?;
+        match &self.task {
+            Some(task) => write_json_string(w, task)?,
+            None => write!(w, "null")?,
+        }
+        writeln!(w, "\n}}")
+    }
+}
+
+// --- XML ---
+
+/// Well-formed XML. Text and attribute values are escaped; file content is wrapped in CDATA.
+///
+/// Business Logic Constraint: Characters that are not allowed in XML 1.0 (most control characters)
+/// and invalid UTF-8 sequences are replaced with U+FFFD. `]]>` inside content is split across two
+/// CDATA sections.
+struct XmlFormatter;
+
+/// Returns whether `c` is allowed in an XML 1.0 document.
+fn is_xml_char(c: char) -> bool {
+    matches!(c, '\t' | '\n' | '\r' | '\u{20}'..='\u{D7FF}' | '\u{E000}'..='\u{FFFD}' | '\u{10000}'..)
+}
+
+/// Escapes text for use in XML element content or attribute values.
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c if is_xml_char(c) => escaped.push(c),
+            _ => escaped.push('\u{FFFD}'),
+        }
+    }
+    escaped
+}
+
+impl Formatter for XmlFormatter {
+    fn begin_document(&mut self, w: &mut dyn Write, _categories: &[CategoryData]) -> io::Result<()> {
+        writeln!(w, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+        writeln!(w, "<repository>")
+    }
+
+    fn part(&mut self, w: &mut dyn Write, index: usize, count: usize) -> io::Result<()> {
+        writeln!(w, "<part index=\"{}\" count=\"{}\">{} of {}</part>", index, count, index, count)
+    }
+
+    fn tree(&mut self, w: &mut dyn Write, tree: &str) -> io::Result<()> {
+        writeln!(w, "<tree>{}</tree>", escape_xml(tree.trim_end_matches('\n')))
+    }
+
+    fn begin_category(&mut self, w: &mut dyn Write, category: &CategoryData) -> io::Result<()> {
+        writeln!(w, "<category name=\"{}\">", escape_xml(&category.name))?;
+        writeln!(w, "<description>{}</description>", escape_xml(&category.description_text))?;
+        writeln!(w, "<files>")
+    }
+
+    fn begin_file(&mut self, w: &mut dyn Write, _category: &CategoryData, file: &FileData) -> io::Result<()> {
+        writeln!(w, "<file>")?;
+        writeln!(w, "<path>{}</path>", escape_xml(&file.relative_path.to_string_lossy()))
+    }
+
+    fn list_only(&mut self, w: &mut dyn Write, file: &FileData) -> io::Result<()> {
+        writeln!(w, "<binary bytes=\"{}\"/>", file.size)
+    }
+
+    fn begin_content(&mut self, w: &mut dyn Write, file: &FileData) -> io::Result<()> {
+        if let ContentMode::Range { offset, len } = file.content {
+            writeln!(w, "<range start=\"{}\" end=\"{}\" bytes=\"{}\"/>", offset, offset + len, file.size)?;
+        }
+        write!(w, "<content><![CDATA[")
+    }
+
+    fn raw_content(&self) -> bool {
+        false
+    }
+
+    fn write_content(&mut self, w: &mut dyn Write, content: &[u8]) -> io::Result<()> {
+        let text = String::from_utf8_lossy(content);
+        let sanitized: String = text
+            .chars()
+            .map(|c| if is_xml_char(c) { c } else { '\u{FFFD}' })
+            .collect();
+        w.write_all(sanitized.replace("]]>", "]]]]><![CDATA[>").as_bytes())
+    }
+
+    fn truncated(&mut self, w: &mut dyn Write, _file: &FileData, omitted_bytes: u64) -> io::Result<()> {
+        write!(w, "]]>\n<truncated bytes=\"{}\"/>\n<![CDATA[", omitted_bytes)
+    }
+
+    fn end_content(&mut self, w: &mut dyn Write, _file: &FileData) -> io::Result<()> {
+        writeln!(w, "]]></content>")
+    }
+
+    fn end_file(&mut self, w: &mut dyn Write, _file: &FileData) -> io::Result<()> {
+        writeln!(w, "</file>")
+    }
+
+    fn end_category(&mut self, w: &mut dyn Write, _category: &CategoryData) -> io::Result<()> {
+        writeln!(w, "</files>")?;
+        writeln!(w, "</category>")
+    }
+
+    fn diff(&mut self, w: &mut dyn Write, diff: &str) -> io::Result<()> {
+        write!(w, "<diff><![CDATA[")?;
+        self.write_content(w, diff.as_bytes())?;
+        writeln!(w, "]]></diff>")
+    }
+
+    fn task(&mut self, w: &mut dyn Write, task: &str) -> io::Result<()> {
+        writeln!(w, "<task>{}</task>", escape_xml(task))
+    }
+
+    fn end_document(&mut self, w: &mut dyn Write) -> io::Result<()> {
+        writeln!(w, "</repository>")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    /// Writes `content` to a file in the temporary directory, removed when dropped.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, content: &[u8]) -> TempFile {
+            let path = std::env::temp_dir().join(format!("kek-format-{}-{}", std::process::id(), name));
+            fs::write(&path, content).unwrap();
+            TempFile(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn boundaries(format: OutputFormat, name: &str, content: &[u8]) -> Option<String> {
+        let formatter = format.formatter();
+        let in_memory = formatter.boundary_for(content);
+        let file = TempFile::new(name, content);
+        assert_eq!(formatter.boundary_for_file(&file.0).unwrap(), in_memory);
+        in_memory
+    }
+
+    #[test]
+    fn pseudo_xml_needs_no_boundary_without_delimiters() {
+        assert_eq!(boundaries(OutputFormat::PseudoXml, "plain", b"<content>fn main() {}</x>"), None);
+        assert_eq!(boundaries(OutputFormat::PseudoXml, "empty", b""), None);
+    }
+
+    #[test]
+    fn pseudo_xml_nonce_for_delimiters() {
+        for (name, content) in [("content", &b"a </content> b"[..]), ("file", b"</file>")] {
+            let nonce = boundaries(OutputFormat::PseudoXml, name, content).expect("a boundary");
+            assert_eq!(nonce.len(), 8);
+            assert!(nonce.bytes().all(|b| b.is_ascii_hexdigit()));
+            assert_eq!(nonce, content_nonce(content, 0));
+        }
+    }
+
+    #[test]
+    fn file_contains_finds_needles_across_chunks() {
+        let mut content = vec![b'x'; SCAN_CHUNK * 2 - 5];
+        content.extend_from_slice(b"content-0123abcd");
+        let file = TempFile::new("contains", &content);
+        assert!(file_contains(&file.0, &[b"content-0123abcd"]).unwrap());
+        assert!(file_contains(&file.0, &[b"nope", b"0123"]).unwrap());
+        assert!(!file_contains(&file.0, &[b"content-0123abce"]).unwrap());
+    }
+
+    #[test]
+    fn pseudo_xml_delimiter_across_chunks() {
+        let mut content = vec![b'x'; SCAN_CHUNK - 4];
+        content.extend_from_slice(b"</content>");
+        content.extend(vec![b'y'; SCAN_CHUNK]);
+        assert!(boundaries(OutputFormat::PseudoXml, "across", &content).is_some());
+    }
+
+    #[test]
+    fn markdown_fence_longer_than_content_fences() {
+        assert_eq!(boundaries(OutputFormat::Markdown, "none", b"no fences, `inline` only"), None);
+        assert_eq!(boundaries(OutputFormat::Markdown, "three", b"text\n```rust\nx\n```\n").as_deref(), Some("````"));
+        assert_eq!(boundaries(OutputFormat::Markdown, "indented", b"   `````\n").as_deref(), Some("``````"));
+        // Four spaces of indentation make a code block, not a fence.
+        assert_eq!(boundaries(OutputFormat::Markdown, "code-block", b"    ```\n"), None);
+        assert_eq!(boundaries(OutputFormat::Markdown, "mid-line", b"a ```` b\n"), None);
+    }
+
+    #[test]
+    fn markdown_fence_across_chunks() {
+        let mut content = vec![b'x'; SCAN_CHUNK - 3];
+        content.extend_from_slice(b"\n``````\n");
+        assert_eq!(boundaries(OutputFormat::Markdown, "across", &content).as_deref(), Some("```````"));
+    }
+
+    fn xml_content(content: &[u8]) -> String {
+        let mut out = Vec::new();
+        XmlFormatter.write_content(&mut out, content).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn xml_splits_cdata_terminators() {
+        assert_eq!(xml_content(b"a]]>b"), "a]]]]><![CDATA[>b");
+        assert_eq!(xml_content(b"]]>]]>"), "]]]]><![CDATA[>]]]]><![CDATA[>");
+        assert_eq!(xml_content(b"]] > ]>"), "]] > ]>");
+
+        // Joining the CDATA sections gives back the original content.
+        let content = "if a[b[c]]>d { x]]>]] }";
+        let wrapped = format!("<![CDATA[{}]]>", xml_content(content.as_bytes()));
+        let unwrapped: String = wrapped
+            .split("]]><![CDATA[")
+            .collect::<String>()
+            .trim_start_matches("<![CDATA[")
+            .trim_end_matches("]]>")
+            .to_string();
+        assert_eq!(unwrapped, content);
+        assert_eq!(wrapped.matches("]]>").count(), 3);
+    }
+
+    #[test]
+    fn xml_replaces_invalid_characters() {
+        assert_eq!(xml_content(b"a\x00b\x1bc\td\n"), "a\u{FFFD}b\u{FFFD}c\td\n");
+        assert_eq!(xml_content(b"\xff"), "\u{FFFD}");
+        assert_eq!(
+            escape_xml("<a href=\"x\">'&'</a>\x07"),
+            "&lt;a href=&quot;x&quot;&gt;&apos;&amp;&apos;&lt;/a&gt;\u{FFFD}"
+        );
+    }
+}
@@ -0,0 +1,514 @@
+use crate::diagnostic::Diagnostic;
+use crate::error::Error;
+
+use git2::{Diff, DiffFormat, DiffOptions, Repository, Sort, Tree};
+use rustc_hash::{FxHashMap, FxHashSet};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Which changes select files in git selection mode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GitSelection {
+    /// Changes since the merge base of the ref and `HEAD`, including uncommitted and untracked changes.
+    ChangedSince(String),
+    /// Changes staged in the index, relative to `HEAD`.
+    Staged,
+    /// Staged and unstaged changes in the working tree relative to `HEAD`, including untracked files.
+    Uncommitted,
+}
+
+/// A repository containing one or more scan roots, with the scan roots as pathspecs.
+/// `pathspecs` is `None` if a scan root is the repository root itself.
+struct RepositoryScope {
+    repository: Repository,
+    workdir: PathBuf,
+    pathspecs: Option<Vec<PathBuf>>,
+}
+
+/// Discovers the repositories containing the scan roots.
+///
+/// Business Logic Constraint: Only the local repository is read; nothing is fetched. Scan roots
+/// that are not inside a git repository with a working tree are skipped with a warning.
+fn discover_repositories(scan_roots: &[PathBuf], diagnostics: &mut Vec<Diagnostic>) -> Vec<RepositoryScope> {
+    let mut scopes: Vec<RepositoryScope> = Vec::new();
+
+    for scan_root in scan_roots {
+        let repository = match Repository::discover(scan_root) {
+            Ok(repository) => repository,
+            Err(e) => {
+                diagnostics.push(Diagnostic::Warning(format!(
+                    "Scan directory {:?} is not inside a git repository: {}. Skipping.",
+                    scan_root,
+                    e.message()
+                )));
+                continue;
+            }
+        };
+        let Some(workdir) = repository.workdir().and_then(|w| fs::canonicalize(w).ok()) else {
+            diagnostics.push(Diagnostic::Warning(format!(
+                "Repository containing {:?} has no working tree. Skipping.",
+                scan_root
+            )));
+            continue;
+        };
+        let pathspec = scan_root
+            .strip_prefix(&workdir)
+            .ok()
+            .filter(|p| !p.as_os_str().is_empty())
+            .map(Path::to_path_buf);
+
+        match scopes.iter_mut().find(|scope| scope.workdir == workdir) {
+            Some(scope) => match (&mut scope.pathspecs, pathspec) {
+                (Some(pathspecs), Some(pathspec)) => pathspecs.push(pathspec),
+                (pathspecs, _) => *pathspecs = None,
+            },
+            None => scopes.push(RepositoryScope {
+                repository,
+                workdir,
+                pathspecs: pathspec.map(|p| vec![p]),
+            }),
+        }
+    }
+
+    scopes
+}
+
+/// Returns the tree of `HEAD`, or `None` if the repository has no commits yet.
+fn head_tree(repository: &Repository) -> Result<Option<Tree<'_>>, String> {
+    match repository.head() {
+        Ok(head) => head
+            .peel_to_tree()
+            .map(Some)
+            .map_err(|e| format!("Failed to resolve HEAD: {}", e.message())),
+        Err(e) if e.code() == git2::ErrorCode::UnbornBranch => Ok(None),
+        Err(e) => Err(format!("Failed to resolve HEAD: {}", e.message())),
+    }
+}
+
+/// Computes the diff selected by `selection`, restricted to the scope's scan roots.
+fn scope_diff<'r>(scope: &'r RepositoryScope, selection: &GitSelection) -> Result<Diff<'r>, String> {
+    let repository = &scope.repository;
+    let mut options = DiffOptions::new();
+    for pathspec in scope.pathspecs.iter().flatten() {
+        options.pathspec(pathspec);
+    }
+
+    // Business Logic Constraint: Selections that include the working tree also include untracked
+    // (but not ignored) files, since those are new files.
+    if selection != &GitSelection::Staged {
+        options
+            .include_untracked(true)
+            .recurse_untracked_dirs(true)
+            .show_untracked_content(true);
+    }
+
+    let diff = match selection {
+        GitSelection::ChangedSince(spec) => {
+            let base_commit = repository
+                .revparse_single(spec)
+                .and_then(|object| object.peel_to_commit())
+                .map_err(|e| format!("Failed to resolve {:?}: {}", spec, e.message()))?;
+            // Business Logic Constraint: Like `git diff <ref>...`, changes are taken relative to
+            // the merge base, so commits made on `<ref>` after branching off are not included.
+            let base_oid = match repository.head().and_then(|head| head.peel_to_commit()) {
+                Ok(head_commit) => repository
+                    .merge_base(base_commit.id(), head_commit.id())
+                    .unwrap_or(base_commit.id()),
+                Err(_) => base_commit.id(),
+            };
+            let base_tree = repository
+                .find_commit(base_oid)
+                .and_then(|commit| commit.tree())
+                .map_err(|e| format!("Failed to resolve {:?}: {}", spec, e.message()))?;
+            repository.diff_tree_to_workdir_with_index(Some(&base_tree), Some(&mut options))
+        }
+        GitSelection::Staged => {
+            let head = head_tree(repository)?;
+            repository.diff_tree_to_index(head.as_ref(), None, Some(&mut options))
+        }
+        GitSelection::Uncommitted => {
+            let head = head_tree(repository)?;
+            repository.diff_tree_to_workdir_with_index(head.as_ref(), Some(&mut options))
+        }
+    };
+
+    diff.map_err(|e| {
+        format!(
+            "Failed to diff repository {:?}: {}",
+            scope.workdir,
+            e.message()
+        )
+    })
+}
+
+/// Returns the canonical absolute paths of the files changed according to `selection`
+/// in the repositories containing the scan roots.
+///
+/// Business Logic Constraint: Deleted files are not returned, since there is nothing to serialize.
+pub fn changed_paths(
+    scan_roots: &[PathBuf],
+    selection: &GitSelection,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Result<FxHashSet<PathBuf>, Error> {
+    let mut changed = FxHashSet::default();
+
+    for scope in discover_repositories(scan_roots, diagnostics) {
+        let diff = scope_diff(&scope, selection).map_err(Error::Git)?;
+        for delta in diff.deltas() {
+            let Some(relative_path) = delta.new_file().path() else {
+                continue;
+            };
+            if let Ok(absolute_path) = fs::canonicalize(scope.workdir.join(relative_path)) {
+                changed.insert(absolute_path);
+            }
+        }
+    }
+
+    Ok(changed)
+}
+
+/// Renders the unified diff selected by `selection` for the repositories containing the scan roots.
+/// Paths in the diff are relative to the respective repository root.
+pub fn unified_diff(
+    scan_roots: &[PathBuf],
+    selection: &GitSelection,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Result<String, Error> {
+    let mut patch = Vec::new();
+
+    for scope in discover_repositories(scan_roots, diagnostics) {
+        let diff = scope_diff(&scope, selection).map_err(Error::Git)?;
+        diff.print(DiffFormat::Patch, |_delta, _hunk, line| {
+            if matches!(line.origin(), '+' | '-' | ' ') {
+                patch.push(line.origin() as u8);
+            }
+            patch.extend_from_slice(line.content());
+            true
+        })
+        .map_err(|e| Error::Git(format!("Failed to render diff: {}", e.message())))?;
+    }
+
+    Ok(String::from_utf8_lossy(&patch).into_owned())
+}
+
+/// Bounds and weight of the git-history-based importance score (`[git_history]` in kek.toml).
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(deny_unknown_fields, default)]
+pub struct GitHistorySettings {
+    /// Maximum number of commits walked, starting at `HEAD`.
+    pub commits: usize,
+    /// Only commits from the last `days` days are considered.
+    pub days: u64,
+    /// Score added to a file that was changed in the newest commit and most often.
+    pub boost: i64,
+}
+
+impl Default for GitHistorySettings {
+    fn default() -> Self {
+        Self {
+            commits: 200,
+            days: 90,
+            boost: 100,
+        }
+    }
+}
+
+/// Computes a recency/churn score for every file changed in the recent history of the
+/// repositories containing the scan roots, keyed by canonical absolute path.
+///
+/// Business Logic Constraint: Commits are walked newest first until `commits` commits were seen or
+/// a commit is older than `days`. Each commit is diffed against its first parent. A file's score is
+/// `boost * (recency + churn) / 2`, where recency is 1.0 for the newest walked commit and falls
+/// linearly with the commit's position, and churn is the number of commits touching the file
+/// relative to the most-changed file. Files not changed in the window get no score.
+/// Business Logic Constraint: Repositories without commits contribute nothing.
+pub fn history_scores(
+    scan_roots: &[PathBuf],
+    settings: &GitHistorySettings,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Result<FxHashMap<PathBuf, i64>, Error> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64);
+    let oldest_allowed = now.saturating_sub((settings.days as i64).saturating_mul(86_400));
+    let mut scores = FxHashMap::default();
+
+    for scope in discover_repositories(scan_roots, diagnostics) {
+        scope_history_scores(&scope, settings, oldest_allowed, &mut scores).map_err(Error::Git)?;
+    }
+
+    Ok(scores)
+}
+
+/// Adds the scores of the files changed in the scope's history since `oldest_allowed` (a Unix
+/// timestamp) to `scores` (see `history_scores`).
+fn scope_history_scores(
+    scope: &RepositoryScope,
+    settings: &GitHistorySettings,
+    oldest_allowed: i64,
+    scores: &mut FxHashMap<PathBuf, i64>,
+) -> Result<(), String> {
+    let repository = &scope.repository;
+    if head_tree(repository)?.is_none() {
+        return Ok(());
+    }
+
+    let mut revwalk = repository
+        .revwalk()
+        .map_err(|e| format!("Failed to walk history of {:?}: {}", scope.workdir, e.message()))?;
+    revwalk
+        .set_sorting(Sort::TIME)
+        .and_then(|_| revwalk.push_head())
+        .map_err(|e| format!("Failed to walk history of {:?}: {}", scope.workdir, e.message()))?;
+
+    // Relative path -> (index of the newest commit touching it, number of commits touching it).
+    let mut touched: FxHashMap<PathBuf, (usize, usize)> = FxHashMap::default();
+    let mut walked = 0usize;
+
+    for oid in revwalk.take(settings.commits) {
+        let commit = oid
+            .and_then(|oid| repository.find_commit(oid))
+            .map_err(|e| format!("Failed to read commit: {}", e.message()))?;
+        if commit.time().seconds() < oldest_allowed {
+            break;
+        }
+
+        let tree = commit
+            .tree()
+            .map_err(|e| format!("Failed to read commit tree: {}", e.message()))?;
+        let parent_tree = match commit.parent(0) {
+            Ok(parent) => Some(
+                parent
+                    .tree()
+                    .map_err(|e| format!("Failed to read commit tree: {}", e.message()))?,
+            ),
+            Err(_) => None,
+        };
+        let mut options = DiffOptions::new();
+        for pathspec in scope.pathspecs.iter().flatten() {
+            options.pathspec(pathspec);
+        }
+        let diff = repository
+            .diff_tree_to_tree(parent_tree.as_ref(), Some(&tree), Some(&mut options))
+            .map_err(|e| format!("Failed to diff commit {}: {}", commit.id(), e.message()))?;
+
+        for delta in diff.deltas() {
+            if let Some(path) = delta.new_file().path() {
+                let entry = touched.entry(path.to_path_buf()).or_insert((walked, 0));
+                entry.1 += 1;
+            }
+        }
+        walked += 1;
+    }
+
+    let max_churn = touched.values().map(|&(_, churn)| churn).max().unwrap_or(1);
+    for (relative_path, (newest_index, churn)) in touched {
+        let Ok(absolute_path) = fs::canonicalize(scope.workdir.join(&relative_path)) else {
+            continue; // Deleted since.
+        };
+        let recency = 1.0 - newest_index as f64 / walked as f64;
+        let churn = churn as f64 / max_churn as f64;
+        let score = (settings.boost as f64 * (recency + churn) / 2.0).round() as i64;
+        scores.insert(absolute_path, score);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git2::{Oid, RepositoryInitOptions, Signature};
+
+    /// A repository in the temporary directory, removed when dropped. Its working tree has:
+    /// - on `main`: `base.txt` (initial commit), then `main_only.txt` (not on `feature`);
+    /// - on `feature` (`HEAD`), branched off the initial commit: `base.txt` renamed to `renamed.txt`
+    ///   and `dir/committed.txt` added, then `dir/committed.txt` changed;
+    /// - `staged.txt` staged, `dir/untracked.txt` untracked and `ignored.txt` ignored.
+    struct TempRepository {
+        root: PathBuf,
+        repository: Repository,
+    }
+
+    impl TempRepository {
+        fn new(name: &str) -> TempRepository {
+            let root = std::env::temp_dir().join(format!("kek-git-{}-{}", std::process::id(), name));
+            let _ = fs::remove_dir_all(&root);
+            fs::create_dir_all(root.join("dir")).unwrap();
+            let root = fs::canonicalize(root).unwrap();
+            let repository = Repository::init_opts(&root, RepositoryInitOptions::new().initial_head("main")).unwrap();
+            let fixture = TempRepository { root, repository };
+
+            fixture.write("base.txt", "base\n");
+            fixture.write(".gitignore", "ignored.txt\n");
+            let initial = fixture.commit("refs/heads/main", 4, &[], &["base.txt", ".gitignore"], &[]);
+            fixture.write("main_only.txt", "main\n");
+            fixture.commit("refs/heads/main", 3, &[initial], &["main_only.txt"], &[]);
+            fs::remove_file(fixture.root.join("main_only.txt")).unwrap();
+            let mut index = fixture.repository.index().unwrap();
+            index.remove_path(Path::new("main_only.txt")).unwrap();
+            index.write().unwrap();
+
+            fixture.repository.set_head_detached(initial).unwrap();
+            fs::rename(fixture.root.join("base.txt"), fixture.root.join("renamed.txt")).unwrap();
+            fixture.write("dir/committed.txt", "one\n");
+            let renamed = fixture.commit(
+                "refs/heads/feature",
+                2,
+                &[initial],
+                &["renamed.txt", "dir/committed.txt"],
+                &["base.txt"],
+            );
+            fixture.write("dir/committed.txt", "one\ntwo\n");
+            fixture.commit("refs/heads/feature", 1, &[renamed], &["dir/committed.txt"], &[]);
+            fixture.repository.set_head("refs/heads/feature").unwrap();
+
+            fixture.write("staged.txt", "staged line\n");
+            let mut index = fixture.repository.index().unwrap();
+            index.add_path(Path::new("staged.txt")).unwrap();
+            index.write().unwrap();
+            fixture.write("dir/untracked.txt", "untracked\n");
+            fixture.write("ignored.txt", "ignored\n");
+            fixture
+        }
+
+        fn write(&self, relative_path: &str, content: &str) {
+            fs::write(self.root.join(relative_path), content).unwrap();
+        }
+
+        /// Commits the index with `added` added and `removed` removed to `reference`, dated
+        /// `hours_ago`, so the history is walked in a fixed order.
+        fn commit(&self, reference: &str, hours_ago: i64, parents: &[Oid], added: &[&str], removed: &[&str]) -> Oid {
+            let mut index = self.repository.index().unwrap();
+            for path in added {
+                index.add_path(Path::new(path)).unwrap();
+            }
+            for path in removed {
+                index.remove_path(Path::new(path)).unwrap();
+            }
+            index.write().unwrap();
+            let tree = self.repository.find_tree(index.write_tree().unwrap()).unwrap();
+            let parents: Vec<_> = parents.iter().map(|&oid| self.repository.find_commit(oid).unwrap()).collect();
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+            let time = git2::Time::new(now - hours_ago * 3600, 0);
+            let signature = Signature::new("kek", "kek@example.com", &time).unwrap();
+            let parents: Vec<_> = parents.iter().collect();
+            self.repository
+                .commit(Some(reference), &signature, &signature, "commit", &tree, &parents)
+                .unwrap()
+        }
+
+        fn changed(&self, scan_root: &str, selection: GitSelection) -> Vec<String> {
+            let mut diagnostics = Vec::new();
+            let changed = changed_paths(&[self.root.join(scan_root)], &selection, &mut diagnostics).unwrap();
+            assert!(diagnostics.is_empty());
+            let mut changed: Vec<String> = changed
+                .iter()
+                .map(|path| path.strip_prefix(&self.root).unwrap().to_string_lossy().into_owned())
+                .collect();
+            changed.sort();
+            changed
+        }
+    }
+
+    impl Drop for TempRepository {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+
+    #[test]
+    fn selections_return_their_changed_paths() {
+        let fixture = TempRepository::new("selections");
+        let changed_since = GitSelection::ChangedSince("main".to_string());
+        assert_eq!(
+            fixture.changed(".", changed_since),
+            ["dir/committed.txt", "dir/untracked.txt", "renamed.txt", "staged.txt"]
+        );
+        assert_eq!(fixture.changed(".", GitSelection::Staged), ["staged.txt"]);
+        assert_eq!(
+            fixture.changed(".", GitSelection::Uncommitted),
+            ["dir/untracked.txt", "staged.txt"]
+        );
+    }
+
+    #[test]
+    fn scan_roots_below_the_repository_root_restrict_the_selection() {
+        let fixture = TempRepository::new("scan-root");
+        let changed_since = GitSelection::ChangedSince("main".to_string());
+        assert_eq!(
+            fixture.changed("dir", changed_since),
+            ["dir/committed.txt", "dir/untracked.txt"]
+        );
+        assert_eq!(fixture.changed("dir", GitSelection::Staged), Vec::<String>::new());
+    }
+
+    #[test]
+    fn diff_contains_the_selected_hunks() {
+        let fixture = TempRepository::new("diff");
+        let mut diagnostics = Vec::new();
+        let diff = unified_diff(std::slice::from_ref(&fixture.root), &GitSelection::Staged, &mut diagnostics).unwrap();
+        assert_eq!(
+            diff,
+            "diff --git a/staged.txt b/staged.txt\n\
+             new file mode 100644\n\
+             index 0000000..be5661a\n\
+             --- /dev/null\n\
+             +++ b/staged.txt\n\
+             @@ -0,0 +1 @@\n\
+             +staged line\n"
+        );
+
+        let diff = unified_diff(
+            std::slice::from_ref(&fixture.root),
+            &GitSelection::ChangedSince("main".to_string()),
+            &mut diagnostics,
+        )
+        .unwrap();
+        assert!(diff.contains("+++ b/dir/committed.txt\n@@ -0,0 +1,2 @@\n+one\n+two\n"), "{}", diff);
+        assert!(diff.contains("+++ b/dir/untracked.txt\n"), "{}", diff);
+        assert!(!diff.contains("main_only.txt"), "{}", diff);
+        assert!(diagnostics.is_empty());
+    }
+
+    #[test]
+    fn unknown_refs_and_directories_outside_repositories() {
+        let fixture = TempRepository::new("errors");
+        let mut diagnostics = Vec::new();
+        let selection = GitSelection::ChangedSince("no-such-branch".to_string());
+        let result = changed_paths(std::slice::from_ref(&fixture.root), &selection, &mut diagnostics);
+        assert!(matches!(result, Err(Error::Git(_))));
+
+        let outside = std::env::temp_dir().join(format!("kek-git-{}-outside", std::process::id()));
+        fs::create_dir_all(&outside).unwrap();
+        let changed = changed_paths(std::slice::from_ref(&outside), &GitSelection::Uncommitted, &mut diagnostics);
+        let _ = fs::remove_dir_all(&outside);
+        assert!(changed.unwrap().is_empty());
+        assert!(matches!(diagnostics.as_slice(), [Diagnostic::Warning(_)]));
+    }
+
+    #[test]
+    fn recent_and_frequent_changes_score_highest() {
+        let fixture = TempRepository::new("history");
+        let settings = GitHistorySettings::default();
+        let mut diagnostics = Vec::new();
+        let scores = history_scores(std::slice::from_ref(&fixture.root), &settings, &mut diagnostics).unwrap();
+        let mut scores: Vec<(String, i64)> = scores
+            .into_iter()
+            .map(|(path, score)| (path.strip_prefix(&fixture.root).unwrap().to_string_lossy().into_owned(), score))
+            .collect();
+        scores.sort();
+        // Three commits walked: `dir/committed.txt` was changed in the newest and in two of them,
+        // `renamed.txt` in the second and `.gitignore` in the oldest; `base.txt` no longer exists
+        // and `main_only.txt` isn't in the history of `HEAD`.
+        assert_eq!(
+            scores,
+            [
+                (".gitignore".to_string(), 42),
+                ("dir/committed.txt".to_string(), 100),
+                ("renamed.txt".to_string(), 58)
+            ]
+        );
+    }
+}
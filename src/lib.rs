@@ -0,0 +1,37 @@
+//! kek serializes a repository into a single document for language models: the selected files by
+//! category, ordered by priority and fit into a token budget, with an optional tree, diff and task.
+//!
+//! `Serializer` runs a serialization like the `kek` command line; the modules expose the steps
+//! (configuration, file collection, budget, formats, output) for finer control. Nothing is printed:
+//! diagnostics such as skipped files are collected in `Serialized::diagnostics`, and failures are
+//! returned as `Error`.
+
+pub mod binary;
+pub mod budget;
+pub mod config;
+mod diagnostic;
+mod error;
+pub mod file_processor;
+pub mod format;
+pub mod git;
+pub mod output;
+pub mod pipeline;
+pub mod priority;
+mod serializer;
+pub mod size_limit;
+#[cfg(feature = "skeleton")]
+pub mod skeleton;
+pub mod split;
+pub mod tokenizer;
+pub mod transform;
+pub mod tree;
+
+pub use config::AppConfig;
+pub use diagnostic::Diagnostic;
+pub use error::Error;
+pub use file_processor::{CategoryData, FileData};
+pub use format::OutputFormat;
+pub use git::GitSelection;
+pub use pipeline::Serialized;
+pub use serializer::Serializer;
+pub use tokenizer::Tokenizer;
@@ -1,80 +1,367 @@
-mod config;
-mod file_processor;
-mod output;
+mod clipboard;
+mod cli;
+mod mcp;
+mod serve;
+mod watch;
 
 use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
 use std::process::exit;
+use std::time::{Duration, SystemTime};
 
 use mimalloc::MiMalloc;
 
 use atty::Stream;
 
+use cli::{Cli, Command, ConfigAction, GlobalArgs, ListArgs, SerializeArgs, ServeArgs, WatchArgs};
+use kek::config::{self, AppConfig};
+use kek::pipeline::{self, Serialized};
+use kek::{Diagnostic, file_processor, output, split};
+
 #[global_allocator]
 static GLOBAL: MiMalloc = MiMalloc;
 
 fn main() {
+    let cli = Cli::parse_command_line();
+    let global = &cli.global;
+
+    match cli.command {
+        None => run_serialize(global, &cli.serialize),
+        Some(Command::Serialize(args)) => run_serialize(global, &args),
+        Some(Command::List(args)) => run_list(global, &args),
+        Some(Command::Watch(args)) => run_watch(global, &args),
+        Some(Command::Serve(args)) => run_serve(global, &args),
+        Some(Command::Mcp) => run_mcp(global),
+        Some(Command::Config { action }) => run_config(global, action),
+    }
+}
+
+/// Loads the configuration for `working_dir` or exits with an error message.
+fn load_config_or_exit(global: &GlobalArgs, working_dir: &Path) -> AppConfig {
+    match config::load_config(global.config.as_deref(), global.profile.as_deref(), working_dir) {
+        Ok(cfg) => cfg,
+        Err(e) => {
+            eprintln!("[ERROR] {}", e);
+            exit(1);
+        }
+    }
+}
+
+/// Prints the diagnostics of a serialization to stderr, warnings as `[WARNING]`, skipped and
+/// dropped files as `[INFO]`.
+fn print_diagnostics(diagnostics: &[Diagnostic]) {
+    for diagnostic in diagnostics {
+        let level = if diagnostic.is_warning() { "WARNING" } else { "INFO" };
+        eprintln!("[{}] {}", level, diagnostic);
+    }
+}
+
+/// Determines the current working directory (base for relative paths and globbing) or exits.
+fn working_dir_or_exit() -> PathBuf {
+    match env::current_dir() {
+        Ok(dir) => dir,
+        Err(e) => {
+            eprintln!("[ERROR] Failed to get current working directory: {}", e);
+            exit(1);
+        }
+    }
+}
+
+fn run_serialize(global: &GlobalArgs, args: &SerializeArgs) {
     // Check if stdout is a TTY (i.e., not piped)
     // Business Logic Constraint: The program is designed to output structured data,
     // which is typically consumed by another process. Direct output to a terminal
     // is not its intended use case and might lead to an undesirable user experience
     // or misinterpretation of the output.
-    if atty::is(Stream::Stdout) {
+    // With --output, --split or --clipboard, the output doesn't go to stdout (which only receives
+    // the paths of split files). --force-tty overrides the check.
+    let writes_stdout = args.output.is_none() && args.split.is_none() && args.clipboard.is_none();
+    if writes_stdout && !args.force_tty && atty::is(Stream::Stdout) {
         eprintln!(
-            "[ERROR] Program output must be piped to another command or redirected to a file."
+            "[ERROR] Program output must be piped to another command, redirected to a file or written with --output."
         );
         eprintln!(
             "Example: {} | your_command",
             env::args().next().unwrap_or_else(|| "kek".to_string())
         );
+        eprintln!("Use --force-tty to print it to the terminal anyway.");
         exit(1);
     }
 
-    // Collect command line arguments, skipping the program name.
-    // These will be printed at the end if any are provided.
-    let cli_args: Vec<String> = env::args().skip(1).collect();
-    let task_args_string: Option<String> = if cli_args.is_empty() {
-        None
-    } else {
-        Some(cli_args.join(" "))
-    };
-
-    // Load application configuration
-    let app_config = match config::load_config() {
-        Ok(cfg) => cfg,
+    let mut app_config = load_config_or_exit(global, &working_dir_or_exit());
+    args.apply(&mut app_config);
+    if let Some(output_path) = &args.output {
+        app_config.exclude_file(output_path);
+    }
+    if args.split.is_some() {
+        app_config.exclude_split_chunks(&args.split_dir, app_config.format.extension());
+    }
+    let Serialized {
+        config: app_config,
+        categories_data,
+        sections,
+        diagnostics,
+    } = match pipeline::serialize(app_config, args.diff, args.task()) {
+        Ok(serialized) => serialized,
         Err(e) => {
-            eprintln!("[ERROR] Configuration error: {}", e);
+            eprintln!("[ERROR] {}", e);
             exit(1);
         }
     };
+    print_diagnostics(&diagnostics);
 
-    // Determine current working directory (base for relative paths and globbing)
-    let working_dir = match env::current_dir() {
-        Ok(dir) => dir,
+    // Business Logic Constraint: If no categories data is processed, and no sections,
+    // there's nothing to output, so the program can exit gracefully.
+    // If there are sections (e.g. task args), we still need to run write_output.
+    if categories_data.is_empty() && sections.is_empty() {
+        // Consider logging this to stderr if it's an unexpected empty result
+        // eprintln!("[INFO] No data processed and no task arguments, exiting.");
+        return;
+    }
+
+    if let Some(limit) = args.split {
+        let mut diagnostics = Vec::new();
+        let written = split::plan_chunks(&categories_data, &sections, &app_config, limit, &mut diagnostics)
+            .and_then(|chunks| split::write_chunks(&chunks, &args.split_dir, app_config.format, &mut diagnostics));
+        print_diagnostics(&diagnostics);
+        match written {
+            Ok(paths) => {
+                for path in paths {
+                    println!("{}", path.display());
+                }
+            }
+            Err(e) => {
+                eprintln!("[ERROR] {}", e);
+                exit(1);
+            }
+        }
+        return;
+    }
+
+    if let Some(backend) = args.clipboard {
+        match clipboard::copy_output(backend, &categories_data, &sections, app_config.format) {
+            Ok((backend, size)) => eprintln!(
+                "[INFO] Copied {} to the clipboard via {}.",
+                file_processor::format_size(size),
+                backend
+            ),
+            Err(e) => {
+                eprintln!("[ERROR] Error copying output to the clipboard: {}", e);
+                exit(1);
+            }
+        }
+        return;
+    }
+
+    if let Some(output_path) = &args.output {
+        if let Err(e) =
+            output::write_output_file(output_path, &categories_data, &sections, app_config.format)
+        {
+            eprintln!("[ERROR] Error writing output to {:?}: {}", output_path, e);
+            exit(1);
+        }
+        return;
+    }
+
+    if let Err(e) = output::write_output(&categories_data, &sections, app_config.format) {
+        eprintln!("[ERROR] Error writing output to stdout: {}", e);
+        exit(1);
+    }
+}
+
+/// Prints one line per file: category, size in bytes, optionally the token count, and the
+/// relative path, separated by tabs.
+fn run_list(global: &GlobalArgs, args: &ListArgs) {
+    let mut app_config = load_config_or_exit(global, &working_dir_or_exit());
+    args.selection.apply(&mut app_config);
+    app_config.count_tokens = args.tokens;
+    let mut diagnostics = Vec::new();
+    let categories_data = match pipeline::collect_files(&app_config, &mut diagnostics) {
+        Ok(data) => pipeline::list_files(&app_config, data, &mut diagnostics),
         Err(e) => {
-            eprintln!("[ERROR] Failed to get current working directory: {}", e);
+            print_diagnostics(&diagnostics);
+            eprintln!("[ERROR] {}", e);
             exit(1);
         }
     };
+    print_diagnostics(&diagnostics);
+
+    let stdout = io::stdout();
+    let mut out = io::BufWriter::new(stdout.lock());
+    let result = categories_data.iter().try_for_each(|category_data| {
+        category_data.files.iter().try_for_each(|file_data| {
+            write!(out, "{}\t{}\t", category_data.name, file_data.size)?;
+            if args.tokens {
+                write!(out, "{}\t", file_data.tokens.unwrap_or_default())?;
+            }
+            writeln!(out, "{}", file_data.relative_path.display())
+        })
+    });
+    if let Err(e) = result.and_then(|_| out.flush()) {
+        eprintln!("[ERROR] Error writing file list to stdout: {}", e);
+        exit(1);
+    }
+}
+
+/// Paths, sizes and modification times of files.
+type FileStates = Vec<(PathBuf, u64, Option<SystemTime>)>;
+
+/// Size and modification time of the configuration files, so edits to them trigger a rewrite.
+fn config_file_states(layers: &[config::ConfigLayer]) -> FileStates {
+    layers
+        .iter()
+        .filter_map(|layer| {
+            let metadata = fs::metadata(&layer.path).ok()?;
+            Some((layer.path.clone(), metadata.len(), metadata.modified().ok()))
+        })
+        .collect()
+}
+
+/// Loads the configuration for watch mode and walks the scan roots, adding watches for the
+/// directories of the configuration files and every walked directory. Returns the configuration
+/// and the state of the selected files and the configuration files.
+fn load_watch_state(
+    global: &GlobalArgs,
+    args: &WatchArgs,
+    output_path: &Path,
+    working_dir: &Path,
+    watcher: &mut watch::Watcher,
+) -> Result<(AppConfig, (FileStates, FileStates)), String> {
+    // A missing KEK_CONFIG file is reported with the serialization (see `AppConfig::diagnostics`).
+    let layers = config::discover_config_layers(global.config.as_deref(), working_dir, &mut Vec::new());
+    watcher.watch_dirs(layers.iter().filter_map(|layer| {
+        let dir = layer.path.parent().filter(|dir| !dir.as_os_str().is_empty());
+        fs::canonicalize(dir.unwrap_or(Path::new("."))).ok()
+    }));
+
+    let mut app_config =
+        config::load_config(global.config.as_deref(), global.profile.as_deref(), working_dir)
+            .map_err(|e| e.to_string())?;
+    args.serialize.apply(&mut app_config);
+    app_config.exclude_file(output_path);
+
+    let snapshot =
+        file_processor::scan_snapshot(&app_config, &app_config.base_dir).map_err(|e| e.to_string())?;
+    watcher.watch_dirs(snapshot.dirs);
+    Ok((app_config, (snapshot.files, config_file_states(&layers))))
+}
+
+fn run_serve(global: &GlobalArgs, args: &ServeArgs) {
+    if let Err(e) = serve::run(global, args, working_dir_or_exit()) {
+        eprintln!("[ERROR] {}", e);
+        exit(1);
+    }
+}
 
-    let categories_data = match file_processor::process_all_categories(&app_config, &working_dir) {
-        Ok(data) => data,
+fn run_mcp(global: &GlobalArgs) {
+    if let Err(e) = mcp::run(global, working_dir_or_exit()) {
+        eprintln!("[ERROR] {}", e);
+        exit(1);
+    }
+}
+
+/// Writes the output to the `-o/--output` file, then rewrites it whenever a scanned file or a configuration
+/// file changes, until interrupted.
+///
+/// Business Logic Constraint: File system events only trigger a new walk. The output is rewritten
+/// only if the selected files (paths, sizes, modification times) or the configuration files
+/// changed, so events for ignored files, e.g. build artifacts or the output file itself, don't
+/// cause rewrites. Errors (e.g. a half-edited kek.toml) are reported and watching continues.
+fn run_watch(global: &GlobalArgs, args: &WatchArgs) {
+    if args.serialize.split.is_some() {
+        eprintln!("[ERROR] --split can't be used with watch.");
+        exit(1);
+    }
+    let Some(output_path) = args.serialize.output.as_deref() else {
+        eprintln!("[ERROR] watch requires an output file (-o/--output).");
+        exit(1);
+    };
+    let working_dir = working_dir_or_exit();
+    let debounce = Duration::from_millis(args.debounce);
+    let mut watcher = match watch::Watcher::new() {
+        Ok(watcher) => watcher,
         Err(e) => {
-            eprintln!("[ERROR] Error processing files: {}", e);
+            eprintln!("[ERROR] {}", e);
             exit(1);
         }
     };
 
-    // Business Logic Constraint: If no categories data is processed, and no task args,
-    // there's nothing to output, so the program can exit gracefully.
-    // If there are task_args, we still need to run write_output.
-    if categories_data.is_empty() && task_args_string.is_none() {
-        // Consider logging this to stderr if it's an unexpected empty result
-        // eprintln!("[INFO] No data processed and no task arguments, exiting.");
-        return;
+    let mut last_state = None;
+    loop {
+        let written = load_watch_state(global, args, output_path, &working_dir, &mut watcher).and_then(
+            |(app_config, state)| {
+                if last_state.as_ref() == Some(&state) {
+                    return Ok(None);
+                }
+                let serialized = pipeline::serialize(app_config, args.serialize.diff, args.serialize.task())
+                    .map_err(|e| e.to_string())?;
+                print_diagnostics(&serialized.diagnostics);
+                output::write_output_file(
+                    output_path,
+                    &serialized.categories_data,
+                    &serialized.sections,
+                    serialized.config.format,
+                )
+                .map_err(|e| format!("Error writing output to {:?}: {}", output_path, e))?;
+                last_state = Some(state);
+                Ok(Some(serialized.categories_data.iter().map(|c| c.files.len()).sum::<usize>()))
+            },
+        );
+        match written {
+            Ok(Some(file_count)) => eprintln!(
+                "[INFO] Wrote {} ({} files). Watching {} directories for changes.",
+                output_path.display(),
+                file_count,
+                watcher.watched_count()
+            ),
+            Ok(None) => {}
+            // Business Logic Constraint: After an error, the next change rewrites the output even
+            // if the files end up as they were before.
+            Err(e) => {
+                last_state = None;
+                eprintln!("[ERROR] {}", e);
+            }
+        }
+
+        if let Err(e) = watcher.wait_for_changes(debounce) {
+            eprintln!("[ERROR] {}", e);
+            exit(1);
+        }
     }
+}
 
-    if let Err(e) = output::write_output(&categories_data, task_args_string) {
-        eprintln!("[ERROR] Error writing output to stdout: {}", e);
-        exit(1);
+fn run_config(global: &GlobalArgs, action: ConfigAction) {
+    let working_dir = working_dir_or_exit();
+    match action {
+        ConfigAction::Path => {
+            let mut diagnostics = Vec::new();
+            let layers = config::discover_config_layers(global.config.as_deref(), &working_dir, &mut diagnostics);
+            print_diagnostics(&diagnostics);
+            if layers.is_empty() {
+                eprintln!("[INFO] No config file found; defaults are used.");
+            }
+            for layer in layers {
+                println!("{}\t{}", layer.source, layer.path.display());
+            }
+        }
+        ConfigAction::Show => {
+            let mut diagnostics = Vec::new();
+            let rendered = config::effective_config_toml(
+                global.config.as_deref(),
+                global.profile.as_deref(),
+                &working_dir,
+                &mut diagnostics,
+            );
+            print_diagnostics(&diagnostics);
+            match rendered {
+                Ok(rendered) => print!("{}", rendered),
+                Err(e) => {
+                    eprintln!("[ERROR] {}", e);
+                    exit(1);
+                }
+            }
+        }
     }
 }
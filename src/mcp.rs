@@ -0,0 +1,463 @@
+use crate::cli::{self, GlobalArgs, ListRequest, SerializeRequest};
+use kek::config::{self, AppConfig};
+use kek::file_processor::{self, CategoryData};
+use kek::format::OutputFormat;
+use kek::output::{self, OutputSections};
+use kek::pipeline;
+
+use serde_json::{Map, Value, json};
+use std::io::{self, BufRead, Read, Write};
+use std::path::PathBuf;
+
+/// MCP protocol versions the server can speak, newest first.
+const PROTOCOL_VERSIONS: [&str; 3] = ["2025-06-18", "2025-03-26", "2024-11-05"];
+
+// JSON-RPC 2.0 error codes.
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+/// JSON Schema type of a tool argument.
+#[derive(Clone, Copy)]
+enum Kind {
+    String,
+    Integer,
+    Boolean,
+    Strings,
+}
+
+/// A tool argument. Arguments are converted into the command-line option of the same name (see
+/// `Tool::options`), so the agent gets exactly the selection the CLI would make.
+#[derive(Clone, Copy)]
+struct Property {
+    name: &'static str,
+    kind: Kind,
+    description: &'static str,
+}
+
+struct Tool {
+    name: &'static str,
+    description: &'static str,
+    properties: &'static [Property],
+    required: &'static [&'static str],
+}
+
+const PROFILE: Property = Property {
+    name: "profile",
+    kind: Kind::String,
+    description: "Name of a [profile.NAME] section of the kek configuration to apply.",
+};
+const INCLUDE: Property = Property {
+    name: "include",
+    kind: Kind::Strings,
+    description: "Only include files matching these gitignore-style globs, e.g. [\"src/**\"].",
+};
+const EXCLUDE: Property = Property {
+    name: "exclude",
+    kind: Kind::Strings,
+    description: "Leave out files and directories matching these gitignore-style globs.",
+};
+const CHANGED_SINCE: Property = Property {
+    name: "changed_since",
+    kind: Kind::String,
+    description: "Only include files changed since the merge base of this git ref and HEAD.",
+};
+const STAGED: Property = Property {
+    name: "staged",
+    kind: Kind::Boolean,
+    description: "Only include files with changes staged in the git index.",
+};
+const UNCOMMITTED: Property = Property {
+    name: "uncommitted",
+    kind: Kind::Boolean,
+    description: "Only include files with staged, unstaged or untracked changes.",
+};
+const BUDGET: Property = Property {
+    name: "budget",
+    kind: Kind::Integer,
+    description: "Maximum number of tokens of the output; lower-priority files are left out to fit.",
+};
+const FORMAT: Property = Property {
+    name: "format",
+    kind: Kind::String,
+    description: "Output format: pseudo-xml, markdown, json or xml. Defaults to the configured format.",
+};
+const MINIFY: Property = Property {
+    name: "minify",
+    kind: Kind::Boolean,
+    description: "Strip comments, trailing whitespace and extra blank lines from source files.",
+};
+
+const TOOLS: [Tool; 4] = [
+    Tool {
+        name: "list_files",
+        description: "List the files kek would serialize for this repository, with their category and \
+                      size in bytes, as a JSON array. Respects .gitignore, the kek configuration and \
+                      the token budget.",
+        properties: &[
+            PROFILE,
+            INCLUDE,
+            EXCLUDE,
+            CHANGED_SINCE,
+            STAGED,
+            UNCOMMITTED,
+            BUDGET,
+            Property {
+                name: "tokens",
+                kind: Kind::Boolean,
+                description: "Also return the token count of every file.",
+            },
+        ],
+        required: &[],
+    },
+    Tool {
+        name: "read_files",
+        description: "Read files of the repository, framed by category as in kek's output. Only files \
+                      kek would serialize can be read; the token budget doesn't apply.",
+        properties: &[
+            Property {
+                name: "paths",
+                kind: Kind::Strings,
+                description: "Paths relative to the base directory (the directory of the project's \
+                              kek.toml, or the server's working directory), as returned by list_files.",
+            },
+            PROFILE,
+            FORMAT,
+            MINIFY,
+        ],
+        required: &["paths"],
+    },
+    Tool {
+        name: "serialize_repository",
+        description: "Serialize the repository into a single document, exactly as `kek` would paste \
+                      it: the selected files by category, most important files last, followed by the \
+                      task.",
+        properties: &[
+            PROFILE,
+            BUDGET,
+            Property {
+                name: "task",
+                kind: Kind::String,
+                description: "Task prompt appended at the end of the document.",
+            },
+            FORMAT,
+            INCLUDE,
+            EXCLUDE,
+            CHANGED_SINCE,
+            STAGED,
+            UNCOMMITTED,
+            MINIFY,
+            Property {
+                name: "tree",
+                kind: Kind::Boolean,
+                description: "Start the document with a directory tree of the serialized files.",
+            },
+            Property {
+                name: "diff",
+                kind: Kind::Boolean,
+                description: "Append the unified diff of the git selection (requires changed_since, \
+                              staged or uncommitted).",
+            },
+        ],
+        required: &[],
+    },
+    Tool {
+        name: "tree",
+        description: "Show the directory tree of the files kek would serialize, with their sizes.",
+        properties: &[
+            PROFILE,
+            INCLUDE,
+            EXCLUDE,
+            CHANGED_SINCE,
+            STAGED,
+            UNCOMMITTED,
+            BUDGET,
+            Property {
+                name: "excluded",
+                kind: Kind::Boolean,
+                description: "Also show scanned files that are not serialized, marked as excluded.",
+            },
+        ],
+        required: &[],
+    },
+];
+
+impl Tool {
+    /// Returns the tool's entry for `tools/list`.
+    fn definition(&self) -> Value {
+        let properties: Map<String, Value> = self
+            .properties
+            .iter()
+            .map(|property| {
+                let mut schema = match property.kind {
+                    Kind::String => json!({"type": "string"}),
+                    Kind::Integer => json!({"type": "integer", "minimum": 0}),
+                    Kind::Boolean => json!({"type": "boolean"}),
+                    Kind::Strings => json!({"type": "array", "items": {"type": "string"}}),
+                };
+                schema["description"] = property.description.into();
+                (property.name.to_string(), schema)
+            })
+            .collect();
+        json!({
+            "name": self.name,
+            "description": self.description,
+            "inputSchema": {
+                "type": "object",
+                "properties": properties,
+                "required": self.required,
+                "additionalProperties": false,
+            },
+        })
+    }
+
+    /// Converts the arguments of a call into named command-line options for `cli::parse_request`.
+    ///
+    /// Business Logic Constraint: Arguments map to the options of the same name, except `budget`
+    /// (`--max-tokens`) and `excluded` (`--tree-excluded`). Array elements become repeated options.
+    fn options(&self, arguments: &Map<String, Value>) -> Result<Vec<(String, String)>, String> {
+        let mut options = Vec::new();
+        for (name, value) in arguments {
+            if !self.properties.iter().any(|property| property.name == name) {
+                return Err(format!("Unknown argument '{}' for tool {}", name, self.name));
+            }
+            let option = match name.as_str() {
+                "budget" => "max-tokens",
+                "excluded" => "tree-excluded",
+                name => name,
+            };
+            let values = match value {
+                Value::Array(values) => values.iter().collect(),
+                Value::Null => Vec::new(),
+                value => vec![value],
+            };
+            for value in values {
+                let value = match value {
+                    Value::String(value) => value.clone(),
+                    Value::Number(value) => value.to_string(),
+                    Value::Bool(value) => value.to_string(),
+                    _ => return Err(format!("Invalid value for argument '{}': {}", name, value)),
+                };
+                options.push((option.to_string(), value));
+            }
+        }
+        for required in self.required {
+            if !arguments.contains_key(*required) {
+                return Err(format!("Missing required argument '{}' for tool {}", required, self.name));
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// Renders files and sections in `format` into a string, as they would be written to stdout.
+fn render(
+    categories_data: &[CategoryData],
+    sections: &OutputSections,
+    format: OutputFormat,
+) -> Result<String, String> {
+    let (mut file, size) = output::write_output_memfd(categories_data, sections, format)
+        .map_err(|e| format!("Error rendering output: {}", e))?;
+    let mut content = Vec::with_capacity(usize::try_from(size).unwrap_or_default());
+    file.read_to_end(&mut content)
+        .map_err(|e| format!("Error rendering output: {}", e))?;
+    Ok(String::from_utf8_lossy(&content).into_owned())
+}
+
+/// Collects the files for a tool call and prints the diagnostics of the collection.
+fn collect_files(app_config: &AppConfig) -> Result<Vec<CategoryData>, String> {
+    let mut diagnostics = Vec::new();
+    let collected = pipeline::collect_files(app_config, &mut diagnostics);
+    crate::print_diagnostics(&diagnostics);
+    collected.map_err(|e| e.to_string())
+}
+
+fn error_response(id: Value, code: i64, message: impl Into<String>) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": {"code": code, "message": message.into()},
+    })
+}
+
+struct Server<'a> {
+    global: &'a GlobalArgs,
+    working_dir: PathBuf,
+}
+
+impl Server<'_> {
+    /// Loads the configuration for a tool call, with its profile taking precedence over the
+    /// server's.
+    fn load_config(&self, profile: Option<&str>) -> Result<AppConfig, String> {
+        let profile = profile.or(self.global.profile.as_deref());
+        config::load_config(self.global.config.as_deref(), profile, &self.working_dir)
+            .map_err(|e| e.to_string())
+    }
+
+    fn list_files(&self, options: &[(String, String)]) -> Result<String, String> {
+        let (request, _) = cli::parse_request::<ListRequest>(options)?;
+        let mut app_config = self.load_config(request.profile.as_deref())?;
+        request.list.selection.apply(&mut app_config);
+        app_config.count_tokens = request.list.tokens;
+        let categories_data = collect_files(&app_config)?;
+        let mut diagnostics = Vec::new();
+        let categories_data = pipeline::list_files(&app_config, categories_data, &mut diagnostics);
+        crate::print_diagnostics(&diagnostics);
+        serde_json::to_string_pretty(&pipeline::file_list_json(&categories_data, request.list.tokens))
+            .map_err(|e| format!("Error rendering file list: {}", e))
+    }
+
+    /// Serializes the repository with the given options, like `kek serialize`.
+    fn serialize(&self, options: &[(String, String)]) -> Result<pipeline::Serialized, String> {
+        let (request, _) = cli::parse_request::<SerializeRequest>(options)?;
+        let mut app_config = self.load_config(request.profile.as_deref())?;
+        request.serialize.apply(&mut app_config);
+        let serialized = pipeline::serialize(app_config, request.serialize.diff, request.serialize.task())
+            .map_err(|e| e.to_string())?;
+        crate::print_diagnostics(&serialized.diagnostics);
+        Ok(serialized)
+    }
+
+    /// Business Logic Constraint: The files are collected without a token budget or tree, so only
+    /// files that are tokenized anyway are tokenized, and any selected file can be read.
+    fn read_files(&self, options: Vec<(String, String)>) -> Result<String, String> {
+        let (paths, options): (Vec<_>, Vec<_>) = options.into_iter().partition(|(name, _)| name == "paths");
+        let paths: Vec<String> = paths.into_iter().map(|(_, path)| path).collect();
+        let (request, _) = cli::parse_request::<SerializeRequest>(&options)?;
+        let mut app_config = self.load_config(request.profile.as_deref())?;
+        request.serialize.apply(&mut app_config);
+        app_config.max_tokens = None;
+        app_config.tree.enabled = false;
+        let categories_data = collect_files(&app_config)?;
+
+        let (mut categories_data, missing) = pipeline::select_files(&categories_data, &paths);
+        if !missing.is_empty() {
+            return Err(format!(
+                "Not in kek's output (missing, ignored or excluded): {}",
+                missing.join(", ")
+            ));
+        }
+        file_processor::expand_descriptions(&mut categories_data);
+        render(&categories_data, &OutputSections::default(), app_config.format)
+    }
+
+    fn serialize_repository(&self, options: &[(String, String)]) -> Result<String, String> {
+        let serialized = self.serialize(options)?;
+        render(&serialized.categories_data, &serialized.sections, serialized.config.format)
+    }
+
+    /// Business Logic Constraint: The budget is applied like for `list_files`, without reserving
+    /// tokens for any sections, so the tree shows the same files.
+    fn tree(&self, mut options: Vec<(String, String)>) -> Result<String, String> {
+        options.push(("tree".to_string(), "true".to_string()));
+        let (request, _) = cli::parse_request::<SerializeRequest>(&options)?;
+        let mut app_config = self.load_config(request.profile.as_deref())?;
+        request.serialize.apply(&mut app_config);
+        let categories_data = collect_files(&app_config)?;
+        let mut diagnostics = Vec::new();
+        let categories_data = pipeline::list_files(&app_config, categories_data, &mut diagnostics);
+        crate::print_diagnostics(&diagnostics);
+        pipeline::render_tree(&app_config, &categories_data).map_err(|e| e.to_string())
+    }
+
+    /// Handles `tools/call`. Unknown tools and invalid arguments are protocol errors; errors while
+    /// running a tool are returned as its result with `isError`, so the agent can see them.
+    fn call_tool(&self, params: &Value) -> Result<Value, (i64, String)> {
+        let name = params["name"].as_str().unwrap_or_default();
+        let tool = TOOLS
+            .iter()
+            .find(|tool| tool.name == name)
+            .ok_or_else(|| (INVALID_PARAMS, format!("Unknown tool: {}", name)))?;
+        let arguments = match &params["arguments"] {
+            Value::Object(arguments) => arguments.clone(),
+            Value::Null => Map::new(),
+            _ => return Err((INVALID_PARAMS, "Tool arguments must be an object".to_string())),
+        };
+        let options = tool.options(&arguments).map_err(|e| (INVALID_PARAMS, e))?;
+
+        let result = match tool.name {
+            "list_files" => self.list_files(&options),
+            "read_files" => self.read_files(options),
+            "serialize_repository" => self.serialize_repository(&options),
+            _ => self.tree(options),
+        };
+        match &result {
+            Ok(text) => eprintln!("[INFO] {} ({})", name, file_processor::format_size(text.len() as u64)),
+            Err(e) => eprintln!("[WARNING] {} failed: {}", name, e),
+        }
+        let (text, is_error) = match result {
+            Ok(text) => (text, false),
+            Err(e) => (e, true),
+        };
+        Ok(json!({
+            "content": [{"type": "text", "text": text}],
+            "isError": is_error,
+        }))
+    }
+
+    /// Handles a JSON-RPC message. Returns the response, or `None` for notifications and
+    /// responses, which get none.
+    fn handle(&self, message: Value) -> Option<Value> {
+        let Value::Object(message) = message else {
+            return Some(error_response(Value::Null, INVALID_REQUEST, "Expected a JSON-RPC message object"));
+        };
+        let method = message.get("method").and_then(Value::as_str)?;
+        let id = message.get("id")?.clone();
+        let params = message.get("params").cloned().unwrap_or(Value::Null);
+
+        let result = match method {
+            "initialize" => {
+                let requested = params["protocolVersion"].as_str().unwrap_or_default();
+                let version = PROTOCOL_VERSIONS
+                    .into_iter()
+                    .find(|version| *version == requested)
+                    .unwrap_or(PROTOCOL_VERSIONS[0]);
+                Ok(json!({
+                    "protocolVersion": version,
+                    "capabilities": {"tools": {"listChanged": false}},
+                    "serverInfo": {"name": "kek", "version": env!("CARGO_PKG_VERSION")},
+                    "instructions": "The tools show this repository exactly as kek would serialize it, \
+                                     respecting .gitignore, the kek configuration and its profiles.",
+                }))
+            }
+            "ping" => Ok(json!({})),
+            "tools/list" => Ok(json!({"tools": TOOLS.iter().map(Tool::definition).collect::<Vec<_>>()})),
+            "tools/call" => self.call_tool(&params),
+            _ => Err((METHOD_NOT_FOUND, format!("Method not found: {}", method))),
+        };
+        Some(match result {
+            Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+            Err((code, message)) => error_response(id, code, message),
+        })
+    }
+}
+
+/// Runs `kek mcp`: a Model Context Protocol server reading JSON-RPC messages from stdin, one per
+/// line, and writing the responses to stdout.
+///
+/// Business Logic Constraint: Every tool call walks the repository again, so results always
+/// reflect the current files and configuration. Log messages go to stderr, since stdout carries
+/// only the protocol. The server exits when stdin is closed.
+pub fn run(global: &GlobalArgs, working_dir: PathBuf) -> Result<(), String> {
+    let server = Server { global, working_dir };
+    let mut stdout = io::stdout().lock();
+    for line in io::stdin().lock().lines() {
+        let line = line.map_err(|e| format!("Failed to read from stdin: {}", e))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str(&line) {
+            Ok(message) => server.handle(message),
+            Err(e) => Some(error_response(Value::Null, PARSE_ERROR, format!("Parse error: {}", e))),
+        };
+        if let Some(response) = response {
+            serde_json::to_writer(&mut stdout, &response)
+                .map_err(io::Error::from)
+                .and_then(|_| stdout.write_all(b"\n"))
+                .and_then(|_| stdout.flush())
+                .map_err(|e| format!("Failed to write to stdout: {}", e))?;
+        }
+    }
+    Ok(())
+}
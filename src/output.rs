@@ -1,10 +1,15 @@
-use crate::file_processor::CategoryData;
+use crate::file_processor::{CategoryData, ContentMode, FileData};
+use crate::format::OutputFormat;
 use rustix::fd::{AsFd, BorrowedFd}; 
-use rustix::fs::{open, sendfile, Mode, OFlags};
+use rustix::fs::{copy_file_range, fstat, memfd_create, open, sendfile, FileType, MemfdFlags, Mode, OFlags};
 use rustix::io as rustix_io;
 use rustix::stdio;
-use std::fmt::Display;
-use std::io::{self, BufWriter, Write};
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Seek, Write};
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+use std::process;
 
 /// A wrapper around `BorrowedFd` to implement `std::io::Write`.
 /// This allows `rustix` file descriptors to be used with `std::io::BufWriter`
@@ -37,128 +42,319 @@ impl<'a> io::Write for FdWriter<'a> {
     }
 }
 
-/// Helper to write a string slice followed by a newline to an `io::Write` implementor.
-fn write_str_line_to_writer(writer: &mut impl Write, text: &str) -> io::Result<()> {
-    writer.write_all(text.as_bytes())?;
-    writer.write_all(b"\n")
+/// Streams `len` bytes of the file, starting at `offset`, to `out_fd`: with `copy_file_range` if
+/// `out_fd` is a regular file, otherwise with `sendfile`.
+fn stream_range(
+    out_fd: BorrowedFd<'_>,
+    out_is_file: bool,
+    file_data: &FileData,
+    offset: u64,
+    len: u64,
+) -> io::Result<()> {
+    if out_is_file {
+        copy_range(out_fd, file_data, offset, len)
+    } else {
+        sendfile_range(out_fd, file_data, offset, len)
+    }
 }
 
-/// Helper to write a Display-able type followed by a newline to an `io::Write` implementor.
-fn write_display_line_to_writer<T: Display>(writer: &mut impl Write, item: T) -> io::Result<()> {
-    writeln!(writer, "{}", item)
+/// Copies `len` bytes of the file, starting at `offset`, to the regular file `out_fd` at its
+/// current position using `copy_file_range`, so the file system can copy (or share) the data
+/// without it passing through user space.
+///
+/// Business Logic Constraint: If the file systems don't support `copy_file_range` between them
+/// (e.g. across mounts on older kernels), the remaining bytes are streamed with `sendfile`.
+fn copy_range(out_fd: BorrowedFd<'_>, file_data: &FileData, offset: u64, len: u64) -> io::Result<()> {
+    let in_fd = open(&file_data.absolute_path, OFlags::RDONLY, Mode::empty()).map_err(|e| {
+        io::Error::other(format!(
+            "Failed to open file {:?} for copy_file_range: {}",
+            file_data.absolute_path, e
+        ))
+    })?;
+
+    let mut in_offset = offset;
+    let mut copied = 0u64;
+    while copied < len {
+        let remaining = usize::try_from(len - copied).unwrap_or(usize::MAX);
+        match copy_file_range(&in_fd, Some(&mut in_offset), out_fd, None, remaining) {
+            Ok(0) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::WriteZero,
+                    format!(
+                        "copy_file_range copied 0 bytes for {:?} before completion (copied {} of {}). File may have been truncated.",
+                        file_data.absolute_path, copied, len
+                    ),
+                ));
+            }
+            Ok(bytes_copied) => copied += bytes_copied as u64,
+            Err(rustix_io::Errno::INTR) => continue,
+            Err(
+                rustix_io::Errno::XDEV
+                | rustix_io::Errno::INVAL
+                | rustix_io::Errno::NOSYS
+                | rustix_io::Errno::OPNOTSUPP,
+            ) => return sendfile_range(out_fd, file_data, in_offset, len - copied),
+            Err(e) => {
+                return Err(io::Error::other(format!(
+                    "copy_file_range failed for {:?}: {}",
+                    file_data.absolute_path, e
+                )));
+            }
+        }
+    }
+    Ok(())
 }
 
-/// Writes the processed category data and optional task arguments to stdout.
-/// Metadata (XML-like tags, descriptions, paths, task arguments) is written using a `BufWriter`
-/// wrapping stdout for buffered I/O.
-/// File content is streamed directly using `sendfile` after flushing the buffer.
-///
-/// Business Logic Constraint: Output is pseudo-XML, not strictly valid XML. No escaping is performed.
-/// Business Logic Constraint: File content is written raw via `sendfile`.
-/// Business Logic Constraint: If `task_args` is `Some`, it will be printed as `<task>{args}</task>`
-/// at the end of the output, even if `categories_data` is empty.
+/// Streams `len` bytes of the file, starting at `offset`, to `out_fd` using `sendfile`.
+fn sendfile_range(out_fd: BorrowedFd<'_>, file_data: &FileData, offset: u64, len: u64) -> io::Result<()> {
+    let file_to_send_owned_fd =
+        open(&file_data.absolute_path, OFlags::RDONLY, Mode::empty()).map_err(|e| {
+            io::Error::other(format!(
+                "Failed to open file {:?} for sendfile: {}",
+                file_data.absolute_path, e
+            ))
+        })?;
+
+    let file_size: usize = len.try_into().unwrap();
+    let mut file_offset = offset;
+
+    let mut sent_total = 0usize;
+    let file_to_send_borrowed_fd = file_to_send_owned_fd.as_fd();
+    while sent_total < file_size {
+        let remaining_to_send = file_size - sent_total;
+        match sendfile(out_fd, file_to_send_borrowed_fd, Some(&mut file_offset), remaining_to_send) {
+            Ok(0) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::WriteZero,
+                    format!(
+                        "sendfile sent 0 bytes for {:?} before completion (sent {} of {}). File may have been truncated or output pipe closed.",
+                        file_data.absolute_path, sent_total, file_size
+                    ),
+                ));
+            }
+            Ok(bytes_sent_this_call) => {
+                sent_total += bytes_sent_this_call;
+            }
+            Err(e) if e == rustix_io::Errno::INTR => continue,
+            Err(e) => {
+                return Err(io::Error::other(format!(
+                    "sendfile failed for {:?}: {}",
+                    file_data.absolute_path, e
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Reads `len` bytes of the file, starting at `offset`.
+fn read_range(file_data: &FileData, offset: u64, len: u64) -> io::Result<Vec<u8>> {
+    let mut content = vec![0; len as usize];
+    File::open(&file_data.absolute_path)
+        .and_then(|file| file.read_exact_at(&mut content, offset))
+        .map_err(|e| {
+            io::Error::other(format!(
+                "Failed to read file {:?}: {}",
+                file_data.absolute_path, e
+            ))
+        })?;
+    Ok(content)
+}
+
+/// Sections written before and after the file contents.
+#[derive(Debug, Default, Clone)]
+pub struct OutputSections {
+    /// Index (1-based) and count of the chunk when the output is split (`--split`), written first.
+    pub part: Option<(usize, usize)>,
+    /// Directory tree (`--tree`), written before the categories.
+    pub tree: Option<String>,
+    /// Unified diff of the git selection (`--diff`).
+    pub diff: Option<String>,
+    /// Task prompt, written last.
+    pub task: Option<String>,
+}
+
+impl OutputSections {
+    pub fn is_empty(&self) -> bool {
+        self.part.is_none() && self.tree.is_none() && self.diff.is_none() && self.task.is_none()
+    }
+}
+
+/// Writes the processed category data and optional sections to stdout in the given format
+/// (see `write_output_to`).
 pub fn write_output(
     categories_data: &[CategoryData],
-    task_args: Option<String>,
+    sections: &OutputSections,
+    format: OutputFormat,
 ) -> io::Result<()> {
-    // If there's no category data and no task arguments, there's nothing to do.
-    if categories_data.is_empty() && task_args.is_none() {
-        return Ok(());
-    }
-
     // Obtain an OwnedFd for stdout from rustix, then immediately get a BorrowedFd.
     // The BorrowedFd's lifetime is tied to the scope of this function call where stdout_owned_fd exists.
     let stdout_owned_fd = stdio::stdout();
-    let stdout_borrowed_fd = stdout_owned_fd.as_fd();
+    write_output_to(stdout_owned_fd.as_fd(), categories_data, sections, format)
+}
 
-    let fd_writer_for_stdout = FdWriter {
-        fd: stdout_borrowed_fd,
-    };
-    let mut buffered_stdout = BufWriter::new(fd_writer_for_stdout);
+/// Writes the processed category data and optional sections to the file at `path` in the given
+/// format (see `write_output_to`).
+///
+/// Business Logic Constraint: The output is written to a temporary file next to `path`, which
+/// then replaces `path` by a rename, so readers never see a partially written file.
+pub fn write_output_file(
+    path: &Path,
+    categories_data: &[CategoryData],
+    sections: &OutputSections,
+    format: OutputFormat,
+) -> io::Result<()> {
+    let file_name = path.file_name().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Output path {:?} has no file name", path),
+        )
+    })?;
+    let mut temp_name = OsString::from(".");
+    temp_name.push(file_name);
+    temp_name.push(format!(".kek-tmp-{}", process::id()));
+    let temp_path = path.with_file_name(temp_name);
 
-    for category_data in categories_data {
-        write_str_line_to_writer(&mut buffered_stdout, "<category>")?;
+    // Business Logic Constraint: An existing output file keeps its permissions.
+    let permissions = fs::metadata(path).ok().map(|metadata| metadata.permissions());
+    let result = File::create(&temp_path)
+        .and_then(|file| match permissions {
+            Some(permissions) => file.set_permissions(permissions).map(|_| file),
+            None => Ok(file),
+        })
+        .and_then(|file| write_output_to(file.as_fd(), categories_data, sections, format))
+        .and_then(|_| fs::rename(&temp_path, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
 
-        write_str_line_to_writer(&mut buffered_stdout, "<description>")?;
-        write_display_line_to_writer(&mut buffered_stdout, &category_data.description_text)?;
-        write_str_line_to_writer(&mut buffered_stdout, "</description>")?;
+/// Writes the processed category data and optional sections into an anonymous in-memory file
+/// (see `write_output_to`). Returns the file, rewound to the start, and the size of the output.
+/// Used when the size must be known before the output is sent anywhere.
+pub fn write_output_memfd(
+    categories_data: &[CategoryData],
+    sections: &OutputSections,
+    format: OutputFormat,
+) -> io::Result<(File, u64)> {
+    let mut file = File::from(memfd_create("kek-output", MemfdFlags::CLOEXEC)?);
+    write_output_to(file.as_fd(), categories_data, sections, format)?;
+    let size = file.stream_position()?;
+    file.rewind()?;
+    Ok((file, size))
+}
 
-        write_str_line_to_writer(&mut buffered_stdout, "<files>")?;
+/// Writes the processed category data and optional sections to `out_fd` in the given format.
+/// Metadata (tags, descriptions, paths, task arguments) is written using a `BufWriter`
+/// wrapping `out_fd` for buffered I/O.
+/// For formats with raw content, file content is streamed directly using `sendfile` (or
+/// `copy_file_range` into a regular file) after flushing the buffer. Other formats read the file and escape its content through the buffer.
+/// Truncated files are written as their head and tail ranges around the formatter's marker.
+///
+/// Business Logic Constraint: The part header and the tree (if any) precede the categories, the diff
+/// (if any) follows them. The task (if any) is written at the end of the output (e.g. `<task>{args}</task>`), even if `categories_data` is empty.
+pub fn write_output_to(
+    out_fd: BorrowedFd<'_>,
+    categories_data: &[CategoryData],
+    sections: &OutputSections,
+    format: OutputFormat,
+) -> io::Result<()> {
+    // If there's no category data and no sections, there's nothing to do.
+    if categories_data.is_empty() && sections.is_empty() {
+        return Ok(());
+    }
 
-        for file_data in &category_data.files {
-            write_str_line_to_writer(&mut buffered_stdout, "<file>")?;
+    let out_is_file = fstat(out_fd).is_ok_and(|stat| FileType::from_raw_mode(stat.st_mode).is_file());
+    let fd_writer = FdWriter {
+        fd: out_fd,
+    };
+    let mut buffered_out = BufWriter::new(fd_writer);
+    let mut formatter = format.formatter();
+
+    formatter.begin_document(&mut buffered_out, categories_data)?;
+    if let Some((index, count)) = sections.part {
+        formatter.part(&mut buffered_out, index, count)?;
+    }
+    if let Some(tree) = &sections.tree {
+        formatter.tree(&mut buffered_out, tree)?;
+    }
 
-            write_str_line_to_writer(&mut buffered_stdout, "<path>")?;
-            write_display_line_to_writer(&mut buffered_stdout, file_data.relative_path.display())?;
-            write_str_line_to_writer(&mut buffered_stdout, "</path>")?;
+    for category_data in categories_data {
+        formatter.begin_category(&mut buffered_out, category_data)?;
 
-            write_str_line_to_writer(&mut buffered_stdout, "<content>")?;
-            buffered_stdout.flush()?; // Flush metadata before sendfile
+        for file_data in &category_data.files {
+            formatter.begin_file(&mut buffered_out, category_data, file_data)?;
 
-            let file_to_send_owned_fd =
-                open(&file_data.absolute_path, OFlags::RDONLY, Mode::empty()).map_err(|e| {
-                    io::Error::new(
-                        io::ErrorKind::Other,
-                        format!(
-                            "Failed to open file {:?} for sendfile: {}",
-                            file_data.absolute_path, e
-                        ),
-                    )
-                })?;
-
-            let file_size: usize = file_data.size.try_into().unwrap();
-
-            if file_size > 0 {
-                let mut sent_total = 0usize;
-                let file_to_send_borrowed_fd = file_to_send_owned_fd.as_fd();
-                while sent_total < file_size {
-                    let remaining_to_send = file_size - sent_total;
-                    match sendfile(
-                        stdout_borrowed_fd,
-                        file_to_send_borrowed_fd,
-                        None,
-                        remaining_to_send,
-                    ) {
-                        Ok(0) => {
-                            return Err(io::Error::new(
-                                io::ErrorKind::WriteZero,
-                                format!(
-                                    "sendfile sent 0 bytes for {:?} before completion (sent {} of {}). File may have been truncated or output pipe closed.",
-                                    file_data.absolute_path, sent_total, file_size
-                                ),
-                            ));
-                        }
-                        Ok(bytes_sent_this_call) => {
-                            sent_total += bytes_sent_this_call;
-                        }
-                        Err(e) if e == rustix_io::Errno::INTR => continue,
-                        Err(e) => {
-                            return Err(io::Error::new(
-                                io::ErrorKind::Other,
-                                format!("sendfile failed for {:?}: {}", file_data.absolute_path, e),
-                            ));
-                        }
+            // Business Logic Constraint: List-only files (e.g. binaries configured as `list`)
+            // get a marker instead of their content.
+            if file_data.content == ContentMode::ListOnly {
+                formatter.list_only(&mut buffered_out, file_data)?;
+                formatter.end_file(&mut buffered_out, file_data)?;
+                continue;
+            }
+
+            formatter.begin_content(&mut buffered_out, file_data)?;
+            match (&file_data.content, formatter.raw_content()) {
+                (ContentMode::Transformed(content), true) => buffered_out.write_all(content)?,
+                (ContentMode::Transformed(content), false) => formatter.write_content(&mut buffered_out, content)?,
+                (&ContentMode::Truncated { head, tail }, true) => {
+                    buffered_out.flush()?;
+                    stream_range(out_fd, out_is_file, file_data, 0, head)?;
+                    formatter.truncated(&mut buffered_out, file_data, file_data.size - head - tail)?;
+                    buffered_out.flush()?;
+                    stream_range(out_fd, out_is_file, file_data, file_data.size - tail, tail)?;
+                }
+                (&ContentMode::Truncated { head, tail }, false) => {
+                    let head_content = read_range(file_data, 0, head)?;
+                    formatter.write_content(&mut buffered_out, &head_content)?;
+                    formatter.truncated(&mut buffered_out, file_data, file_data.size - head - tail)?;
+                    let tail_content = read_range(file_data, file_data.size - tail, tail)?;
+                    formatter.write_content(&mut buffered_out, &tail_content)?;
+                }
+                (&ContentMode::Range { offset, len }, true) => {
+                    buffered_out.flush()?;
+                    stream_range(out_fd, out_is_file, file_data, offset, len)?;
+                }
+                (&ContentMode::Range { offset, len }, false) => {
+                    let content = read_range(file_data, offset, len)?;
+                    formatter.write_content(&mut buffered_out, &content)?;
+                }
+                (_, true) => {
+                    if file_data.size > 0 {
+                        buffered_out.flush()?; // Flush metadata before sendfile
+                        stream_range(out_fd, out_is_file, file_data, 0, file_data.size)?;
                     }
                 }
+                (_, false) => {
+                    let content = fs::read(&file_data.absolute_path).map_err(|e| {
+                        io::Error::other(format!(
+                            "Failed to read file {:?}: {}",
+                            file_data.absolute_path, e
+                        ))
+                    })?;
+                    formatter.write_content(&mut buffered_out, &content)?;
+                }
             }
-            // Write a newline after the file content; this goes through the buffer.
-            buffered_stdout.write_all(b"\n")?;
-            write_str_line_to_writer(&mut buffered_stdout, "</content>")?;
-            write_str_line_to_writer(&mut buffered_stdout, "</file>")?;
+            formatter.end_content(&mut buffered_out, file_data)?;
+            formatter.end_file(&mut buffered_out, file_data)?;
         }
-        write_str_line_to_writer(&mut buffered_stdout, "</files>")?;
-        write_str_line_to_writer(&mut buffered_stdout, "</category>")?;
+        formatter.end_category(&mut buffered_out, category_data)?;
     }
 
+    if let Some(diff) = &sections.diff {
+        formatter.diff(&mut buffered_out, diff)?;
+    }
     // After all categories and files, write the task arguments if present.
     // Business Logic Constraint: If command line arguments were provided to the program
-    // (after the program name), they are joined by spaces and printed here,
-    // wrapped in <task> tags. This occurs even if the joined string is empty
+    // (after the program name), they are joined by spaces and written here.
+    // This occurs even if the joined string is empty
     // (e.g., if the only argument was an empty string).
-    if let Some(joined_args) = task_args {
-        write_str_line_to_writer(&mut buffered_stdout, &format!("<task>{}</task>", joined_args))?;
+    if let Some(joined_args) = &sections.task {
+        formatter.task(&mut buffered_out, joined_args)?;
     }
+    formatter.end_document(&mut buffered_out)?;
 
-    buffered_stdout.flush()?; // Ensure all buffered data, including task args, is written.
+    buffered_out.flush()?; // Ensure all buffered data, including task args, is written.
     Ok(())
 }
@@ -0,0 +1,222 @@
+use crate::budget;
+use crate::config::AppConfig;
+use crate::diagnostic::Diagnostic;
+use crate::error::Error;
+use crate::file_processor::{self, CategoryData, FileData};
+use crate::git;
+use crate::output::{self, OutputSections};
+use crate::tree;
+
+use rustix::fd::BorrowedFd;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Files and sections of one serialization, ready to be written.
+pub struct Serialized {
+    pub config: AppConfig,
+    pub categories_data: Vec<CategoryData>,
+    pub sections: OutputSections,
+    /// Files skipped or dropped and other problems worked around, in the order they were noticed.
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Serialized {
+    /// Returns the serialized files with their category names, in output order.
+    pub fn files(&self) -> impl Iterator<Item = (&str, &FileData)> {
+        self.categories_data
+            .iter()
+            .flat_map(|category_data| category_data.files.iter().map(|f| (category_data.name.as_str(), f)))
+    }
+
+    /// Writes the output to `out_fd`, streaming file contents with `sendfile`/`copy_file_range`
+    /// where the format allows (see `output::write_output_to`).
+    pub fn write_to_fd(&self, out_fd: BorrowedFd<'_>) -> Result<(), Error> {
+        output::write_output_to(out_fd, &self.categories_data, &self.sections, self.config.format)?;
+        Ok(())
+    }
+
+    /// Writes the output to `writer` and returns its size in bytes.
+    ///
+    /// Business Logic Constraint: The output is rendered into an in-memory file first, since file
+    /// contents are streamed between file descriptors; prefer `write_to_fd` for large outputs.
+    pub fn write_to(&self, mut writer: impl Write) -> Result<u64, Error> {
+        let (mut file, _) =
+            output::write_output_memfd(&self.categories_data, &self.sections, self.config.format)?;
+        let size = io::copy(&mut file, &mut writer)?;
+        writer.flush()?;
+        Ok(size)
+    }
+
+    /// Writes the output to the file at `path`, replacing it atomically (see
+    /// `output::write_output_file`).
+    pub fn write_to_file(&self, path: &Path) -> Result<(), Error> {
+        output::write_output_file(path, &self.categories_data, &self.sections, self.config.format)?;
+        Ok(())
+    }
+}
+
+/// Walks the scan roots and collects the selected files, categorized and ordered. This is the
+/// expensive part of a serialization (it reads and tokenizes files), which `kek serve` caches.
+/// The configuration's diagnostics and those of the walk are added to `diagnostics`.
+pub fn collect_files(app_config: &AppConfig, diagnostics: &mut Vec<Diagnostic>) -> Result<Vec<CategoryData>, Error> {
+    diagnostics.extend(app_config.diagnostics.iter().cloned());
+    file_processor::process_all_categories(app_config, &app_config.base_dir, diagnostics)
+}
+
+/// Applies the token budget to the collected files and renders the sections around them: the
+/// unified diff of the git selection if `diff` is set, and the `task` prompt. `diagnostics` are
+/// those of the collection (see `collect_files`); the budget's are added.
+pub fn finish(
+    app_config: AppConfig,
+    mut categories_data: Vec<CategoryData>,
+    mut diagnostics: Vec<Diagnostic>,
+    diff: bool,
+    task: Option<String>,
+) -> Result<Serialized, Error> {
+    let working_dir = &app_config.base_dir;
+
+    // Business Logic Constraint: Skipped scan paths and repositories were already reported while
+    // collecting the files with the same git selection.
+    let diff = match (&app_config.git_selection, diff) {
+        (Some(selection), true) => {
+            let scan_roots = file_processor::resolve_scan_roots(&app_config, working_dir, &mut Vec::new());
+            Some(git::unified_diff(&scan_roots, selection, &mut Vec::new())?)
+        }
+        _ => None,
+    };
+
+    let scanned = scanned_files(&app_config)?;
+    let render_tree = |categories_data: &[CategoryData]| {
+        app_config
+            .tree
+            .enabled
+            .then(|| tree::render_tree(categories_data, &scanned, app_config.tree.metric))
+    };
+
+    // Business Logic Constraint: Tokens for the tree are reserved for all files before the budget
+    // is applied; the tree is then re-rendered for the kept files, so it can only get smaller.
+    let mut sections = OutputSections {
+        part: None,
+        tree: render_tree(&categories_data),
+        diff,
+        task,
+    };
+    let reserved_tokens =
+        budget::section_tokens(app_config.tokenizer, app_config.format, &categories_data, &sections);
+    budget::apply_token_budget(&mut categories_data, &app_config, reserved_tokens, &mut diagnostics);
+    sections.tree = render_tree(&categories_data);
+    file_processor::expand_descriptions(&mut categories_data);
+
+    Ok(Serialized {
+        config: app_config,
+        categories_data,
+        sections,
+        diagnostics,
+    })
+}
+
+/// Collects, orders and budgets the files to serialize and renders the sections around them
+/// (see `finish`).
+pub fn serialize(app_config: AppConfig, diff: bool, task: Option<String>) -> Result<Serialized, Error> {
+    let mut diagnostics = Vec::new();
+    let categories_data = collect_files(&app_config, &mut diagnostics)?;
+    finish(app_config, categories_data, diagnostics, diff, task)
+}
+
+/// Applies the token budget to the collected files for `kek list`, without any sections. Dropped
+/// files are added to `diagnostics`.
+pub fn list_files(
+    app_config: &AppConfig,
+    mut categories_data: Vec<CategoryData>,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Vec<CategoryData> {
+    let reserved_tokens = budget::section_tokens(
+        app_config.tokenizer,
+        app_config.format,
+        &categories_data,
+        &OutputSections::default(),
+    );
+    budget::apply_token_budget(&mut categories_data, app_config, reserved_tokens, diagnostics);
+    categories_data
+}
+
+/// Lists every scanned file for a tree that shows excluded files, or nothing otherwise.
+///
+/// Business Logic Constraint: The tree shows excluded files only on request, since finding them
+/// takes a second walk without any selection settings.
+fn scanned_files(app_config: &AppConfig) -> Result<Vec<(PathBuf, u64)>, Error> {
+    if !(app_config.tree.enabled && app_config.tree.excluded) {
+        return Ok(Vec::new());
+    }
+    file_processor::list_scanned_files(app_config, &app_config.base_dir)
+}
+
+/// Renders the directory tree of the collected files on its own, with the excluded files if the
+/// configuration asks for them.
+pub fn render_tree(app_config: &AppConfig, categories_data: &[CategoryData]) -> Result<String, Error> {
+    let scanned = scanned_files(app_config)?;
+    Ok(tree::render_tree(categories_data, &scanned, app_config.tree.metric))
+}
+
+/// Returns the files of `paths` (relative to the base directory) with their categories, in output
+/// order, and the paths that are not among the files.
+///
+/// Business Logic Constraint: Only files in the output can be selected, so excluded and ignored
+/// files stay hidden from clients of `kek serve` and `kek mcp`.
+pub fn select_files<'a>(
+    categories_data: &[CategoryData],
+    paths: &'a [impl AsRef<str>],
+) -> (Vec<CategoryData>, Vec<&'a str>) {
+    let normalized: Vec<&Path> = paths
+        .iter()
+        .map(|path| Path::new(path.as_ref().trim_start_matches("./")))
+        .collect();
+    let selected = categories_data
+        .iter()
+        .filter_map(|category_data| {
+            let files: Vec<FileData> = category_data
+                .files
+                .iter()
+                .filter(|file_data| normalized.contains(&file_data.relative_path.as_path()))
+                .cloned()
+                .collect();
+            (!files.is_empty()).then(|| CategoryData {
+                total_size: files.iter().map(|file_data| file_data.size).sum(),
+                files,
+                ..category_data.clone()
+            })
+        })
+        .collect::<Vec<_>>();
+    let missing = paths
+        .iter()
+        .zip(&normalized)
+        .filter(|(_, path)| {
+            !selected
+                .iter()
+                .any(|category_data| category_data.files.iter().any(|f| f.relative_path == **path))
+        })
+        .map(|(path, _)| path.as_ref())
+        .collect();
+    (selected, missing)
+}
+
+/// Renders the listed files as a JSON array of objects with `path`, `category`, `size` and, if
+/// `tokens` is set, `tokens`.
+pub fn file_list_json(categories_data: &[CategoryData], tokens: bool) -> serde_json::Value {
+    categories_data
+        .iter()
+        .flat_map(|category_data| {
+            category_data.files.iter().map(move |file_data| {
+                let mut entry = serde_json::json!({
+                    "path": file_data.relative_path.to_string_lossy(),
+                    "category": category_data.name,
+                    "size": file_data.size,
+                });
+                if tokens {
+                    entry["tokens"] = file_data.tokens.unwrap_or_default().into();
+                }
+                entry
+            })
+        })
+        .collect()
+}
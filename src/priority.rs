@@ -0,0 +1,220 @@
+use crate::file_processor::CategoryData;
+
+use globset::GlobSet;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Secondary sort key for files with equal priority scores (`sort` in kek.toml, `--sort`).
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SortKey {
+    /// Ascending relative path.
+    #[default]
+    Path,
+    /// Ascending size in bytes, then path.
+    Size,
+}
+
+impl FromStr for SortKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "path" => Ok(SortKey::Path),
+            "size" => Ok(SortKey::Size),
+            _ => Err(format!(
+                "Unknown sort key \"{}\" (expected \"path\" or \"size\")",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for SortKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SortKey::Path => f.write_str("path"),
+            SortKey::Size => f.write_str("size"),
+        }
+    }
+}
+
+/// Priority scores from the `[[priority]]` entries, compiled into a single glob set.
+/// `scores[i]` is the score of the i-th glob in `globs`.
+#[derive(Debug, Clone)]
+pub struct PriorityRules {
+    pub globs: GlobSet,
+    pub scores: Vec<i64>,
+}
+
+impl PriorityRules {
+    /// Returns the priority score of the file at `relative_path`.
+    ///
+    /// Business Logic Constraint: If several entries match, the highest score wins. Files matching
+    /// no entry have a score of 0, so negative scores push files away from the task.
+    pub fn score_for(&self, relative_path: &Path) -> i64 {
+        self.globs
+            .matches(relative_path)
+            .into_iter()
+            .map(|index| self.scores[index])
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+/// Orders categories and the files within them by priority, then by `sort_key`.
+///
+/// Business Logic Constraint: Models pay more attention to the end of the prompt, so higher scores
+/// come later, closest to the task. Files are sorted by ascending score within their category.
+/// Categories are sorted by their configured position first, then by the ascending highest score of
+/// their files; ties are broken by descending total size, so small categories stay close to the task.
+/// Business Logic Constraint: The result is fully deterministic (ties end with the relative path and
+/// the category name), so an unchanged tree always produces byte-identical output regardless of the
+/// order in which the parallel walker found the files.
+pub fn sort_by_priority(categories_data: &mut [CategoryData], sort_key: SortKey) {
+    for category_data in categories_data.iter_mut() {
+        match sort_key {
+            SortKey::Path => category_data.files.sort_by(|a, b| {
+                (a.priority, &a.relative_path).cmp(&(b.priority, &b.relative_path))
+            }),
+            SortKey::Size => category_data.files.sort_by(|a, b| {
+                (a.priority, a.size, &a.relative_path).cmp(&(b.priority, b.size, &b.relative_path))
+            }),
+        }
+    }
+    categories_data.sort_by(|a, b| {
+        let key = |c: &CategoryData| {
+            (
+                c.position,
+                c.files.iter().map(|f| f.priority).max().unwrap_or(0),
+                Reverse(c.total_size),
+            )
+        };
+        key(a).cmp(&key(b)).then_with(|| a.name.cmp(&b.name))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_processor::{ContentMode, FileData};
+    use globset::{Glob, GlobSetBuilder};
+    use std::path::PathBuf;
+
+    fn file(path: &str, size: u64, priority: i64) -> FileData {
+        FileData {
+            relative_path: PathBuf::from(path),
+            absolute_path: PathBuf::from("/nonexistent").join(path),
+            size,
+            tokens: None,
+            content: ContentMode::Full,
+            boundary: None,
+            priority,
+        }
+    }
+
+    fn category(name: &str, position: i64, files: Vec<FileData>) -> CategoryData {
+        CategoryData {
+            name: name.to_string(),
+            description_text: String::new(),
+            total_size: files.iter().map(|f| f.size).sum(),
+            files,
+            position,
+        }
+    }
+
+    /// Sorts every rotation of `files`, forwards and backwards, and returns the resulting order,
+    /// asserting that it is the same for all of them.
+    fn sorted_paths(files: Vec<FileData>, sort_key: SortKey) -> Vec<String> {
+        let mut orders = Vec::new();
+        for reverse in [false, true] {
+            for rotation in 0..files.len() {
+                let mut shuffled = files.clone();
+                shuffled.rotate_left(rotation);
+                if reverse {
+                    shuffled.reverse();
+                }
+                let mut categories_data = [category("src", 0, shuffled)];
+                sort_by_priority(&mut categories_data, sort_key);
+                let [category_data] = categories_data;
+                orders.push(
+                    category_data
+                        .files
+                        .iter()
+                        .map(|f| f.relative_path.to_string_lossy().into_owned())
+                        .collect::<Vec<_>>(),
+                );
+            }
+        }
+        assert!(orders.windows(2).all(|pair| pair[0] == pair[1]), "{:?}", orders);
+        orders.swap_remove(0)
+    }
+
+    #[test]
+    fn equal_priorities_are_ordered_by_path_or_size() {
+        let files = vec![
+            file("src/b.rs", 10, 0),
+            file("src/a.rs", 30, 0),
+            file("src/c.rs", 20, 0),
+            file("src/d.rs", 20, 0),
+            file("Cargo.toml", 30, 0),
+        ];
+        assert_eq!(
+            sorted_paths(files.clone(), SortKey::Path),
+            ["Cargo.toml", "src/a.rs", "src/b.rs", "src/c.rs", "src/d.rs"]
+        );
+        assert_eq!(
+            sorted_paths(files, SortKey::Size),
+            ["src/b.rs", "src/c.rs", "src/d.rs", "Cargo.toml", "src/a.rs"]
+        );
+    }
+
+    #[test]
+    fn higher_priorities_come_last() {
+        let files = vec![
+            file("src/main.rs", 10, 5),
+            file("src/a.rs", 10, 0),
+            file("tests/it.rs", 10, -5),
+            file("src/z.rs", 10, 0),
+        ];
+        assert_eq!(
+            sorted_paths(files, SortKey::Path),
+            ["tests/it.rs", "src/a.rs", "src/z.rs", "src/main.rs"]
+        );
+    }
+
+    #[test]
+    fn categories_are_ordered_by_position_score_and_size() {
+        let mut categories_data = [
+            category("important", 0, vec![file("lib.rs", 10, 5)]),
+            category("last", 1, vec![file("z.rs", 10, 0)]),
+            category("small", 0, vec![file("b.rs", 10, 0)]),
+            category("large", 0, vec![file("a.rs", 100, 0)]),
+            category("other", 0, vec![file("c.rs", 10, 0)]),
+            category("first", -1, vec![file("d.rs", 10, 9)]),
+        ];
+        sort_by_priority(&mut categories_data, SortKey::Path);
+        let names: Vec<&str> = categories_data.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["first", "large", "other", "small", "important", "last"]);
+    }
+
+    #[test]
+    fn highest_matching_score_wins() {
+        let mut globs = GlobSetBuilder::new();
+        for glob in ["src/**", "src/main.rs", "**/*.md"] {
+            globs.add(Glob::new(glob).unwrap());
+        }
+        let rules = PriorityRules {
+            globs: globs.build().unwrap(),
+            scores: vec![10, 50, -20],
+        };
+        assert_eq!(rules.score_for(Path::new("src/main.rs")), 50);
+        assert_eq!(rules.score_for(Path::new("src/lib.rs")), 10);
+        assert_eq!(rules.score_for(Path::new("src/README.md")), 10);
+        assert_eq!(rules.score_for(Path::new("README.md")), -20);
+        assert_eq!(rules.score_for(Path::new("Cargo.toml")), 0);
+    }
+}
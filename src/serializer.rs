@@ -0,0 +1,224 @@
+use crate::config::{self, AppConfig};
+use crate::error::Error;
+use crate::format::OutputFormat;
+use crate::git::GitSelection;
+use crate::output::OutputSections;
+use crate::pipeline::{self, Serialized};
+use crate::tokenizer::Tokenizer;
+
+use std::path::PathBuf;
+
+/// Builds and runs serializations of a repository, like `kek serialize` and `kek list`.
+///
+/// By default, the configuration is discovered like on the command line (user configuration,
+/// project `kek.toml`, `KEK_CONFIG`); the builder methods override it like the corresponding
+/// command-line options.
+///
+/// ```no_run
+/// use kek::{OutputFormat, Serializer};
+///
+/// let serialized = Serializer::new("path/to/repository")
+///     .profile("backend")
+///     .include("src/**")
+///     .format(OutputFormat::Markdown)
+///     .max_tokens(50_000)
+///     .task("Fix the login bug.")
+///     .serialize()?;
+/// for (category, file) in serialized.files() {
+///     println!("{}\t{}", category, file.relative_path.display());
+/// }
+/// serialized.write_to(std::io::stdout().lock())?;
+/// # Ok::<(), kek::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct Serializer {
+    working_dir: PathBuf,
+    /// Configuration used as-is instead of discovering the configuration files.
+    config: Option<AppConfig>,
+    config_file: Option<PathBuf>,
+    profile: Option<String>,
+    scan: Vec<PathBuf>,
+    include: Vec<String>,
+    exclude: Vec<String>,
+    format: Option<OutputFormat>,
+    tokenizer: Option<Tokenizer>,
+    max_tokens: Option<usize>,
+    count_tokens: bool,
+    git_selection: Option<GitSelection>,
+    diff: bool,
+    tree: bool,
+    minify: bool,
+    task: Option<String>,
+}
+
+impl Serializer {
+    /// Creates a serializer for the repository at `working_dir`, which takes the place of the
+    /// working directory of the command line.
+    pub fn new(working_dir: impl Into<PathBuf>) -> Self {
+        Serializer {
+            working_dir: working_dir.into(),
+            config: None,
+            config_file: None,
+            profile: None,
+            scan: Vec::new(),
+            include: Vec::new(),
+            exclude: Vec::new(),
+            format: None,
+            tokenizer: None,
+            max_tokens: None,
+            count_tokens: false,
+            git_selection: None,
+            diff: false,
+            tree: false,
+            minify: false,
+            task: None,
+        }
+    }
+
+    /// Creates a serializer using `config` as-is, without reading any configuration files. Paths
+    /// are relative to `config.base_dir`.
+    pub fn with_config(config: AppConfig) -> Self {
+        Serializer {
+            config: Some(config.clone()),
+            ..Serializer::new(config.base_dir)
+        }
+    }
+
+    /// Merges the configuration file at `path` over the discovered ones, like `--config`.
+    pub fn config_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config_file = Some(path.into());
+        self
+    }
+
+    /// Applies `[profile.<name>]` of the configuration, like `--profile`.
+    pub fn profile(mut self, name: impl Into<String>) -> Self {
+        self.profile = Some(name.into());
+        self
+    }
+
+    /// Adds a directory to scan instead of the configured `scan` paths. Relative paths are relative
+    /// to the base directory (see `AppConfig::base_dir`).
+    pub fn scan_root(mut self, path: impl Into<PathBuf>) -> Self {
+        self.scan.push(path.into());
+        self
+    }
+
+    /// Only includes files matching the gitignore-style `glob`, like `--include`.
+    pub fn include(mut self, glob: impl Into<String>) -> Self {
+        self.include.push(glob.into());
+        self
+    }
+
+    /// Leaves out files and directories matching the gitignore-style `glob`, like `--exclude`.
+    pub fn exclude(mut self, glob: impl Into<String>) -> Self {
+        self.exclude.push(glob.into());
+        self
+    }
+
+    /// Sets the output format, like `--format`.
+    pub fn format(mut self, format: OutputFormat) -> Self {
+        self.format = Some(format);
+        self
+    }
+
+    /// Sets the tokenizer for token counts and the budget, like `--tokenizer`.
+    pub fn tokenizer(mut self, tokenizer: Tokenizer) -> Self {
+        self.tokenizer = Some(tokenizer);
+        self
+    }
+
+    /// Limits the output to `max_tokens` tokens, like `--max-tokens`.
+    pub fn max_tokens(mut self, max_tokens: usize) -> Self {
+        self.max_tokens = Some(max_tokens);
+        self
+    }
+
+    /// Counts the tokens of every file even without a budget, so `FileData::tokens` is set.
+    pub fn count_tokens(mut self, count_tokens: bool) -> Self {
+        self.count_tokens = count_tokens;
+        self
+    }
+
+    /// Only includes files changed in git, like `--changed-since`, `--staged` or `--uncommitted`.
+    pub fn git_selection(mut self, selection: GitSelection) -> Self {
+        self.git_selection = Some(selection);
+        self
+    }
+
+    /// Appends the unified diff of the git selection, like `--diff`.
+    pub fn diff(mut self, diff: bool) -> Self {
+        self.diff = diff;
+        self
+    }
+
+    /// Writes a directory tree of the serialized files before their contents, like `--tree`.
+    pub fn tree(mut self, tree: bool) -> Self {
+        self.tree = tree;
+        self
+    }
+
+    /// Strips comments, trailing whitespace and extra blank lines from the 'src' category, like
+    /// `--minify`.
+    pub fn minify(mut self, minify: bool) -> Self {
+        self.minify = minify;
+        self
+    }
+
+    /// Appends a task prompt at the end of the output.
+    pub fn task(mut self, task: impl Into<String>) -> Self {
+        self.task = Some(task.into());
+        self
+    }
+
+    /// Returns the configuration with all overrides of the builder applied.
+    pub fn app_config(&self) -> Result<AppConfig, Error> {
+        let mut app_config = match &self.config {
+            Some(config) => config.clone(),
+            None => {
+                config::load_config(self.config_file.as_deref(), self.profile.as_deref(), &self.working_dir)?
+            }
+        };
+        if !self.scan.is_empty() {
+            app_config.scan = self.scan.clone();
+        }
+        app_config.include.extend(self.include.iter().cloned());
+        app_config.exclude.extend(self.exclude.iter().cloned());
+        if let Some(format) = self.format {
+            app_config.format = format;
+        }
+        if let Some(tokenizer) = self.tokenizer {
+            app_config.tokenizer = tokenizer;
+        }
+        if let Some(max_tokens) = self.max_tokens {
+            app_config.max_tokens = Some(max_tokens);
+        }
+        app_config.count_tokens |= self.count_tokens;
+        if let Some(selection) = &self.git_selection {
+            app_config.git_selection = Some(selection.clone());
+        }
+        app_config.tree.enabled |= self.tree;
+        app_config.minify |= self.minify;
+        Ok(app_config)
+    }
+
+    /// Collects, orders and budgets the files and renders the sections around them, ready to be
+    /// written (see `Serialized::write_to`).
+    pub fn serialize(&self) -> Result<Serialized, Error> {
+        pipeline::serialize(self.app_config()?, self.diff, self.task.clone())
+    }
+
+    /// Returns the files `kek list` would print, by category, with the token budget applied and
+    /// without any sections.
+    pub fn list(&self) -> Result<Serialized, Error> {
+        let app_config = self.app_config()?;
+        let mut diagnostics = Vec::new();
+        let categories_data = pipeline::collect_files(&app_config, &mut diagnostics)?;
+        let categories_data = pipeline::list_files(&app_config, categories_data, &mut diagnostics);
+        Ok(Serialized {
+            config: app_config,
+            categories_data,
+            sections: OutputSections::default(),
+            diagnostics,
+        })
+    }
+}
@@ -0,0 +1,640 @@
+use crate::cli::{self, GlobalArgs, ListRequest, SerializeRequest, ServeArgs};
+use kek::config::{self, AppConfig};
+use kek::file_processor::{self, CategoryData};
+use kek::output::{self, OutputSections};
+use kek::pipeline;
+use crate::watch::Watcher;
+
+use clap::Parser;
+use rustc_hash::FxHashMap;
+use rustix::event::{PollFd, PollFlags, poll};
+use rustix::fd::{AsFd, BorrowedFd};
+use rustix::fs::sendfile;
+use rustix::io::Errno;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Maximum size of a request line plus headers.
+const MAX_REQUEST_HEAD: usize = 16 * 1024;
+
+/// Time a client may take to send its request or receive the response.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Maximum number of endpoint and option combinations whose collected files are cached.
+const MAX_CACHE_ENTRIES: usize = 32;
+
+/// Host names a request on the TCP port may be addressed to.
+const LOCAL_HOSTS: [&str; 3] = ["localhost", "127.0.0.1", "[::1]"];
+
+const USAGE: &str = "Endpoints:
+  GET /serialize?<options>     Serialized output, e.g. /serialize?format=markdown&task=Fix%20it
+  GET /files?<options>         JSON list of the selected files with categories (tokens=true for token counts)
+  GET /file?path=<path>&<options>  Framed content of a single selected file
+Options are the long command-line options of `kek serialize` and `kek list` without dashes;
+flags take `true` or no value, and repeatable options can be repeated.
+";
+
+enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+enum Connection {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Listener {
+    fn accept(&self) -> io::Result<Connection> {
+        match self {
+            Listener::Tcp(listener) => listener.accept().map(|(stream, _)| Connection::Tcp(stream)),
+            Listener::Unix(listener) => listener.accept().map(|(stream, _)| Connection::Unix(stream)),
+        }
+    }
+}
+
+impl AsFd for Listener {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        match self {
+            Listener::Tcp(listener) => listener.as_fd(),
+            Listener::Unix(listener) => listener.as_fd(),
+        }
+    }
+}
+
+impl Connection {
+    fn set_timeouts(&self, timeout: Duration) -> io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => stream
+                .set_read_timeout(Some(timeout))
+                .and_then(|_| stream.set_write_timeout(Some(timeout))),
+            Connection::Unix(stream) => stream
+                .set_read_timeout(Some(timeout))
+                .and_then(|_| stream.set_write_timeout(Some(timeout))),
+        }
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Connection::Tcp(stream) => stream.read(buf),
+            Connection::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Connection::Tcp(stream) => stream.write(buf),
+            Connection::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => stream.flush(),
+            Connection::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+impl AsFd for Connection {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        match self {
+            Connection::Tcp(stream) => stream.as_fd(),
+            Connection::Unix(stream) => stream.as_fd(),
+        }
+    }
+}
+
+/// Binds the Unix socket at `path`.
+///
+/// Business Logic Constraint: A socket file left behind by a server that is no longer running is
+/// replaced; a socket with a live server behind it is an error. The socket is only accessible to
+/// the current user, since the output contains the repository's contents.
+fn bind_unix_socket(path: &Path) -> Result<UnixListener, String> {
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            return Err(format!("Socket {:?} is already in use", path));
+        }
+        fs::remove_file(path).map_err(|e| format!("Failed to remove stale socket {:?}: {}", path, e))?;
+    }
+    let listener =
+        UnixListener::bind(path).map_err(|e| format!("Failed to bind socket {:?}: {}", path, e))?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))
+        .map_err(|e| format!("Failed to restrict permissions of socket {:?}: {}", path, e))?;
+    Ok(listener)
+}
+
+/// An HTTP response.
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: Body,
+}
+
+enum Body {
+    Bytes(Vec<u8>),
+    /// An in-memory file with the rendered output and its size.
+    File(File, u64),
+}
+
+impl Response {
+    fn text(status: u16, message: impl Into<String>) -> Self {
+        let mut body = message.into().into_bytes();
+        if !body.ends_with(b"\n") {
+            body.push(b'\n');
+        }
+        Response {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: Body::Bytes(body),
+        }
+    }
+
+    fn len(&self) -> u64 {
+        match &self.body {
+            Body::Bytes(bytes) => bytes.len() as u64,
+            Body::File(_, size) => *size,
+        }
+    }
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        431 => "Request Header Fields Too Large",
+        _ => "Internal Server Error",
+    }
+}
+
+/// Streams `len` bytes of `file` from its start to `out_fd` using `sendfile`.
+fn send_file(out_fd: BorrowedFd<'_>, file: &File, len: u64) -> io::Result<()> {
+    let mut offset = 0u64;
+    while offset < len {
+        let remaining = usize::try_from(len - offset).unwrap_or(usize::MAX);
+        match sendfile(out_fd, file, Some(&mut offset), remaining) {
+            Ok(0) => return Err(io::Error::new(io::ErrorKind::WriteZero, "sendfile sent 0 bytes")),
+            Ok(_) => {}
+            Err(Errno::INTR) => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(())
+}
+
+/// Writes `response` and closes the connection afterwards (`Connection: close`).
+fn write_response(connection: &mut Connection, response: &Response) -> io::Result<()> {
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n{}\r\n",
+        response.status,
+        reason_phrase(response.status),
+        response.content_type,
+        response.len(),
+        if response.status == 405 { "Allow: GET\r\n" } else { "" }
+    );
+    connection.write_all(head.as_bytes())?;
+    match &response.body {
+        Body::Bytes(bytes) => connection.write_all(bytes)?,
+        Body::File(file, size) => send_file(connection.as_fd(), file, *size)?,
+    }
+    connection.flush()
+}
+
+/// Decodes `%XX` escapes and, if `plus_as_space` is set, `+` in a request target component.
+fn percent_decode(component: &str, plus_as_space: bool) -> Result<String, String> {
+    let bytes = component.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let escaped = bytes
+                    .get(i + 1..i + 3)
+                    .and_then(|hex| std::str::from_utf8(hex).ok())
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .ok_or_else(|| format!("Invalid percent-encoding in {:?}", component))?;
+                decoded.push(escaped);
+                i += 3;
+            }
+            b'+' if plus_as_space => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            byte => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).map_err(|_| format!("{:?} is not valid UTF-8 when decoded", component))
+}
+
+/// A parsed request line and `Host` header.
+struct Request {
+    method: String,
+    path: String,
+    query: Vec<(String, String)>,
+    /// The raw request target, for logging.
+    target: String,
+    host: Option<String>,
+}
+
+/// Reads the request head (request line and headers) and parses the request line and the `Host`
+/// header. Request bodies and other headers are ignored, since all endpoints are `GET` requests.
+fn read_request(connection: &mut impl Read) -> Result<Request, Response> {
+    let mut head = Vec::new();
+    let mut buffer = [0u8; 4096];
+    while !head.windows(4).any(|window| window == b"\r\n\r\n") {
+        if head.len() > MAX_REQUEST_HEAD {
+            return Err(Response::text(431, "Request head too large"));
+        }
+        match connection.read(&mut buffer) {
+            Ok(0) => return Err(Response::text(400, "Incomplete request")),
+            Ok(n) => head.extend_from_slice(&buffer[..n]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(Response::text(400, format!("Failed to read request: {}", e))),
+        }
+    }
+
+    let head = String::from_utf8_lossy(&head);
+    let mut lines = head.lines();
+    let request_line = lines.next().unwrap_or_default();
+    let mut parts = request_line.split(' ');
+    let (Some(method), Some(target), Some(version)) = (parts.next(), parts.next(), parts.next()) else {
+        return Err(Response::text(400, format!("Malformed request line {:?}", request_line)));
+    };
+    if !version.starts_with("HTTP/1.") {
+        return Err(Response::text(400, format!("Unsupported protocol {:?}", version)));
+    }
+
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let path = percent_decode(path, false).map_err(|e| Response::text(400, e))?;
+    let query = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            Ok((percent_decode(key, true)?, percent_decode(value, true)?))
+        })
+        .collect::<Result<Vec<_>, String>>()
+        .map_err(|e| Response::text(400, e))?;
+
+    let host = lines
+        .map(str::trim_end)
+        .take_while(|line| !line.is_empty())
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("host"))
+        .map(|(_, value)| value.trim().to_string());
+
+    Ok(Request {
+        method: method.to_string(),
+        path,
+        query,
+        target: target.to_string(),
+        host,
+    })
+}
+
+/// Whether a `Host` header names this machine's loopback interface and `port`.
+///
+/// Business Logic Constraint: Browsers send any page's requests to 127.0.0.1 if its domain
+/// resolves there (DNS rebinding), but with that domain in `Host`. Only loopback names are
+/// accepted, so such pages can't read the output. A missing port means port 80.
+fn is_local_host(host: &str, port: u16) -> bool {
+    let host = host.to_ascii_lowercase();
+    LOCAL_HOSTS.iter().any(|name| match host.strip_prefix(name) {
+        Some("") => port == 80,
+        Some(rest) => rest.strip_prefix(':') == Some(port.to_string().as_str()),
+        None => false,
+    })
+}
+
+/// Parses the query parameters of a request like command-line options (see `cli::parse_request`).
+fn parse_query<P: Parser>(query: &[(String, String)]) -> Result<(P, Vec<String>), Response> {
+    cli::parse_request(query).map_err(|e| Response::text(400, e))
+}
+
+/// Serves requests until the listener fails.
+struct Server<'a> {
+    global: &'a GlobalArgs,
+    working_dir: PathBuf,
+    /// The TCP port requests must be addressed to, or `None` on a Unix socket.
+    port: Option<u16>,
+    watcher: Watcher,
+    /// Collected files by endpoint and request options (without the task).
+    cache: FxHashMap<(&'static str, Vec<String>), Vec<CategoryData>>,
+}
+
+impl Server<'_> {
+    /// Loads the configuration for a request, with its profile taking precedence over the server's.
+    fn load_config(&self, profile: Option<&str>) -> Result<AppConfig, Response> {
+        let profile = profile.or(self.global.profile.as_deref());
+        config::load_config(self.global.config.as_deref(), profile, &self.working_dir)
+            .map_err(|e| Response::text(500, e.to_string()))
+    }
+
+    /// Forgets all collected files if a watched directory changed since the last call.
+    fn invalidate_if_changed(&mut self) -> Result<(), String> {
+        if self.watcher.drain()? {
+            self.cache.clear();
+        }
+        Ok(())
+    }
+
+    /// Returns the files collected for `app_config`, from the cache if nothing changed since.
+    ///
+    /// Business Logic Constraint: Before collecting, the directories of the walk and of the
+    /// configuration files are watched, so any later change invalidates the cached files. The
+    /// diagnostics of the collection are printed when collecting, not for every cached response.
+    fn collected_files(
+        &mut self,
+        endpoint: &'static str,
+        key: Vec<String>,
+        app_config: &AppConfig,
+    ) -> Result<Vec<CategoryData>, Response> {
+        let key = (endpoint, key);
+        if let Some(categories_data) = self.cache.get(&key) {
+            return Ok(categories_data.clone());
+        }
+
+        let layers = config::discover_config_layers(self.global.config.as_deref(), &self.working_dir, &mut Vec::new());
+        self.watcher.watch_dirs(
+            layers
+                .iter()
+                .filter_map(|layer| fs::canonicalize(layer.path.parent()?).ok()),
+        );
+        let snapshot = file_processor::scan_snapshot(app_config, &app_config.base_dir)
+            .map_err(|e| Response::text(500, e.to_string()))?;
+        self.watcher.watch_dirs(snapshot.dirs);
+
+        let mut diagnostics = Vec::new();
+        let collected = pipeline::collect_files(app_config, &mut diagnostics);
+        crate::print_diagnostics(&diagnostics);
+        let categories_data = collected.map_err(|e| Response::text(500, e.to_string()))?;
+        // Business Logic Constraint: Every distinct set of options adds an entry, so a full cache
+        // starts over rather than growing without bound.
+        if self.cache.len() >= MAX_CACHE_ENTRIES {
+            self.cache.clear();
+        }
+        self.cache.insert(key, categories_data.clone());
+        Ok(categories_data)
+    }
+
+    /// `GET /serialize`: the output of `kek serialize` with the options from the query.
+    fn serialize(&mut self, query: &[(String, String)]) -> Result<Response, Response> {
+        let (request, key) = parse_query::<SerializeRequest>(query)?;
+        let mut app_config = self.load_config(request.profile.as_deref())?;
+        request.serialize.apply(&mut app_config);
+        let categories_data = self.collected_files("serialize", key, &app_config)?;
+
+        let serialized = pipeline::finish(
+            app_config,
+            categories_data,
+            Vec::new(),
+            request.serialize.diff,
+            request.serialize.task(),
+        )
+        .map_err(|e| Response::text(500, e.to_string()))?;
+        crate::print_diagnostics(&serialized.diagnostics);
+        let format = serialized.config.format;
+        let (file, size) =
+            output::write_output_memfd(&serialized.categories_data, &serialized.sections, format)
+                .map_err(|e| Response::text(500, format!("Error rendering output: {}", e)))?;
+        Ok(Response {
+            status: 200,
+            content_type: format.media_type(),
+            body: Body::File(file, size),
+        })
+    }
+
+    /// `GET /files`: the files `kek list` would print, as a JSON array of objects with `path`,
+    /// `category`, `size` and, with `tokens=true`, `tokens`.
+    fn files(&mut self, query: &[(String, String)]) -> Result<Response, Response> {
+        let (request, key) = parse_query::<ListRequest>(query)?;
+        let mut app_config = self.load_config(request.profile.as_deref())?;
+        request.list.selection.apply(&mut app_config);
+        app_config.count_tokens = request.list.tokens;
+        let categories_data = self.collected_files("files", key, &app_config)?;
+        let mut diagnostics = Vec::new();
+        let categories_data = pipeline::list_files(&app_config, categories_data, &mut diagnostics);
+        crate::print_diagnostics(&diagnostics);
+
+        let files = pipeline::file_list_json(&categories_data, request.list.tokens);
+        let body = serde_json::to_vec(&files)
+            .map_err(|e| Response::text(500, format!("Error rendering file list: {}", e)))?;
+        Ok(Response {
+            status: 200,
+            content_type: "application/json",
+            body: Body::Bytes(body),
+        })
+    }
+
+    /// `GET /file?path=...`: a single file of the output, framed in its category like in the
+    /// output of `/serialize` with the same options, but without any sections.
+    fn file(&mut self, query: &[(String, String)]) -> Result<Response, Response> {
+        let (paths, options): (Vec<_>, Vec<_>) =
+            query.iter().cloned().partition(|(key, _)| key == "path");
+        let [(_, path)] = paths.as_slice() else {
+            return Err(Response::text(400, "Exactly one 'path' parameter is required"));
+        };
+        let (request, key) = parse_query::<SerializeRequest>(&options)?;
+        let mut app_config = self.load_config(request.profile.as_deref())?;
+        request.serialize.apply(&mut app_config);
+        let categories_data = self.collected_files("serialize", key, &app_config)?;
+        let serialized = pipeline::finish(
+            app_config,
+            categories_data,
+            Vec::new(),
+            request.serialize.diff,
+            request.serialize.task(),
+        )
+        .map_err(|e| Response::text(500, e.to_string()))?;
+        crate::print_diagnostics(&serialized.diagnostics);
+
+        let (categories_data, missing) =
+            pipeline::select_files(&serialized.categories_data, std::slice::from_ref(path));
+        if !missing.is_empty() {
+            return Err(Response::text(404, format!("{} is not in the output", path)));
+        }
+
+        let format = serialized.config.format;
+        let (file, size) = output::write_output_memfd(&categories_data, &OutputSections::default(), format)
+            .map_err(|e| Response::text(500, format!("Error rendering output: {}", e)))?;
+        Ok(Response {
+            status: 200,
+            content_type: format.media_type(),
+            body: Body::File(file, size),
+        })
+    }
+
+    fn handle(&mut self, connection: &mut Connection) -> Result<(), String> {
+        let _ = connection.set_timeouts(CLIENT_TIMEOUT);
+        let (target, response) = match read_request(connection) {
+            Ok(request) => {
+                self.invalidate_if_changed()?;
+                let foreign_host = self.port.is_some_and(|port| {
+                    !request.host.as_deref().is_some_and(|host| is_local_host(host, port))
+                });
+                let response = match (request.method.as_str(), request.path.as_str()) {
+                    _ if foreign_host => Err(Response::text(
+                        403,
+                        "Requests must be addressed to localhost, 127.0.0.1 or [::1]",
+                    )),
+                    ("GET", "/serialize") => self.serialize(&request.query),
+                    ("GET", "/files") => self.files(&request.query),
+                    ("GET", "/file") => self.file(&request.query),
+                    ("GET", "/") => Ok(Response::text(200, USAGE)),
+                    (_, "/" | "/serialize" | "/files" | "/file") => {
+                        Err(Response::text(405, "Only GET is supported"))
+                    }
+                    _ => Err(Response::text(404, USAGE)),
+                };
+                (
+                    format!("{} {}", request.method, request.target),
+                    response.unwrap_or_else(|response| response),
+                )
+            }
+            Err(response) => ("-".to_string(), response),
+        };
+
+        eprintln!(
+            "[INFO] {} {} ({})",
+            target,
+            response.status,
+            file_processor::format_size(response.len())
+        );
+        // Business Logic Constraint: A client that went away doesn't stop the server.
+        if let Err(e) = write_response(connection, &response) {
+            eprintln!("[WARNING] Failed to send response: {}", e);
+        }
+        Ok(())
+    }
+}
+
+/// Runs `kek serve`: answers HTTP requests on 127.0.0.1 or a Unix socket, one at a time.
+///
+/// Business Logic Constraint: Requests on the TCP port must name a loopback host in `Host`.
+/// Collected files are cached per endpoint and options, so repeated requests don't walk and read
+/// the tree again. Changes in the walked directories or the configuration files (noticed with
+/// inotify) clear the cache. Tasks, budgets and sections are
+/// computed per request, so requests differing only in the task share the cache.
+pub fn run(global: &GlobalArgs, args: &ServeArgs, working_dir: PathBuf) -> Result<(), String> {
+    let listener = match &args.socket {
+        Some(path) => Listener::Unix(bind_unix_socket(path)?),
+        None => Listener::Tcp(
+            TcpListener::bind((Ipv4Addr::LOCALHOST, args.port))
+                .map_err(|e| format!("Failed to listen on 127.0.0.1:{}: {}", args.port, e))?,
+        ),
+    };
+    match &args.socket {
+        Some(path) => eprintln!("[INFO] Listening on {}", path.display()),
+        None => eprintln!("[INFO] Listening on http://127.0.0.1:{}/", args.port),
+    }
+
+    let mut server = Server {
+        global,
+        working_dir,
+        port: args.socket.is_none().then_some(args.port),
+        watcher: Watcher::new()?,
+        cache: FxHashMap::default(),
+    };
+
+    loop {
+        let mut fds = [
+            PollFd::new(&listener, PollFlags::IN),
+            PollFd::new(&server.watcher, PollFlags::IN),
+        ];
+        match poll(&mut fds, None) {
+            Ok(_) => {}
+            Err(Errno::INTR) => continue,
+            Err(e) => return Err(format!("Failed to wait for connections: {}", e)),
+        }
+        let (connection_ready, files_changed) = (!fds[0].revents().is_empty(), !fds[1].revents().is_empty());
+
+        if files_changed {
+            server.invalidate_if_changed()?;
+        }
+        if connection_ready {
+            match listener.accept() {
+                Ok(mut connection) => server.handle(&mut connection)?,
+                Err(e) => eprintln!("[WARNING] Failed to accept connection: {}", e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(head: &str) -> Result<Request, u16> {
+        read_request(&mut head.as_bytes()).map_err(|response| response.status)
+    }
+
+    #[test]
+    fn parses_request_line_query_and_host() {
+        let request = parse("GET /serialize?task=Fix+it%21&include=src/*&minify HTTP/1.1\r\nHost: localhost:8080\r\n\r\n")
+            .unwrap();
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.path, "/serialize");
+        assert_eq!(
+            request.query,
+            [
+                ("task".to_string(), "Fix it!".to_string()),
+                ("include".to_string(), "src/*".to_string()),
+                ("minify".to_string(), String::new()),
+            ]
+        );
+        assert_eq!(request.host.as_deref(), Some("localhost:8080"));
+    }
+
+    #[test]
+    fn ignores_headers_after_the_head() {
+        let request = parse("GET / HTTP/1.0\r\nAccept: */*\r\n\r\nHost: evil.example\r\n").unwrap();
+        assert_eq!(request.host, None);
+    }
+
+    #[test]
+    fn rejects_malformed_requests() {
+        assert_eq!(parse("GET /\r\n\r\n").err(), Some(400));
+        assert_eq!(parse("GET / SPDY/3\r\n\r\n").err(), Some(400));
+        assert_eq!(parse("GET /file?path=%zz HTTP/1.1\r\n\r\n").err(), Some(400));
+        assert_eq!(parse("GET / HTTP/1.1\r\n").err(), Some(400));
+        let huge = format!("GET / HTTP/1.1\r\nX: {}\r\n\r\n", "a".repeat(2 * MAX_REQUEST_HEAD));
+        assert_eq!(parse(&huge).err(), Some(431));
+    }
+
+    #[test]
+    fn percent_decoding_keeps_plus_in_paths() {
+        assert_eq!(percent_decode("a+b%20c", false).unwrap(), "a+b c");
+        assert_eq!(percent_decode("a+b%20c", true).unwrap(), "a b c");
+        assert!(percent_decode("%ff", false).is_err());
+        assert!(percent_decode("%4", false).is_err());
+    }
+
+    #[test]
+    fn accepts_only_loopback_hosts_on_the_bound_port() {
+        assert!(is_local_host("localhost:8080", 8080));
+        assert!(is_local_host("LocalHost:8080", 8080));
+        assert!(is_local_host("127.0.0.1:8080", 8080));
+        assert!(is_local_host("[::1]:8080", 8080));
+        assert!(is_local_host("localhost", 80));
+        assert!(!is_local_host("localhost", 8080));
+        assert!(!is_local_host("localhost:8081", 8080));
+        assert!(!is_local_host("evil.example:8080", 8080));
+        assert!(!is_local_host("localhost.evil.example:8080", 8080));
+        assert!(!is_local_host("127.0.0.1.nip.io:8080", 8080));
+    }
+}
@@ -0,0 +1,172 @@
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+
+/// What to do with a file larger than its `max_file_size`.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OversizedAction {
+    /// Keep the start and the end of the file, separated by a `<truncated bytes="..."/>` marker.
+    #[default]
+    Truncate,
+    /// Leave the file out of the output and report it on stderr.
+    Skip,
+}
+
+/// The head and tail excerpts of a truncated file.
+#[derive(Debug)]
+pub struct Excerpts {
+    pub head: Vec<u8>,
+    pub tail: Vec<u8>,
+}
+
+impl Excerpts {
+    /// The excerpts concatenated, e.g. for token counting and collision checks.
+    pub fn joined(&self) -> Vec<u8> {
+        [self.head.as_slice(), self.tail.as_slice()].concat()
+    }
+}
+
+/// Returns the length of `bytes` without a UTF-8 sequence cut off at the end.
+pub fn complete_prefix_len(bytes: &[u8]) -> usize {
+    let Some(lead) = (bytes.len().saturating_sub(4)..bytes.len())
+        .rev()
+        .find(|&i| (bytes[i] & 0b1100_0000) != 0b1000_0000)
+    else {
+        return bytes.len();
+    };
+    let sequence_len = match bytes[lead] {
+        0b1111_0000.. => 4,
+        0b1110_0000.. => 3,
+        0b1100_0000.. => 2,
+        _ => 1,
+    };
+    if lead + sequence_len > bytes.len() {
+        lead
+    } else {
+        bytes.len()
+    }
+}
+
+/// Returns the number of UTF-8 continuation bytes at the start of `bytes` (at most 3).
+fn leading_continuation_len(bytes: &[u8]) -> usize {
+    bytes
+        .iter()
+        .take(3)
+        .take_while(|&&b| (b & 0b1100_0000) == 0b1000_0000)
+        .count()
+}
+
+/// Reads the head and tail excerpts of a file of `size` bytes, keeping at most `limit` bytes in total.
+///
+/// Business Logic Constraint: The first half of the limit goes to the head, the rest to the tail.
+/// The head ends before the last line break in its window and the tail starts after the first line
+/// break in its window, so excerpts consist of whole lines and the marker gets a line of its own.
+/// Windows without a line break (e.g. minified files) are cut at a UTF-8 character boundary instead.
+pub fn read_excerpts(path: &Path, size: u64, limit: u64) -> io::Result<Excerpts> {
+    let file = File::open(path)?;
+    let head_window_len = (limit / 2).min(size) as usize;
+    let tail_window_len = (limit - limit / 2).min(size - head_window_len as u64) as usize;
+
+    let mut head = vec![0; head_window_len];
+    file.read_exact_at(&mut head, 0)?;
+    let mut tail = vec![0; tail_window_len];
+    file.read_exact_at(&mut tail, size - tail_window_len as u64)?;
+
+    let head_len = match memchr::memrchr(b'\n', &head) {
+        Some(line_break) => line_break,
+        None => complete_prefix_len(&head),
+    };
+    head.truncate(head_len);
+
+    let tail_start = match memchr::memchr(b'\n', &tail) {
+        Some(line_break) => line_break + 1,
+        None => leading_continuation_len(&tail),
+    };
+    tail.drain(..tail_start);
+
+    Ok(Excerpts { head, tail })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    /// Writes `content` to a file in the temporary directory, removed when dropped.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, content: &[u8]) -> TempFile {
+            let path = std::env::temp_dir().join(format!("kek-size-limit-{}-{}", std::process::id(), name));
+            fs::write(&path, content).unwrap();
+            TempFile(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    /// Returns the excerpts of `content` as strings, checking that they are a prefix and a suffix
+    /// that don't overlap and stay within `limit`.
+    fn excerpts(name: &str, content: &str, limit: u64) -> (String, String) {
+        let file = TempFile::new(name, content.as_bytes());
+        let Excerpts { head, tail } = read_excerpts(&file.0, content.len() as u64, limit).unwrap();
+        assert!(content.as_bytes().starts_with(&head) && content.as_bytes().ends_with(&tail));
+        assert!(head.len() + tail.len() <= (limit as usize).min(content.len()));
+        (String::from_utf8(head).unwrap(), String::from_utf8(tail).unwrap())
+    }
+
+    #[test]
+    fn excerpts_consist_of_whole_lines() {
+        let content = "one\ntwo\nthree\nfour\nfive\n";
+        assert_eq!(excerpts("lines", content, 16), ("one\ntwo".into(), "five\n".into()));
+    }
+
+    #[test]
+    fn windows_without_line_breaks_are_cut_at_character_boundaries() {
+        // The head window ends and the tail window starts inside a three-byte '€'.
+        let content = "€€€€€€";
+        assert_eq!(excerpts("multibyte", content, 10), ("€".into(), "€".into()));
+        assert_eq!(excerpts("multibyte-small", content, 4), ("".into(), "".into()));
+        assert_eq!(excerpts("ascii", "abcdefghij\n0123456789", 8), ("abcd".into(), "6789".into()));
+    }
+
+    #[test]
+    fn file_just_over_the_limit() {
+        // The windows are adjacent; the tail window is a single line ending at the end of the file,
+        // so nothing of it is kept.
+        let content = "0123\n5678\n";
+        assert_eq!(excerpts("just-over", content, 9), ("0123".into(), "".into()));
+        assert_eq!(excerpts("one-line", "0123456789", 9), ("0123".into(), "56789".into()));
+    }
+
+    #[test]
+    fn empty_tail_window() {
+        // With a limit of at least twice the size, the head window takes the whole file.
+        assert_eq!(excerpts("no-tail", "one\ntwo\n", 100), ("one\ntwo".into(), "".into()));
+        assert_eq!(excerpts("limit-one", "one\ntwo\n", 1), ("".into(), "".into()));
+        assert_eq!(excerpts("empty", "", 10), ("".into(), "".into()));
+    }
+
+    #[test]
+    fn complete_prefix_drops_cut_off_characters() {
+        let crab = "🦀".as_bytes();
+        assert_eq!(complete_prefix_len(b""), 0);
+        assert_eq!(complete_prefix_len(b"abc"), 3);
+        for cut in 1..4 {
+            assert_eq!(complete_prefix_len(&[b"ab", &crab[..cut]].concat()), 2);
+        }
+        assert_eq!(complete_prefix_len(&[b"ab", crab].concat()), 6);
+        assert_eq!(complete_prefix_len("é€".as_bytes()), 5);
+        assert_eq!(complete_prefix_len(&"é€".as_bytes()[..4]), 2);
+        assert_eq!(leading_continuation_len(&crab[1..]), 3);
+        assert_eq!(leading_continuation_len(crab), 0);
+    }
+}
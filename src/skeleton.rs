@@ -0,0 +1,310 @@
+use crate::diagnostic::Diagnostic;
+
+use std::ops::Range;
+use std::path::Path;
+use tree_sitter::{Language, Node, Parser};
+
+/// How the function bodies of a language are found and elided.
+struct Grammar {
+    language: Language,
+    /// Node kinds whose `body` field is elided.
+    functions: &'static [&'static str],
+    /// Text replacing an elided body.
+    marker: &'static str,
+    /// Keeps the docstring at the start of a body, as in Python.
+    docstrings: bool,
+}
+
+fn grammar_for(path: &Path) -> Option<Grammar> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    let grammar = match extension.as_str() {
+        "rs" => Grammar {
+            language: tree_sitter_rust::LANGUAGE.into(),
+            functions: &["function_item"],
+            marker: "{ ... }",
+            docstrings: false,
+        },
+        "go" => Grammar {
+            language: tree_sitter_go::LANGUAGE.into(),
+            functions: &["function_declaration", "method_declaration", "func_literal"],
+            marker: "{ ... }",
+            docstrings: false,
+        },
+        "py" | "pyi" => Grammar {
+            language: tree_sitter_python::LANGUAGE.into(),
+            functions: &["function_definition"],
+            marker: "...",
+            docstrings: true,
+        },
+        // Business Logic Constraint: JavaScript is parsed with the TSX grammar, which accepts
+        // plain JavaScript and JSX as well.
+        "ts" | "mts" | "cts" | "tsx" | "js" | "jsx" | "mjs" | "cjs" => Grammar {
+            language: if matches!(extension.as_str(), "ts" | "mts" | "cts") {
+                tree_sitter_typescript::LANGUAGE_TYPESCRIPT.into()
+            } else {
+                tree_sitter_typescript::LANGUAGE_TSX.into()
+            },
+            functions: &[
+                "function_declaration",
+                "generator_function_declaration",
+                "function_expression",
+                "generator_function",
+                "method_definition",
+                "arrow_function",
+            ],
+            marker: "{ ... }",
+            docstrings: false,
+        },
+        _ => return None,
+    };
+    Some(grammar)
+}
+
+/// Returns the skeleton of the file at `path`: its content with the bodies of functions and
+/// methods replaced by a marker, keeping signatures, type definitions and doc comments. Returns
+/// `None` for unsupported languages (anything but Rust, Go, Python, TypeScript and JavaScript) and
+/// files that don't parse.
+///
+/// Business Logic Constraint: A file with syntax errors is sent whole (with a warning in
+/// `diagnostics`), since error recovery could elide the wrong ranges. Empty bodies are left alone.
+pub fn skeleton(path: &Path, content: &[u8], diagnostics: &mut Vec<Diagnostic>) -> Option<Vec<u8>> {
+    let grammar = grammar_for(path)?;
+    let mut parser = Parser::new();
+    parser.set_language(&grammar.language).ok()?;
+    let tree = parser.parse(content, None)?;
+    if tree.root_node().has_error() {
+        diagnostics.push(Diagnostic::SkeletonFailed(path.to_path_buf()));
+        return None;
+    }
+
+    let mut elisions = Vec::new();
+    collect_elisions(tree.root_node(), &grammar, content, &mut elisions);
+
+    let mut out = Vec::with_capacity(content.len());
+    let mut copied = 0;
+    for (range, marker) in elisions {
+        out.extend_from_slice(&content[copied..range.start]);
+        out.extend_from_slice(&marker);
+        copied = range.end;
+    }
+    out.extend_from_slice(&content[copied..]);
+    Some(out)
+}
+
+/// Collects the byte ranges of the outermost function bodies under `node` with their
+/// replacements, in order.
+fn collect_elisions(node: Node, grammar: &Grammar, content: &[u8], elisions: &mut Vec<(Range<usize>, Vec<u8>)>) {
+    if grammar.functions.contains(&node.kind())
+        && let Some(body) = node.child_by_field_name("body")
+    {
+        // Arrow functions with an expression body have nothing to elide.
+        if node.kind() == "arrow_function" && body.kind() != "statement_block" {
+            return;
+        }
+        if let Some(elision) = elide_body(body, grammar, content) {
+            elisions.push(elision);
+        }
+        return;
+    }
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        collect_elisions(child, grammar, content, elisions);
+    }
+}
+
+/// Returns the range of `body` to replace and its replacement, if the body isn't empty.
+fn elide_body(body: Node, grammar: &Grammar, content: &[u8]) -> Option<(Range<usize>, Vec<u8>)> {
+    let statements = body.named_child_count();
+    if statements == 0 {
+        return None;
+    }
+    let range = body.byte_range();
+    let docstring = body
+        .named_child(0)
+        .filter(|_| grammar.docstrings)
+        .filter(|first| first.kind() == "expression_statement")
+        .filter(|first| first.named_child(0).is_some_and(|expression| expression.kind() == "string"));
+    let Some(docstring) = docstring else {
+        return Some((range, grammar.marker.as_bytes().to_vec()));
+    };
+    if statements == 1 {
+        return None;
+    }
+    // The marker goes on its own line after the docstring, indented like the body.
+    let line_start = content[..range.start]
+        .iter()
+        .rposition(|&b| b == b'\n')
+        .map_or(0, |p| p + 1);
+    let indent = &content[line_start..range.start];
+    let mut replacement = content[range.start..docstring.end_byte()].to_vec();
+    if indent.iter().all(|&b| b == b' ' || b == b'\t') {
+        replacement.push(b'\n');
+        replacement.extend_from_slice(indent);
+    } else {
+        replacement.extend_from_slice(b"; ");
+    }
+    replacement.extend_from_slice(grammar.marker.as_bytes());
+    Some((range, replacement))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn skeleton_of(path: &str, content: &str) -> Option<String> {
+        let mut diagnostics = Vec::new();
+        let skeleton = skeleton(Path::new(path), content.as_bytes(), &mut diagnostics);
+        assert!(diagnostics.is_empty());
+        skeleton.map(|skeleton| String::from_utf8(skeleton).unwrap())
+    }
+
+    #[test]
+    fn rust_bodies_become_markers() {
+        let content = r#"/// Adds one.
+pub fn add_one(x: i32) -> i32 {
+    let y = x + 1;
+    y
+}
+
+struct Point {
+    x: i32,
+}
+
+impl Point {
+    /// The origin.
+    fn origin() -> Self {
+        Point { x: 0 }
+    }
+
+    fn noop(&self) {}
+}
+"#;
+        assert_eq!(
+            skeleton_of("src/lib.rs", content).unwrap(),
+            r#"/// Adds one.
+pub fn add_one(x: i32) -> i32 { ... }
+
+struct Point {
+    x: i32,
+}
+
+impl Point {
+    /// The origin.
+    fn origin() -> Self { ... }
+
+    fn noop(&self) {}
+}
+"#
+        );
+    }
+
+    #[test]
+    fn python_keeps_docstrings() {
+        let content = r#"class Greeter:
+    """Greets people."""
+
+    def greet(self, name):
+        """Returns a greeting."""
+        message = "Hello, " + name
+        return message
+
+    def documented(self):
+        """Only a docstring."""
+
+def inline(x): return x + 1
+"#;
+        assert_eq!(
+            skeleton_of("greeter.py", content).unwrap(),
+            r#"class Greeter:
+    """Greets people."""
+
+    def greet(self, name):
+        """Returns a greeting."""
+        ...
+
+    def documented(self):
+        """Only a docstring."""
+
+def inline(x): ...
+"#
+        );
+    }
+
+    #[test]
+    fn typescript_arrow_functions_with_blocks_are_elided() {
+        let content = r#"export const double = (x: number): number => x * 2;
+export const log = (message: string) => {
+    console.log(message);
+};
+class Counter {
+    count = 0;
+    increment(): void {
+        this.count++;
+    }
+}
+"#;
+        let expected = r#"export const double = (x: number): number => x * 2;
+export const log = (message: string) => { ... };
+class Counter {
+    count = 0;
+    increment(): void { ... }
+}
+"#;
+        assert_eq!(skeleton_of("counter.ts", content).unwrap(), expected);
+        let javascript = content.replace(": number", "").replace(": string", "").replace(": void", "");
+        let expected = expected.replace(": number", "").replace(": string", "").replace(": void", "");
+        assert_eq!(skeleton_of("counter.js", &javascript).unwrap(), expected);
+    }
+
+    #[test]
+    fn go_methods_and_functions_are_elided() {
+        let content = r#"package main
+
+// Counter counts.
+type Counter struct {
+	count int
+}
+
+// Increment adds one.
+func (c *Counter) Increment() {
+	c.count++
+}
+
+func main() {
+	c := &Counter{}
+	c.Increment()
+}
+"#;
+        assert_eq!(
+            skeleton_of("main.go", content).unwrap(),
+            r#"package main
+
+// Counter counts.
+type Counter struct {
+	count int
+}
+
+// Increment adds one.
+func (c *Counter) Increment() { ... }
+
+func main() { ... }
+"#
+        );
+    }
+
+    #[test]
+    fn syntax_errors_and_unsupported_languages_are_sent_whole() {
+        let mut diagnostics = Vec::new();
+        let content = b"fn broken( {\n    1 +\n}\n";
+        assert_eq!(skeleton(Path::new("broken.rs"), content, &mut diagnostics), None);
+        assert_eq!(diagnostics, [Diagnostic::SkeletonFailed(Path::new("broken.rs").to_path_buf())]);
+        let transforms = crate::transform::Transforms {
+            skeleton: true,
+            ..Default::default()
+        };
+        assert_eq!(transforms.apply(Path::new("broken.rs"), content, &mut diagnostics), content);
+
+        assert_eq!(skeleton_of("README.md", "# Title\n"), None);
+        assert_eq!(skeleton_of("Makefile", "all:\n\ttrue\n"), None);
+    }
+}
@@ -0,0 +1,557 @@
+use crate::budget;
+use crate::config::AppConfig;
+use crate::diagnostic::Diagnostic;
+use crate::error::Error;
+use crate::file_processor::{CategoryData, ContentMode, FileData};
+use crate::format::{Formatter, OutputFormat};
+use crate::output::{self, OutputSections};
+use crate::size_limit;
+use crate::tokenizer::Tokenizer;
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Chunk files are numbered with three digits, which `AppConfig::exclude_split_chunks` relies on.
+pub const MAX_CHUNKS: usize = 999;
+
+/// Maximum size of one output chunk (`--split`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SplitLimit {
+    Bytes(usize),
+    Tokens(usize),
+}
+
+impl FromStr for SplitLimit {
+    type Err = String;
+
+    /// Parses `200000`, `200000b` or `200k` as bytes and `50000t` or `50kt` as tokens.
+    /// `k` and `m` multiply by 1,000 and 1,000,000.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lower = s.trim().to_ascii_lowercase();
+        let (number, tokens) = if let Some(number) = lower.strip_suffix('t') {
+            (number, true)
+        } else {
+            (lower.strip_suffix('b').unwrap_or(&lower), false)
+        };
+        let (number, multiplier) = if let Some(number) = number.strip_suffix('k') {
+            (number, 1_000)
+        } else if let Some(number) = number.strip_suffix('m') {
+            (number, 1_000_000)
+        } else {
+            (number, 1)
+        };
+        let value = number
+            .parse::<usize>()
+            .ok()
+            .and_then(|value| value.checked_mul(multiplier))
+            .filter(|&value| value > 0)
+            .ok_or_else(|| {
+                format!(
+                    "Invalid split limit \"{}\" (expected e.g. \"200000\", \"200k\" for bytes or \"50kt\" for tokens)",
+                    s
+                )
+            })?;
+        Ok(if tokens {
+            SplitLimit::Tokens(value)
+        } else {
+            SplitLimit::Bytes(value)
+        })
+    }
+}
+
+/// One output chunk: its files and sections.
+pub struct Chunk {
+    pub categories_data: Vec<CategoryData>,
+    pub sections: OutputSections,
+}
+
+/// Greedy assignment of files to chunks, measured in the unit of the split limit.
+struct Planner {
+    limit: SplitLimit,
+    tokenizer: Tokenizer,
+    format: OutputFormat,
+    formatter: Box<dyn Formatter>,
+    /// Space per chunk for categories and files, after the document framing.
+    capacity: usize,
+    chunks: Vec<Vec<CategoryData>>,
+    used: usize,
+}
+
+impl Planner {
+    fn measure(&self, rendered: &[u8]) -> usize {
+        match self.limit {
+            SplitLimit::Bytes(_) => rendered.len(),
+            SplitLimit::Tokens(_) => self.tokenizer.count_bytes(rendered),
+        }
+    }
+
+    /// Size of file content as written, including escaping for formats without raw content.
+    fn measure_content(&mut self, content: &[u8]) -> usize {
+        match self.limit {
+            SplitLimit::Bytes(_) if !self.formatter.raw_content() => {
+                let mut rendered = Vec::with_capacity(content.len());
+                let _ = self.formatter.write_content(&mut rendered, content);
+                rendered.len()
+            }
+            _ => self.measure(content),
+        }
+    }
+
+    fn file_cost(&mut self, category_data: &CategoryData, file_data: &FileData) -> Result<usize, String> {
+        let framing = self.measure(&budget::render_file_framing(self.format, category_data, file_data));
+        let content = match (&file_data.content, self.limit) {
+            (ContentMode::ListOnly, _) => 0,
+            (_, SplitLimit::Tokens(_)) => file_data.tokens.unwrap_or(0),
+            (ContentMode::Full, SplitLimit::Bytes(_)) if !self.formatter.raw_content() => {
+                let content = read(file_data)?;
+                self.measure_content(&content)
+            }
+            (ContentMode::Full, SplitLimit::Bytes(_)) => file_data.size as usize,
+            (ContentMode::Transformed(content), SplitLimit::Bytes(_)) if !self.formatter.raw_content() => {
+                self.measure_content(content)
+            }
+            (ContentMode::Transformed(content), SplitLimit::Bytes(_)) => content.len(),
+            (ContentMode::Truncated { head, tail }, SplitLimit::Bytes(_)) => (head + tail) as usize,
+            (ContentMode::Range { len, .. }, SplitLimit::Bytes(_)) => *len as usize,
+        };
+        Ok(framing + content)
+    }
+
+    /// Cost of opening `category_data` in the current chunk (0 if it's already open).
+    fn category_cost(&self, category_data: &CategoryData) -> usize {
+        let current = self.chunks.last().expect("at least one chunk");
+        if current.last().is_some_and(|c| c.name == category_data.name) {
+            0
+        } else {
+            self.measure(&budget::render_category_framing(self.format, category_data))
+        }
+    }
+
+    fn start_chunk(&mut self) {
+        self.chunks.push(Vec::new());
+        self.used = 0;
+    }
+
+    fn push(&mut self, category_data: &CategoryData, file_data: FileData, cost: usize) {
+        self.used += cost;
+        let current = self.chunks.last_mut().expect("at least one chunk");
+        if current.last().is_none_or(|c| c.name != category_data.name) {
+            current.push(CategoryData {
+                name: category_data.name.clone(),
+                description_text: category_data.description_text.clone(),
+                files: Vec::new(),
+                total_size: 0,
+                position: category_data.position,
+            });
+        }
+        let category = current.last_mut().expect("category was just pushed");
+        category.total_size += file_data.size;
+        category.files.push(file_data);
+    }
+
+    /// Splits a file that doesn't fit into a chunk on its own into byte ranges, each filling a chunk.
+    ///
+    /// Business Logic Constraint: Pieces end at line breaks where possible; a line that doesn't fit
+    /// is cut at a UTF-8 character boundary. In token mode, lines are measured one by one, so the
+    /// pieces are an approximation.
+    fn split_file(&mut self, category_data: &CategoryData, file_data: &FileData) -> Result<(), String> {
+        let content = read(file_data)?;
+        // The range marker is largest for the largest offsets.
+        let widest_piece = FileData {
+            content: ContentMode::Range {
+                offset: file_data.size,
+                len: file_data.size,
+            },
+            ..file_data.clone()
+        };
+        let framing = self.measure(&budget::render_file_framing(self.format, category_data, &widest_piece))
+            + self.measure(&budget::render_category_framing(self.format, category_data));
+        let piece_capacity = self.capacity.saturating_sub(framing).max(1);
+
+        let mut start = 0;
+        while start < content.len() {
+            let mut end = start;
+            let mut piece_cost = 0;
+            for line in content[start..].split_inclusive(|&b| b == b'\n') {
+                let line_cost = self.measure_content(line);
+                if piece_cost + line_cost > piece_capacity {
+                    break;
+                }
+                piece_cost += line_cost;
+                end += line.len();
+            }
+            if end == start {
+                // Tokens never cover less than a byte, so `piece_capacity` bytes also fit in tokens.
+                let window = &content[start..(start + piece_capacity).min(content.len())];
+                end = start + size_limit::complete_prefix_len(window).max(1);
+            }
+
+            let mut piece = FileData {
+                content: ContentMode::Range {
+                    offset: start as u64,
+                    len: (end - start) as u64,
+                },
+                ..file_data.clone()
+            };
+            if let SplitLimit::Tokens(_) = self.limit {
+                piece.tokens = Some(self.tokenizer.count_bytes(&content[start..end]));
+            }
+            if self.used > 0 || !self.chunks.last().expect("at least one chunk").is_empty() {
+                self.start_chunk();
+            }
+            let cost = self.category_cost(category_data) + self.file_cost(category_data, &piece)?;
+            self.push(category_data, piece, cost);
+            start = end;
+        }
+        Ok(())
+    }
+}
+
+fn read(file_data: &FileData) -> Result<Vec<u8>, String> {
+    fs::read(&file_data.absolute_path)
+        .map_err(|e| format!("Failed to read file {:?}: {}", file_data.absolute_path, e))
+}
+
+/// Distributes the files over chunks of at most `limit`, in output order.
+///
+/// Business Logic Constraint: Every chunk is a complete document with a "part i of n" header. The
+/// tree goes into the first chunk; the diff and the task go into the last one, which gets a chunk
+/// of its own if they don't fit after the files.
+/// Business Logic Constraint: Files are never split, unless a file alone exceeds the limit; such a
+/// file is split into byte ranges (see `Planner::split_file`). Truncated and list-only files are
+/// never split; one over the limit on its own is reported in `diagnostics`.
+/// Business Logic Constraint: More than `MAX_CHUNKS` chunks is an error.
+pub fn plan_chunks(
+    categories_data: &[CategoryData],
+    sections: &OutputSections,
+    config: &AppConfig,
+    limit: SplitLimit,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Result<Vec<Chunk>, Error> {
+    let format = config.format;
+    let mut rendered = Vec::new();
+    let mut formatter = format.formatter();
+    let _ = formatter.begin_document(&mut rendered, categories_data);
+    let _ = formatter.part(&mut rendered, 999, 999);
+    let _ = formatter.end_document(&mut rendered);
+
+    let mut planner = Planner {
+        limit,
+        tokenizer: config.tokenizer,
+        format,
+        formatter: format.formatter(),
+        capacity: 0,
+        chunks: vec![Vec::new()],
+        used: 0,
+    };
+    let limit_value = match limit {
+        SplitLimit::Bytes(value) | SplitLimit::Tokens(value) => value,
+    };
+    let document_overhead = planner.measure(&rendered);
+    planner.capacity = limit_value.checked_sub(document_overhead).filter(|&c| c > 0).ok_or_else(|| {
+        Error::Split(format!(
+            "Split limit of {} is too small for the document framing ({})",
+            limit_value, document_overhead
+        ))
+    })?;
+
+    let section_cost = |tree: Option<&String>, diff: Option<&String>, task: Option<&String>| {
+        let mut formatter = format.formatter();
+        let mut rendered = Vec::new();
+        if let Some(tree) = tree {
+            let _ = formatter.tree(&mut rendered, tree);
+        }
+        if let Some(diff) = diff {
+            let _ = formatter.diff(&mut rendered, diff);
+        }
+        if let Some(task) = task {
+            let _ = formatter.task(&mut rendered, task);
+        }
+        let _ = formatter.end_document(&mut rendered);
+        rendered
+    };
+    planner.used = planner.measure(&section_cost(sections.tree.as_ref(), None, None));
+
+    for category_data in categories_data {
+        for file_data in &category_data.files {
+            let cost = planner.file_cost(category_data, file_data).map_err(Error::Files)?;
+            if planner.used + planner.category_cost(category_data) + cost <= planner.capacity {
+                let cost = cost + planner.category_cost(category_data);
+                planner.push(category_data, file_data.clone(), cost);
+                continue;
+            }
+            let alone_cost = cost + planner.measure(&budget::render_category_framing(format, category_data));
+            if alone_cost <= planner.capacity || !matches!(file_data.content, ContentMode::Full) {
+                if alone_cost > planner.capacity {
+                    diagnostics.push(Diagnostic::ChunkOverLimit(file_data.relative_path.clone()));
+                }
+                if !planner.chunks.last().expect("at least one chunk").is_empty() {
+                    planner.start_chunk();
+                }
+                planner.push(category_data, file_data.clone(), alone_cost);
+                continue;
+            }
+            planner.split_file(category_data, file_data).map_err(Error::Files)?;
+        }
+    }
+
+    if sections.diff.is_some() || sections.task.is_some() {
+        let tail_cost = planner.measure(&section_cost(None, sections.diff.as_ref(), sections.task.as_ref()));
+        if planner.used + tail_cost > planner.capacity && planner.used > 0 {
+            planner.start_chunk();
+        }
+    }
+
+    let count = planner.chunks.len();
+    if count > MAX_CHUNKS {
+        return Err(Error::Split(format!(
+            "The output needs {} chunks, more than the {} supported. Use a higher split limit.",
+            count, MAX_CHUNKS
+        )));
+    }
+    Ok(planner
+        .chunks
+        .into_iter()
+        .enumerate()
+        .map(|(index, categories_data)| Chunk {
+            categories_data,
+            sections: OutputSections {
+                part: Some((index + 1, count)),
+                tree: if index == 0 { sections.tree.clone() } else { None },
+                diff: if index + 1 == count { sections.diff.clone() } else { None },
+                task: if index + 1 == count { sections.task.clone() } else { None },
+            },
+        })
+        .collect())
+}
+
+/// Writes the chunks to `kek-001.<ext>`, `kek-002.<ext>`, ... in `dir` and returns their paths.
+///
+/// Business Logic Constraint: The directory is created if needed. Every chunk is replaced
+/// atomically (see `output::write_output_file`). Chunk files left over from an earlier run with more
+/// chunks are removed and reported in `diagnostics`, so the directory always holds exactly one set.
+pub fn write_chunks(
+    chunks: &[Chunk],
+    dir: &Path,
+    format: OutputFormat,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Result<Vec<PathBuf>, Error> {
+    fs::create_dir_all(dir)
+        .map_err(|e| io::Error::new(e.kind(), format!("Failed to create directory {:?}: {}", dir, e)))?;
+    let extension = format.extension();
+
+    let mut paths = Vec::with_capacity(chunks.len());
+    for (index, chunk) in chunks.iter().enumerate() {
+        let path = dir.join(format!("kek-{:03}.{}", index + 1, extension));
+        output::write_output_file(&path, &chunk.categories_data, &chunk.sections, format)
+            .map_err(|e| io::Error::new(e.kind(), format!("Failed to write {:?}: {}", path, e)))?;
+        paths.push(path);
+    }
+
+    for stale_index in chunks.len() + 1.. {
+        let stale = dir.join(format!("kek-{:03}.{}", stale_index, extension));
+        if fs::remove_file(&stale).is_err() {
+            break;
+        }
+        diagnostics.push(Diagnostic::RemovedStaleChunk(stale));
+    }
+
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::sync::Arc;
+
+    /// Writes `content` to a file in the temporary directory, removed when dropped.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, content: &[u8]) -> TempFile {
+            let path = std::env::temp_dir().join(format!("kek-split-{}-{}", std::process::id(), name));
+            fs::write(&path, content).unwrap();
+            TempFile(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn transformed(path: &str, content: &[u8]) -> FileData {
+        FileData {
+            relative_path: PathBuf::from(path),
+            absolute_path: PathBuf::from("/nonexistent").join(path),
+            size: content.len() as u64,
+            tokens: None,
+            content: ContentMode::Transformed(Arc::from(content)),
+            boundary: None,
+            priority: 0,
+        }
+    }
+
+    fn category(name: &str, files: Vec<FileData>) -> CategoryData {
+        CategoryData {
+            name: name.to_string(),
+            description_text: format!("Files in {}.", name),
+            total_size: files.iter().map(|f| f.size).sum(),
+            files,
+            position: 0,
+        }
+    }
+
+    fn render(chunk: &Chunk, format: OutputFormat) -> String {
+        let (mut file, _) = output::write_output_memfd(&chunk.categories_data, &chunk.sections, format).unwrap();
+        let mut rendered = String::new();
+        file.read_to_string(&mut rendered).unwrap();
+        rendered
+    }
+
+    fn paths(chunk: &Chunk) -> Vec<&str> {
+        chunk
+            .categories_data
+            .iter()
+            .flat_map(|c| &c.files)
+            .map(|f| f.relative_path.to_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn parses_split_limits() {
+        assert_eq!("200000".parse(), Ok(SplitLimit::Bytes(200_000)));
+        assert_eq!("200k".parse(), Ok(SplitLimit::Bytes(200_000)));
+        assert_eq!("2MB".parse(), Ok(SplitLimit::Bytes(2_000_000)));
+        assert_eq!("50kt".parse(), Ok(SplitLimit::Tokens(50_000)));
+        assert_eq!(" 50T ".parse(), Ok(SplitLimit::Tokens(50)));
+        for invalid in ["0", "k", "-5", "1.5k", "12x"] {
+            assert!(invalid.parse::<SplitLimit>().is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn files_fill_chunks_in_order_with_sections_at_the_ends() {
+        let body = vec![b'x'; 300];
+        let categories_data = vec![
+            category("docs", vec![transformed("a.md", &body), transformed("b.md", &body)]),
+            category("src", vec![transformed("c.rs", &body), transformed("d.rs", &body)]),
+        ];
+        let sections = OutputSections {
+            part: None,
+            tree: Some(". (4 files)\n".to_string()),
+            diff: None,
+            task: Some("Fix the bug.".to_string()),
+        };
+        let config = AppConfig::default();
+        let limit = 1000;
+        let mut diagnostics = Vec::new();
+        let chunks = plan_chunks(&categories_data, &sections, &config, SplitLimit::Bytes(limit), &mut diagnostics)
+            .unwrap();
+
+        assert!(diagnostics.is_empty());
+        assert_eq!(
+            chunks.iter().map(paths).collect::<Vec<_>>(),
+            [vec!["a.md", "b.md"], vec!["c.rs", "d.rs"]]
+        );
+        for (index, chunk) in chunks.iter().enumerate() {
+            assert_eq!(chunk.sections.part, Some((index + 1, 2)));
+            assert!(render(chunk, config.format).len() <= limit);
+        }
+        assert!(chunks[0].sections.tree.is_some() && chunks[1].sections.tree.is_none());
+        assert!(chunks[0].sections.task.is_none() && chunks[1].sections.task.is_some());
+        assert_eq!(chunks[1].categories_data[0].total_size, 600);
+    }
+
+    #[test]
+    fn oversized_files_are_split_at_line_breaks() {
+        let content: Vec<u8> = (0..200).flat_map(|i| format!("line {:03}\n", i).into_bytes()).collect();
+        let file = TempFile::new("oversized", &content);
+        let file_data = FileData {
+            relative_path: PathBuf::from("big.txt"),
+            absolute_path: file.0.clone(),
+            size: content.len() as u64,
+            tokens: None,
+            content: ContentMode::Full,
+            boundary: None,
+            priority: 0,
+        };
+        let categories_data = vec![category("other", vec![file_data])];
+        let config = AppConfig::default();
+        let limit = 700;
+        let chunks = plan_chunks(
+            &categories_data,
+            &OutputSections::default(),
+            &config,
+            SplitLimit::Bytes(limit),
+            &mut Vec::new(),
+        )
+        .unwrap();
+
+        assert!(chunks.len() > 1);
+        let mut next_offset = 0;
+        for chunk in &chunks {
+            let [piece] = chunk.categories_data[0].files.as_slice() else {
+                panic!("one piece per chunk");
+            };
+            let ContentMode::Range { offset, len } = piece.content else {
+                panic!("pieces are ranges");
+            };
+            assert_eq!(offset, next_offset);
+            assert_eq!(content[(offset + len - 1) as usize], b'\n');
+            next_offset = offset + len;
+            assert!(render(chunk, config.format).len() <= limit);
+        }
+        assert_eq!(next_offset, content.len() as u64);
+    }
+
+    #[test]
+    fn unsplittable_files_are_reported() {
+        let categories_data = vec![category("src", vec![transformed("a.rs", &[b'x'; 2000])])];
+        let mut diagnostics = Vec::new();
+        let chunks = plan_chunks(
+            &categories_data,
+            &OutputSections::default(),
+            &AppConfig::default(),
+            SplitLimit::Bytes(500),
+            &mut diagnostics,
+        )
+        .unwrap();
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(diagnostics, [Diagnostic::ChunkOverLimit(PathBuf::from("a.rs"))]);
+    }
+
+    #[test]
+    fn limit_below_the_document_framing_is_an_error() {
+        let result = plan_chunks(
+            &[],
+            &OutputSections::default(),
+            &AppConfig::default(),
+            SplitLimit::Bytes(10),
+            &mut Vec::new(),
+        );
+        assert!(matches!(result, Err(Error::Split(_))));
+    }
+
+    #[test]
+    fn more_chunks_than_numbers_is_an_error() {
+        let plan = |file_count: usize| {
+            let files = (0..file_count)
+                .map(|index| transformed(&format!("{:04}.rs", index), &[b'x'; 300]))
+                .collect();
+            plan_chunks(
+                &[category("src", files)],
+                &OutputSections::default(),
+                &AppConfig::default(),
+                SplitLimit::Bytes(500),
+                &mut Vec::new(),
+            )
+        };
+        assert_eq!(plan(MAX_CHUNKS).unwrap().len(), MAX_CHUNKS);
+        assert!(matches!(plan(MAX_CHUNKS + 1), Err(Error::Split(_))));
+    }
+}
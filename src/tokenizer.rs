@@ -0,0 +1,61 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use tiktoken_rs::{CoreBPE, cl100k_base_singleton, o200k_base_singleton};
+
+/// Embedded BPE tokenizers used for token counting.
+///
+/// Business Logic Constraint: Token counts are estimates for budgeting purposes. Text is encoded
+/// without special tokens, so sequences like `<|endoftext|>` inside files count as plain text.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Tokenizer {
+    /// The `cl100k_base` encoding (GPT-3.5 / GPT-4).
+    #[default]
+    Cl100k,
+    /// The `o200k_base` encoding (GPT-4o and later).
+    O200k,
+}
+
+impl Tokenizer {
+    fn bpe(&self) -> &'static CoreBPE {
+        match self {
+            Tokenizer::Cl100k => cl100k_base_singleton(),
+            Tokenizer::O200k => o200k_base_singleton(),
+        }
+    }
+
+    /// Counts the tokens in `text`.
+    pub fn count(&self, text: &str) -> usize {
+        self.bpe().encode_ordinary(text).len()
+    }
+
+    /// Counts the tokens in `bytes`, replacing invalid UTF-8 sequences.
+    pub fn count_bytes(&self, bytes: &[u8]) -> usize {
+        self.count(&String::from_utf8_lossy(bytes))
+    }
+}
+
+impl FromStr for Tokenizer {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "cl100k" | "cl100k_base" => Ok(Tokenizer::Cl100k),
+            "o200k" | "o200k_base" => Ok(Tokenizer::O200k),
+            _ => Err(format!(
+                "Unknown tokenizer \"{}\" (expected \"cl100k\" or \"o200k\")",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Tokenizer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Tokenizer::Cl100k => f.write_str("cl100k"),
+            Tokenizer::O200k => f.write_str("o200k"),
+        }
+    }
+}
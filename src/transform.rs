@@ -0,0 +1,632 @@
+use crate::diagnostic::Diagnostic;
+
+use globset::GlobSet;
+use std::path::Path;
+
+/// Content transforms applied to a file before it is written (`[[transform]]` in kek.toml,
+/// `--minify`). Files with transforms are held in memory instead of being streamed with `sendfile`,
+/// so files none apply to keep the fast path.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Transforms {
+    /// Removes comments, for languages whose comment syntax is known (see `syntax_for`).
+    pub strip_comments: bool,
+    /// Collapses runs of blank lines into one and removes blank lines at the start and the end.
+    pub collapse_blank_lines: bool,
+    /// Removes spaces and tabs at the end of lines.
+    pub trim_trailing_whitespace: bool,
+    /// Replaces function bodies with a marker, keeping only signatures, type definitions and doc
+    /// comments (see `skeleton::skeleton`).
+    pub skeleton: bool,
+}
+
+impl Transforms {
+    /// The comment and whitespace transforms, as applied by `--minify`.
+    pub const MINIFY: Transforms = Transforms {
+        strip_comments: true,
+        collapse_blank_lines: true,
+        trim_trailing_whitespace: true,
+        skeleton: false,
+    };
+
+    pub fn is_empty(&self) -> bool {
+        *self == Transforms::default()
+    }
+
+    /// Returns the transforms of both `self` and `other`.
+    pub fn union(self, other: Transforms) -> Transforms {
+        Transforms {
+            strip_comments: self.strip_comments || other.strip_comments,
+            collapse_blank_lines: self.collapse_blank_lines || other.collapse_blank_lines,
+            trim_trailing_whitespace: self.trim_trailing_whitespace || other.trim_trailing_whitespace,
+            skeleton: self.skeleton || other.skeleton,
+        }
+    }
+
+    /// Applies the transforms to the content of the file at `path`.
+    ///
+    /// Business Logic Constraint: The skeleton is made first, from the file as written. Comments are
+    /// stripped next, so lines that only held a comment disappear and the whitespace transforms see
+    /// the result. The transforms don't know about
+    /// multi-line string literals; whitespace inside them is changed like any other. A file that
+    /// doesn't parse for its skeleton is reported in `diagnostics`.
+    pub fn apply(&self, path: &Path, content: &[u8], diagnostics: &mut Vec<Diagnostic>) -> Vec<u8> {
+        let mut content = content.to_vec();
+        #[cfg(not(feature = "skeleton"))]
+        let _ = diagnostics;
+        #[cfg(feature = "skeleton")]
+        if self.skeleton
+            && let Some(skeleton) = crate::skeleton::skeleton(path, &content, diagnostics)
+        {
+            content = skeleton;
+        }
+        if self.strip_comments
+            && let Some(syntax) = syntax_for(path)
+        {
+            content = strip_comments(&content, syntax);
+        }
+        if self.trim_trailing_whitespace {
+            content = trim_trailing_whitespace(&content);
+        }
+        if self.collapse_blank_lines {
+            content = collapse_blank_lines(&content);
+        }
+        content
+    }
+}
+
+/// Transforms from the `[[transform]]` entries, compiled into a single glob set.
+/// `transforms[i]` are the transforms of the i-th glob in `globs`.
+#[derive(Debug, Clone)]
+pub struct TransformRules {
+    pub globs: GlobSet,
+    pub transforms: Vec<Transforms>,
+}
+
+impl TransformRules {
+    /// Returns the transforms for the file at `relative_path`.
+    ///
+    /// Business Logic Constraint: If several entries match, all of their transforms apply.
+    pub fn transforms_for(&self, relative_path: &Path) -> Transforms {
+        self.globs
+            .matches(relative_path)
+            .into_iter()
+            .fold(Transforms::default(), |transforms, index| {
+                transforms.union(self.transforms[index])
+            })
+    }
+}
+
+/// A kind of string literal: comment markers inside it are left alone.
+struct StringDelimiter {
+    open: &'static str,
+    close: &'static str,
+    /// Whether a backslash escapes the next character.
+    escapes: bool,
+    /// Whether the literal can span lines. Single-line literals end at a line break even without
+    /// their closing delimiter, so an unmatched quote (e.g. a C++ digit separator) can't hide the
+    /// rest of the file.
+    multiline: bool,
+}
+
+const fn delimiter(quote: &'static str, escapes: bool, multiline: bool) -> StringDelimiter {
+    StringDelimiter {
+        open: quote,
+        close: quote,
+        escapes,
+        multiline,
+    }
+}
+
+/// Comment and string literal syntax of a language family.
+struct Syntax {
+    line_comments: &'static [&'static str],
+    block_comment: Option<(&'static str, &'static str)>,
+    /// Whether block comments nest, as in Rust, Swift and Kotlin.
+    nested_block_comments: bool,
+    /// String literals, longest opening delimiter first.
+    strings: &'static [StringDelimiter],
+    /// Rust raw strings (`r#"..."#`) and lifetimes, which start with a quote but aren't literals.
+    rust_literals: bool,
+    /// JavaScript regular expression literals, which may contain quotes and slashes.
+    regex_literals: bool,
+    /// Keeps a `#!` line at the start of the file, where `#` starts comments.
+    shebang: bool,
+}
+
+const C: Syntax = Syntax {
+    line_comments: &["//"],
+    block_comment: Some(("/*", "*/")),
+    nested_block_comments: false,
+    strings: &[delimiter("\"", true, false), delimiter("'", true, false)],
+    rust_literals: false,
+    regex_literals: false,
+    shebang: false,
+};
+
+const JAVA: Syntax = Syntax {
+    strings: &[
+        delimiter("\"\"\"", true, true),
+        delimiter("\"", true, false),
+        delimiter("'", true, false),
+    ],
+    ..C
+};
+
+const C_SHARP: Syntax = Syntax {
+    strings: &[
+        StringDelimiter {
+            open: "@\"",
+            close: "\"",
+            escapes: false,
+            multiline: true,
+        },
+        delimiter("\"", true, false),
+        delimiter("'", true, false),
+    ],
+    ..C
+};
+
+const KOTLIN: Syntax = Syntax {
+    nested_block_comments: true,
+    strings: &[
+        delimiter("\"\"\"", false, true),
+        delimiter("\"", true, false),
+        delimiter("'", true, false),
+    ],
+    ..C
+};
+
+const SWIFT: Syntax = Syntax {
+    nested_block_comments: true,
+    strings: &[delimiter("\"\"\"", true, true), delimiter("\"", true, false)],
+    ..C
+};
+
+const DART: Syntax = Syntax {
+    nested_block_comments: true,
+    strings: &[
+        delimiter("\"\"\"", true, true),
+        delimiter("'''", true, true),
+        delimiter("\"", true, false),
+        delimiter("'", true, false),
+    ],
+    ..C
+};
+
+const GO: Syntax = Syntax {
+    strings: &[
+        delimiter("`", false, true),
+        delimiter("\"", true, false),
+        delimiter("'", true, false),
+    ],
+    ..C
+};
+
+const JAVASCRIPT: Syntax = Syntax {
+    strings: &[
+        delimiter("`", true, true),
+        delimiter("\"", true, false),
+        delimiter("'", true, false),
+    ],
+    regex_literals: true,
+    ..C
+};
+
+const RUST: Syntax = Syntax {
+    nested_block_comments: true,
+    strings: &[delimiter("\"", true, true)],
+    rust_literals: true,
+    ..C
+};
+
+/// Only block comments: `//` is not a comment in CSS and appears in unquoted URLs.
+const CSS: Syntax = Syntax {
+    line_comments: &[],
+    ..C
+};
+
+const PYTHON: Syntax = Syntax {
+    line_comments: &["#"],
+    block_comment: None,
+    nested_block_comments: false,
+    strings: &[
+        delimiter("\"\"\"", true, true),
+        delimiter("'''", true, true),
+        delimiter("\"", true, false),
+        delimiter("'", true, false),
+    ],
+    rust_literals: false,
+    regex_literals: false,
+    shebang: true,
+};
+
+const TOML: Syntax = Syntax {
+    strings: &[
+        delimiter("\"\"\"", true, true),
+        delimiter("'''", false, true),
+        delimiter("\"", true, false),
+        delimiter("'", false, false),
+    ],
+    shebang: false,
+    ..PYTHON
+};
+
+const R: Syntax = Syntax {
+    strings: &[delimiter("\"", true, true), delimiter("'", true, true)],
+    shebang: false,
+    ..PYTHON
+};
+
+const SQL: Syntax = Syntax {
+    line_comments: &["--"],
+    strings: &[delimiter("'", false, true), delimiter("\"", false, true)],
+    ..C
+};
+
+const MARKUP: Syntax = Syntax {
+    line_comments: &[],
+    block_comment: Some(("<!--", "-->")),
+    strings: &[],
+    ..C
+};
+
+/// Returns the comment syntax for the file at `path` by its extension, or `None` if comments
+/// can't be stripped safely (e.g. shell scripts, whose here-documents look like code).
+fn syntax_for(path: &Path) -> Option<&'static Syntax> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    let syntax = match extension.as_str() {
+        "c" | "h" | "cc" | "cpp" | "cxx" | "hpp" | "hh" | "hxx" | "proto" => &C,
+        "java" => &JAVA,
+        "cs" => &C_SHARP,
+        "kt" | "kts" | "scala" => &KOTLIN,
+        "swift" => &SWIFT,
+        "dart" => &DART,
+        "go" => &GO,
+        "js" | "jsx" | "mjs" | "cjs" | "ts" | "tsx" | "mts" | "cts" => &JAVASCRIPT,
+        "rs" => &RUST,
+        "css" | "scss" | "less" => &CSS,
+        "py" | "pyi" => &PYTHON,
+        "toml" => &TOML,
+        "r" => &R,
+        "sql" => &SQL,
+        "html" | "htm" | "xml" | "svg" => &MARKUP,
+        _ => return None,
+    };
+    Some(syntax)
+}
+
+fn is_identifier_byte(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || byte == b'_' || byte >= 0x80
+}
+
+fn is_blank(byte: u8) -> bool {
+    matches!(byte, b' ' | b'\t')
+}
+
+/// Returns the length of the comment at the start of `rest`, if one starts there. Line comments
+/// end before the line break (including a `\r` before it).
+fn comment_len(rest: &[u8], syntax: &Syntax) -> Option<usize> {
+    if syntax.line_comments.iter().any(|marker| rest.starts_with(marker.as_bytes())) {
+        let end = rest.iter().position(|&b| b == b'\n').unwrap_or(rest.len());
+        return Some(if end > 0 && rest[end - 1] == b'\r' { end - 1 } else { end });
+    }
+    let (open, close) = syntax.block_comment?;
+    if !rest.starts_with(open.as_bytes()) {
+        return None;
+    }
+    let mut depth = 0;
+    let mut i = 0;
+    while i < rest.len() {
+        if rest[i..].starts_with(open.as_bytes()) && (depth == 0 || syntax.nested_block_comments) {
+            depth += 1;
+            i += open.len();
+        } else if rest[i..].starts_with(close.as_bytes()) {
+            depth -= 1;
+            i += close.len();
+            if depth == 0 {
+                return Some(i);
+            }
+        } else {
+            i += 1;
+        }
+    }
+    Some(rest.len())
+}
+
+/// Returns the length of the string literal (or other literal that may contain comment markers)
+/// at the start of `rest`, if one starts there. `preceding` is the output so far.
+fn literal_len(rest: &[u8], preceding: &[u8], syntax: &Syntax) -> Option<usize> {
+    if syntax.rust_literals
+        && let Some(len) = rust_literal_len(rest, preceding)
+    {
+        return Some(len);
+    }
+    for string in syntax.strings {
+        if !rest.starts_with(string.open.as_bytes()) {
+            continue;
+        }
+        let mut i = string.open.len();
+        while i < rest.len() {
+            if string.escapes && rest[i] == b'\\' {
+                i += 2;
+            } else if rest[i..].starts_with(string.close.as_bytes()) {
+                return Some(i + string.close.len());
+            } else if rest[i] == b'\n' && !string.multiline {
+                return Some(i);
+            } else {
+                i += 1;
+            }
+        }
+        return Some(rest.len());
+    }
+    if syntax.regex_literals && rest[0] == b'/' && regex_allowed(preceding) {
+        return regex_len(rest);
+    }
+    None
+}
+
+/// Handles the Rust literals that the generic string handling can't: raw strings (`r"..."`,
+/// `br#"..."#`) and character literals, which must be told apart from lifetimes (`'a`).
+fn rust_literal_len(rest: &[u8], preceding: &[u8]) -> Option<usize> {
+    if rest[0] == b'\'' {
+        let len = if rest.get(1) == Some(&b'\\') {
+            // An escape: '\n', '\'', '\x7f' or '\u{1F980}'.
+            rest.iter().skip(3).take(10).position(|&b| b == b'\'').map(|end| end + 4)
+        } else {
+            let char_len = rest
+                .get(1..)
+                .and_then(|tail| tail.utf8_chunks().next())
+                .and_then(|chunk| chunk.valid().chars().next())
+                .map_or(1, char::len_utf8);
+            (rest.get(1 + char_len) == Some(&b'\'')).then_some(char_len + 2)
+        };
+        // Not a character literal: a lifetime or label, which is copied as code.
+        return Some(len.unwrap_or(1));
+    }
+
+    if preceding.last().is_some_and(|&b| is_identifier_byte(b)) {
+        return None;
+    }
+    let prefix = if rest.starts_with(b"br") {
+        2
+    } else if rest[0] == b'r' {
+        1
+    } else {
+        return None;
+    };
+    let hashes = rest[prefix..].iter().take_while(|&&b| b == b'#').count();
+    if rest.get(prefix + hashes) != Some(&b'"') {
+        return None;
+    }
+    let mut terminator = vec![b'"'];
+    terminator.extend(std::iter::repeat_n(b'#', hashes));
+    let body = prefix + hashes + 1;
+    Some(
+        rest[body..]
+            .windows(terminator.len())
+            .position(|window| window == terminator)
+            .map_or(rest.len(), |end| body + end + terminator.len()),
+    )
+}
+
+/// Returns whether a `/` after `preceding` starts a regular expression rather than a division.
+fn regex_allowed(preceding: &[u8]) -> bool {
+    const KEYWORDS: [&[u8]; 13] = [
+        b"return", b"typeof", b"instanceof", b"case", b"do", b"else", b"in", b"of", b"yield", b"await",
+        b"void", b"delete", b"throw",
+    ];
+    let trimmed = preceding.trim_ascii_end();
+    match trimmed.last() {
+        None => true,
+        Some(b')' | b']') => false,
+        Some(&b) if is_identifier_byte(b) || b == b'$' => {
+            let word_start = trimmed
+                .iter()
+                .rposition(|&b| !is_identifier_byte(b) && b != b'$')
+                .map_or(0, |p| p + 1);
+            KEYWORDS.contains(&&trimmed[word_start..])
+        }
+        Some(_) => true,
+    }
+}
+
+/// Returns the length of the regular expression literal at the start of `rest` (without flags),
+/// or `None` if it doesn't end on the same line, so it was a division after all.
+fn regex_len(rest: &[u8]) -> Option<usize> {
+    let mut in_class = false;
+    let mut i = 1;
+    while i < rest.len() {
+        match rest[i] {
+            b'\\' => i += 1,
+            b'[' => in_class = true,
+            b']' => in_class = false,
+            b'/' if !in_class => return Some(i + 1),
+            b'\n' => return None,
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Removes the comment ending at `end` of `content`, whose output up to the comment is `out`.
+/// Returns where to continue in `content`.
+///
+/// Business Logic Constraint: Lines holding nothing but comments are removed with their line
+/// break. Whitespace before a comment at the end of a line goes with it, and whitespace after a
+/// comment at the start of a line or after whitespace. A comment between two tokens is replaced by
+/// a space, so the tokens stay apart (`a/**/b`).
+fn remove_comment(out: &mut Vec<u8>, content: &[u8], end: usize) -> usize {
+    let line_start = out.iter().rposition(|&b| b == b'\n').map_or(0, |p| p + 1);
+    let blank_before = out[line_start..].iter().all(|&b| is_blank(b));
+    let blank_after = content[end..]
+        .iter()
+        .take_while(|&&b| is_blank(b) || b == b'\r')
+        .count();
+    let line_end = end + blank_after;
+    let ends_line = line_end == content.len() || content[line_end] == b'\n';
+
+    if blank_before && ends_line {
+        out.truncate(line_start);
+        return (line_end + 1).min(content.len());
+    }
+    if ends_line {
+        while out.last().is_some_and(|&b| is_blank(b)) {
+            out.pop();
+        }
+        return end;
+    }
+    if blank_before || out.last().is_some_and(|&b| is_blank(b)) {
+        return line_end;
+    }
+    if !content[end].is_ascii_whitespace() {
+        out.push(b' ');
+    }
+    end
+}
+
+/// Removes the comments from `content`, leaving string literals alone.
+fn strip_comments(content: &[u8], syntax: &Syntax) -> Vec<u8> {
+    let mut out = Vec::with_capacity(content.len());
+    let mut i = 0;
+    if syntax.shebang && content.starts_with(b"#!") {
+        i = content.iter().position(|&b| b == b'\n').map_or(content.len(), |p| p + 1);
+        out.extend_from_slice(&content[..i]);
+    }
+    while i < content.len() {
+        let rest = &content[i..];
+        if let Some(len) = comment_len(rest, syntax) {
+            i = remove_comment(&mut out, content, i + len);
+        } else if let Some(len) = literal_len(rest, &out, syntax) {
+            out.extend_from_slice(&rest[..len]);
+            i += len;
+        } else {
+            out.push(content[i]);
+            i += 1;
+        }
+    }
+    out
+}
+
+/// Splits a line into its content and its line break (`\n`, `\r\n` or none for the last line).
+fn split_line_break(line: &[u8]) -> (&[u8], &[u8]) {
+    let break_len = if line.ends_with(b"\r\n") {
+        2
+    } else if line.ends_with(b"\n") {
+        1
+    } else {
+        0
+    };
+    line.split_at(line.len() - break_len)
+}
+
+fn trim_trailing_whitespace(content: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(content.len());
+    for line in content.split_inclusive(|&b| b == b'\n') {
+        let (text, line_break) = split_line_break(line);
+        let trimmed_len = text.iter().rposition(|&b| !is_blank(b)).map_or(0, |p| p + 1);
+        out.extend_from_slice(&text[..trimmed_len]);
+        out.extend_from_slice(line_break);
+    }
+    out
+}
+
+/// Collapses runs of blank lines into one and drops blank lines at the start and the end.
+fn collapse_blank_lines(content: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(content.len());
+    let mut pending_blank: Option<&[u8]> = None;
+    for line in content.split_inclusive(|&b| b == b'\n') {
+        let (text, line_break) = split_line_break(line);
+        if text.iter().all(|&b| is_blank(b)) {
+            if !out.is_empty() {
+                pending_blank.get_or_insert(line_break);
+            }
+            continue;
+        }
+        if let Some(blank) = pending_blank.take() {
+            out.extend_from_slice(blank);
+        }
+        out.extend_from_slice(line);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strip(content: &str, syntax: &Syntax) -> String {
+        String::from_utf8(strip_comments(content.as_bytes(), syntax)).unwrap()
+    }
+
+    #[test]
+    fn comments_between_tokens_leave_a_space() {
+        assert_eq!(strip("a/**/b", &C), "a b");
+        assert_eq!(strip("1 /* c */ + 2", &C), "1 + 2");
+        assert_eq!(strip("f(/* c */x)", &C), "f( x)");
+    }
+
+    #[test]
+    fn comment_only_lines_are_removed() {
+        assert_eq!(strip("// only\nx\n  /* block */  \ny\n", &C), "x\ny\n");
+        assert_eq!(strip("x; // trailing\ny;", &C), "x;\ny;");
+        assert_eq!(strip("x; // trailing\r\ny;\r\n", &C), "x;\r\ny;\r\n");
+        assert_eq!(strip("/* a\n   b */\nz", &C), "z");
+    }
+
+    #[test]
+    fn leading_comments_keep_the_indentation() {
+        assert_eq!(strip("    /* lead */ code();\n", &C), "    code();\n");
+    }
+
+    #[test]
+    fn literals_keep_comment_markers() {
+        assert_eq!(
+            strip("s = \"// not /* a */ comment\"; // real", &C),
+            "s = \"// not /* a */ comment\";"
+        );
+        assert_eq!(strip("let r = /\\/\\//; // c", &JAVASCRIPT), "let r = /\\/\\//;");
+        assert_eq!(strip("x = a / b; // c", &JAVASCRIPT), "x = a / b;");
+        assert_eq!(strip("let s = r#\"/* \"x\" */\"#; // c", &RUST), "let s = r#\"/* \"x\" */\"#;");
+        assert_eq!(
+            strip("fn f<'a>(x: &'a str) -> char { '/' } // c", &RUST),
+            "fn f<'a>(x: &'a str) -> char { '/' }"
+        );
+    }
+
+    #[test]
+    fn nested_block_comments() {
+        assert_eq!(strip("a /* x /* y */ z */ b", &RUST), "a b");
+        assert_eq!(strip("a /* x /* y */ z */ b", &C), "a z */ b");
+    }
+
+    #[test]
+    fn shebang_is_kept() {
+        assert_eq!(
+            strip("#!/usr/bin/env python\n# comment\nx = '#'  # c\n", &PYTHON),
+            "#!/usr/bin/env python\nx = '#'\n"
+        );
+    }
+
+    #[test]
+    fn collapses_blank_lines() {
+        assert_eq!(collapse_blank_lines(b"\n\na\n\n\n  \nb\n\n"), b"a\n\nb\n");
+        assert_eq!(collapse_blank_lines(b"a\r\n\r\n\t\r\nb"), b"a\r\n\r\nb");
+        assert_eq!(collapse_blank_lines(b"a\nb\n"), b"a\nb\n");
+        assert_eq!(collapse_blank_lines(b" \n\n"), b"");
+    }
+
+    #[test]
+    fn trims_trailing_whitespace() {
+        assert_eq!(trim_trailing_whitespace(b"a  \t\r\nb \n c"), b"a\r\nb\n c");
+    }
+
+    #[test]
+    fn minify_strips_comments_before_the_whitespace_transforms() {
+        let content = b"// header\n\nfn f() {\n    // c\n\n\n    x();   \n}\n\n";
+        let minified = Transforms::MINIFY.apply(Path::new("src/a.rs"), content, &mut Vec::new());
+        assert_eq!(String::from_utf8(minified).unwrap(), "fn f() {\n\n    x();\n}\n");
+        let unknown = Transforms::MINIFY.apply(Path::new("run.sh"), b"# c\n\n\nx  \n", &mut Vec::new());
+        assert_eq!(unknown, b"# c\n\nx\n");
+    }
+}
@@ -0,0 +1,206 @@
+use crate::file_processor::{self, CategoryData};
+
+use rustc_hash::FxHashSet;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+/// Per-entry annotation in the tree.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TreeMetric {
+    /// Size in bytes, human-readable.
+    #[default]
+    Size,
+    /// Number of content tokens.
+    Tokens,
+}
+
+/// Settings of the directory tree section (`[tree]` in kek.toml, `--tree`, `--tree-excluded`).
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields, default)]
+pub struct TreeSettings {
+    /// Writes the tree before the file contents.
+    pub enabled: bool,
+    /// Also shows scanned files that are not in the output, marked as excluded.
+    pub excluded: bool,
+    pub metric: TreeMetric,
+}
+
+#[derive(Default)]
+struct Node {
+    children: BTreeMap<OsString, Node>,
+    /// Total size and tokens of the included files at or below this node.
+    size: u64,
+    tokens: usize,
+    included_files: usize,
+    /// For excluded files: their size.
+    excluded_size: Option<u64>,
+}
+
+impl Node {
+    fn insert(&mut self, relative_path: &Path, size: u64, tokens: Option<usize>) {
+        let mut node = self;
+        match tokens {
+            Some(tokens) => {
+                for component in relative_path.components() {
+                    node.size += size;
+                    node.tokens += tokens;
+                    node.included_files += 1;
+                    node = node.children.entry(component.as_os_str().to_owned()).or_default();
+                }
+                node.size += size;
+                node.tokens += tokens;
+                node.included_files += 1;
+            }
+            None => {
+                for component in relative_path.components() {
+                    node = node.children.entry(component.as_os_str().to_owned()).or_default();
+                }
+                node.excluded_size = Some(size);
+            }
+        }
+    }
+
+    fn annotation(&self, metric: TreeMetric) -> String {
+        if let Some(size) = self.excluded_size {
+            return format!("excluded, {}", file_processor::format_size(size));
+        }
+        if self.included_files == 0 {
+            return "excluded".to_string();
+        }
+        match metric {
+            TreeMetric::Size => file_processor::format_size(self.size),
+            TreeMetric::Tokens => format!("{} tokens", self.tokens),
+        }
+    }
+
+    fn render(&self, out: &mut String, prefix: &str, metric: TreeMetric) {
+        let count = self.children.len();
+        for (index, (name, child)) in self.children.iter().enumerate() {
+            let last = index + 1 == count;
+            let directory_suffix = if child.children.is_empty() { "" } else { "/" };
+            let _ = writeln!(
+                out,
+                "{}{}{}{} ({})",
+                prefix,
+                if last { "└── " } else { "├── " },
+                name.to_string_lossy(),
+                directory_suffix,
+                child.annotation(metric)
+            );
+            let child_prefix = format!("{}{}", prefix, if last { "    " } else { "│   " });
+            child.render(out, &child_prefix, metric);
+        }
+    }
+}
+
+/// Renders the directory tree of the files in `categories_data`, relative to the working directory.
+/// `scanned` lists every scanned file (relative path and size); those not in `categories_data` are
+/// shown as excluded. Pass an empty slice to show only the included files.
+///
+/// Business Logic Constraint: Entries are sorted by name, so the tree is deterministic. Directories
+/// are annotated with the total of the included files below them.
+pub fn render_tree(
+    categories_data: &[CategoryData],
+    scanned: &[(PathBuf, u64)],
+    metric: TreeMetric,
+) -> String {
+    let mut root = Node::default();
+    for file_data in categories_data.iter().flat_map(|c| &c.files) {
+        root.insert(
+            &file_data.relative_path,
+            file_data.size,
+            Some(file_data.tokens.unwrap_or(0)),
+        );
+    }
+    let included: FxHashSet<&Path> = categories_data
+        .iter()
+        .flat_map(|c| &c.files)
+        .map(|f| f.relative_path.as_path())
+        .collect();
+    for (relative_path, size) in scanned {
+        if !included.contains(relative_path.as_path()) {
+            root.insert(relative_path, *size, None);
+        }
+    }
+
+    let mut out = String::new();
+    let _ = writeln!(out, ". ({})", root.annotation(metric));
+    root.render(&mut out, "", metric);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_processor::{ContentMode, FileData};
+
+    fn file(path: &str, size: u64, tokens: usize) -> FileData {
+        FileData {
+            relative_path: PathBuf::from(path),
+            absolute_path: PathBuf::from("/nonexistent").join(path),
+            size,
+            tokens: Some(tokens),
+            content: ContentMode::Full,
+            boundary: None,
+            priority: 0,
+        }
+    }
+
+    fn categories_data() -> Vec<CategoryData> {
+        let category = |name: &str, files: Vec<FileData>| CategoryData {
+            name: name.to_string(),
+            description_text: String::new(),
+            total_size: files.iter().map(|f| f.size).sum(),
+            files,
+            position: 0,
+        };
+        vec![
+            category("src", vec![file("src/util/a.rs", 100, 30), file("src/main.rs", 2048, 500)]),
+            category("docs", vec![file("README.md", 10, 3)]),
+        ]
+    }
+
+    #[test]
+    fn renders_sorted_entries_with_sizes_and_excluded_files() {
+        let scanned = [
+            (PathBuf::from("target/build.bin"), 5000),
+            (PathBuf::from("src/main.rs"), 2048),
+            (PathBuf::from("src/util/skip.rs"), 7),
+            (PathBuf::from("src/util/a.rs"), 100),
+            (PathBuf::from("README.md"), 10),
+        ];
+        assert_eq!(
+            render_tree(&categories_data(), &scanned, TreeMetric::Size),
+            concat!(
+                ". (2.1 KiB)\n",
+                "├── README.md (10 B)\n",
+                "├── src/ (2.1 KiB)\n",
+                "│   ├── main.rs (2.0 KiB)\n",
+                "│   └── util/ (100 B)\n",
+                "│       ├── a.rs (100 B)\n",
+                "│       └── skip.rs (excluded, 7 B)\n",
+                "└── target/ (excluded)\n",
+                "    └── build.bin (excluded, 4.9 KiB)\n",
+            )
+        );
+    }
+
+    #[test]
+    fn renders_token_counts_of_included_files() {
+        assert_eq!(
+            render_tree(&categories_data(), &[], TreeMetric::Tokens),
+            concat!(
+                ". (533 tokens)\n",
+                "├── README.md (3 tokens)\n",
+                "└── src/ (530 tokens)\n",
+                "    ├── main.rs (500 tokens)\n",
+                "    └── util/ (30 tokens)\n",
+                "        └── a.rs (30 tokens)\n",
+            )
+        );
+    }
+}
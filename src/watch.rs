@@ -0,0 +1,130 @@
+use rustc_hash::{FxHashMap, FxHashSet};
+use rustix::event::{PollFd, PollFlags, Timespec, poll};
+use rustix::fd::{AsFd, BorrowedFd, OwnedFd};
+use rustix::fs::inotify::{self, CreateFlags, ReadFlags, WatchFlags};
+use rustix::io::Errno;
+use std::mem::MaybeUninit;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Watches directories for changes of the entries in them using inotify.
+pub struct Watcher {
+    inotify: OwnedFd,
+    /// Watched directories by watch descriptor.
+    watched: FxHashMap<i32, PathBuf>,
+    watched_dirs: FxHashSet<PathBuf>,
+    /// Whether adding a watch failed because of the inotify limits, so the warning is shown once.
+    limit_reported: bool,
+}
+
+impl Watcher {
+    pub fn new() -> Result<Self, String> {
+        let inotify = inotify::init(CreateFlags::CLOEXEC | CreateFlags::NONBLOCK)
+            .map_err(|e| format!("Failed to initialize inotify: {}", e))?;
+        Ok(Self {
+            inotify,
+            watched: FxHashMap::default(),
+            watched_dirs: FxHashSet::default(),
+            limit_reported: false,
+        })
+    }
+
+    /// Returns the number of watched directories.
+    pub fn watched_count(&self) -> usize {
+        self.watched_dirs.len()
+    }
+
+    /// Watches `dirs` for created, modified, moved and deleted entries. Directories that are
+    /// already watched are skipped.
+    ///
+    /// Business Logic Constraint: inotify isn't recursive, so every directory needs its own watch.
+    /// Directories that can't be watched (e.g. deleted in the meantime) are skipped; hitting the
+    /// `max_user_watches` limit is reported once.
+    pub fn watch_dirs(&mut self, dirs: impl IntoIterator<Item = PathBuf>) {
+        let flags = WatchFlags::CREATE
+            | WatchFlags::DELETE
+            | WatchFlags::MODIFY
+            | WatchFlags::MOVE
+            | WatchFlags::DELETE_SELF
+            | WatchFlags::MOVE_SELF
+            | WatchFlags::ONLYDIR;
+        for dir in dirs {
+            if self.watched_dirs.contains(&dir) {
+                continue;
+            }
+            match inotify::add_watch(&self.inotify, &dir, flags) {
+                Ok(wd) => {
+                    self.watched_dirs.insert(dir.clone());
+                    self.watched.insert(wd, dir);
+                }
+                Err(Errno::NOSPC) if !self.limit_reported => {
+                    self.limit_reported = true;
+                    eprintln!(
+                        "[WARNING] Reached the inotify watch limit at {:?}; changes in some directories \
+                         won't be noticed. Raise fs.inotify.max_user_watches to watch all of them.",
+                        dir
+                    );
+                }
+                Err(_) => {}
+            }
+        }
+    }
+
+    /// Blocks until a watched directory changes, then until no further change happened for
+    /// `debounce`, so a burst of changes (e.g. a branch switch or a formatter run) is handled once.
+    pub fn wait_for_changes(&mut self, debounce: Duration) -> Result<(), String> {
+        self.wait(None)?;
+        self.drain()?;
+        while self.wait(Some(debounce))? {
+            self.drain()?;
+        }
+        Ok(())
+    }
+
+    /// Waits until events are available or `timeout` elapsed. Returns whether events are available.
+    fn wait(&self, timeout: Option<Duration>) -> Result<bool, String> {
+        let timeout = timeout
+            .map(Timespec::try_from)
+            .transpose()
+            .map_err(|_| "Debounce interval is too long".to_string())?;
+        loop {
+            let mut fds = [PollFd::new(&self.inotify, PollFlags::IN)];
+            match poll(&mut fds, timeout.as_ref()) {
+                Ok(ready) => return Ok(ready > 0),
+                Err(Errno::INTR) => continue,
+                Err(e) => return Err(format!("Failed to wait for file system events: {}", e)),
+            }
+        }
+    }
+
+    /// Reads all pending events without blocking and returns whether there were any. Watches of
+    /// deleted directories are forgotten, so the directories are watched again if they are
+    /// recreated.
+    pub fn drain(&mut self) -> Result<bool, String> {
+        let mut buffer = [MaybeUninit::uninit(); 4096];
+        let mut reader = inotify::Reader::new(&self.inotify, &mut buffer);
+        let mut changed = false;
+        loop {
+            match reader.next() {
+                Ok(event) => {
+                    changed = true;
+                    if event.events().contains(ReadFlags::IGNORED)
+                        && let Some(dir) = self.watched.remove(&event.wd())
+                    {
+                        self.watched_dirs.remove(&dir);
+                    }
+                }
+                Err(Errno::WOULDBLOCK) => return Ok(changed),
+                Err(Errno::INTR) => continue,
+                Err(e) => return Err(format!("Failed to read file system events: {}", e)),
+            }
+        }
+    }
+}
+
+/// The inotify file descriptor, readable when events are pending (e.g. for `poll`).
+impl AsFd for Watcher {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.inotify.as_fd()
+    }
+}